@@ -0,0 +1,47 @@
+mod bmp;
+mod gif;
+mod huffman;
+mod imagely;
+mod jpeg;
+mod netpbm;
+mod png;
+mod qoi;
+mod quantize;
+mod registry;
+mod tiff;
+mod webp;
+mod zlib;
+
+pub use gif::{Frame, GifOptions};
+pub use imagely::{ChannelLayout, ColorSpace, ImagelyHeader, ImagelyOptions};
+pub use jpeg::{ChromaSubsampling, JpegOptions};
+pub use netpbm::{NetpbmFormat, NetpbmOptions};
+pub use png::{PngFilter, PngOptions};
+pub use registry::{register_codec, Codec};
+pub use tiff::{TiffCompression, TiffOptions};
+
+pub(crate) use imagely::{decode as decode_imagely, is_imagely};
+
+use std::io;
+
+// Decoders refuse larger images before allocating anything for them, so a
+// hostile header can't make them exhaust memory
+const MAX_PIXELS: usize = 400_000_000;
+
+// Samples in a width x height image with `channels` samples per pixel
+fn image_len(width: usize, height: usize, channels: usize) -> io::Result<usize> {
+    match width.checked_mul(height) {
+        Some(pixels) if pixels <= MAX_PIXELS => {
+            pixels.checked_mul(channels).ok_or_else(|| unsupported("image is too large"))
+        }
+        _ => Err(unsupported("image is too large")),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn unsupported(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.to_string())
+}
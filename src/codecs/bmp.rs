@@ -0,0 +1,471 @@
+use std::fs;
+use std::io;
+
+use super::{image_len, invalid_data, unsupported};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel};
+
+const FILE_HEADER_LEN: usize = 14;
+const INFO_HEADER_LEN: usize = 40;
+const V4_HEADER_LEN: usize = 108;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+fn read_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+#[derive(Clone, Copy)]
+struct Mask {
+    mask: u32,
+    shift: u32,
+    bits: u32,
+}
+
+impl Mask {
+    fn new(mask: u32) -> Mask {
+        if mask == 0 {
+            return Mask { mask, shift: 0, bits: 0 };
+        }
+        let shift = mask.trailing_zeros();
+        Mask { mask, shift, bits: (mask >> shift).trailing_ones() }
+    }
+
+    fn extract(&self, pixel: u32, default: u8) -> u8 {
+        if self.bits == 0 {
+            return default;
+        }
+        let value = (pixel & self.mask) >> self.shift;
+        let max = if self.bits >= 32 { u32::MAX } else { (1u32 << self.bits) - 1 };
+        ((value as u64 * 255 + max as u64 / 2) / max as u64) as u8
+    }
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    top_down: bool,
+    bits_per_pixel: u16,
+    compression: u32,
+    masks: [Mask; 4],
+    palette: Vec<[u8; 4]>,
+}
+
+fn parse_header(bytes: &[u8]) -> io::Result<Header> {
+    if bytes.len() < FILE_HEADER_LEN + 12 || &bytes[..2] != b"BM" {
+        return Err(invalid_data("not a BMP file"));
+    }
+
+    let header_len = read_u32(bytes, FILE_HEADER_LEN) as usize;
+    if bytes.len() < FILE_HEADER_LEN + header_len {
+        return Err(invalid_data("truncated BMP header"));
+    }
+    let info = &bytes[FILE_HEADER_LEN..FILE_HEADER_LEN + header_len];
+
+    // OS/2 BITMAPCOREHEADER uses 16-bit dimensions and 3 byte palette entries
+    let core = header_len == 12;
+    let (width, height, bits_per_pixel, compression) = if core {
+        (read_u16(info, 4) as i32, read_u16(info, 6) as i16 as i32, read_u16(info, 10), BI_RGB)
+    } else if header_len >= INFO_HEADER_LEN {
+        (read_u32(info, 4) as i32, read_u32(info, 8) as i32, read_u16(info, 14), read_u32(info, 16))
+    } else {
+        return Err(unsupported("unsupported BMP header size"));
+    };
+
+    if width <= 0 || height == 0 {
+        return Err(invalid_data("BMP has no pixels"));
+    }
+
+    // Masks live inside V2+ headers, or right after a plain info header
+    let mut masks = match bits_per_pixel {
+        16 => [0x7c00, 0x03e0, 0x001f, 0],
+        32 => [0x00ff0000, 0x0000ff00, 0x000000ff, 0xff000000],
+        _ => [0; 4],
+    };
+    let mut palette_start = FILE_HEADER_LEN + header_len;
+    if compression == BI_BITFIELDS || compression == BI_ALPHABITFIELDS {
+        let mask_count = if compression == BI_ALPHABITFIELDS { 4 } else { 3 };
+        let mask_start = if header_len >= INFO_HEADER_LEN + 12 {
+            FILE_HEADER_LEN + INFO_HEADER_LEN
+        } else {
+            palette_start += mask_count * 4;
+            FILE_HEADER_LEN + header_len
+        };
+        if bytes.len() < mask_start + mask_count * 4 {
+            return Err(invalid_data("truncated BMP bitfield masks"));
+        }
+        for (i, mask) in masks.iter_mut().enumerate() {
+            *mask = if i < mask_count || header_len >= INFO_HEADER_LEN + 16 {
+                read_u32(bytes, mask_start + i * 4)
+            } else {
+                0
+            };
+        }
+    }
+
+    let mut palette = Vec::new();
+    if bits_per_pixel <= 8 {
+        let colors_used = if core { 0 } else { read_u32(info, 32) as usize };
+        let count = if colors_used == 0 { 1 << bits_per_pixel } else { colors_used.min(256) };
+        let entry_len = if core { 3 } else { 4 };
+        for i in 0..count {
+            let pos = palette_start + i * entry_len;
+            if pos + 3 > bytes.len() {
+                break;
+            }
+            palette.push([bytes[pos + 2], bytes[pos + 1], bytes[pos], 255]);
+        }
+        if palette.is_empty() {
+            return Err(invalid_data("missing BMP palette"));
+        }
+        palette.resize(256, [0, 0, 0, 255]);
+    }
+
+    Ok(Header {
+        width: width as usize,
+        height: height.unsigned_abs() as usize,
+        top_down: height < 0,
+        bits_per_pixel,
+        compression,
+        masks: masks.map(Mask::new),
+        palette,
+    })
+}
+
+// Returns palette indices in bottom-up row order
+fn decode_rle(data: &[u8], header: &Header) -> io::Result<Vec<u8>> {
+    let (width, height) = (header.width, header.height);
+    let mut indices = vec![0u8; width * height];
+    let is_rle4 = header.compression == BI_RLE4;
+
+    let (mut x, mut y) = (0usize, 0usize);
+    let mut pos = 0;
+    let mut put = |x: &mut usize, y: usize, index: u8| {
+        if *x < width && y < height {
+            indices[y * width + *x] = index;
+        }
+        *x += 1;
+    };
+
+    while pos + 1 < data.len() {
+        let (count, value) = (data[pos] as usize, data[pos + 1]);
+        pos += 2;
+
+        if count > 0 {
+            for i in 0..count {
+                let index = if is_rle4 {
+                    if i % 2 == 0 { value >> 4 } else { value & 0x0f }
+                } else {
+                    value
+                };
+                put(&mut x, y, index);
+            }
+            continue;
+        }
+
+        match value {
+            0 => {
+                x = 0;
+                y += 1;
+            }
+            1 => break,
+            2 => {
+                if pos + 1 >= data.len() {
+                    return Err(invalid_data("truncated RLE delta"));
+                }
+                x += data[pos] as usize;
+                y += data[pos + 1] as usize;
+                pos += 2;
+            }
+            literal_count => {
+                let literal_count = literal_count as usize;
+                let byte_len = if is_rle4 { literal_count.div_ceil(2) } else { literal_count };
+                if pos + byte_len > data.len() {
+                    return Err(invalid_data("truncated RLE literal run"));
+                }
+                for i in 0..literal_count {
+                    let index = if is_rle4 {
+                        let byte = data[pos + i / 2];
+                        if i % 2 == 0 { byte >> 4 } else { byte & 0x0f }
+                    } else {
+                        data[pos + i]
+                    };
+                    put(&mut x, y, index);
+                }
+                // Literal runs are padded to 16 bits
+                pos += byte_len.next_multiple_of(2);
+            }
+        }
+    }
+
+    Ok(indices)
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> io::Result<DynamicImage> {
+    let header = parse_header(bytes)?;
+    let (width, height) = (header.width, header.height);
+
+    let data_start = read_u32(bytes, 10) as usize;
+    if data_start > bytes.len() {
+        return Err(invalid_data("BMP pixel data offset out of range"));
+    }
+    let pixel_data = &bytes[data_start..];
+
+    // Row indices are always bottom-up here, top-down files are flipped when reading
+    let row_for = |y: usize| if header.top_down { y } else { height - 1 - y };
+
+    // Allocated once the pixel data is known to be there
+    let rgba_len = image_len(width, height, 4)?;
+    let mut rgba = Vec::new();
+
+    match (header.compression, header.bits_per_pixel) {
+        (BI_RLE8, 8) | (BI_RLE4, 4) => {
+            if header.top_down {
+                return Err(invalid_data("RLE BMPs can't be top-down"));
+            }
+            let indices = decode_rle(pixel_data, &header)?;
+            rgba.resize(rgba_len, 0);
+            for y in 0..height {
+                for x in 0..width {
+                    let color = header.palette[indices[(height - 1 - y) * width + x] as usize];
+                    let index = (y * width + x) * 4;
+                    rgba[index..index + 4].copy_from_slice(&color);
+                }
+            }
+        }
+        (BI_RGB | BI_BITFIELDS | BI_ALPHABITFIELDS, bits_per_pixel @ (1 | 2 | 4 | 8 | 16 | 24 | 32)) => {
+            let bits_per_pixel = bits_per_pixel as usize;
+            let row_len = (width * bits_per_pixel).div_ceil(32) * 4;
+            if pixel_data.len() < row_len * (height - 1) + (width * bits_per_pixel).div_ceil(8) {
+                return Err(invalid_data("not enough BMP pixel data"));
+            }
+            rgba.resize(rgba_len, 0);
+
+            for y in 0..height {
+                let row = &pixel_data[row_for(y) * row_len..];
+                for x in 0..width {
+                    let color = match bits_per_pixel {
+                        1 | 2 | 4 | 8 => {
+                            let bit = x * bits_per_pixel;
+                            let shift = 8 - bits_per_pixel - bit % 8;
+                            let index = (row[bit / 8] >> shift) & ((1u16 << bits_per_pixel) - 1) as u8;
+                            header.palette[index as usize]
+                        }
+                        24 => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3], 255],
+                        _ => {
+                            let pixel = if bits_per_pixel == 16 {
+                                read_u16(row, x * 2) as u32
+                            } else {
+                                read_u32(row, x * 4)
+                            };
+                            let [red, green, blue, alpha] = header.masks;
+                            [
+                                red.extract(pixel, 0),
+                                green.extract(pixel, 0),
+                                blue.extract(pixel, 0),
+                                alpha.extract(pixel, 255),
+                            ]
+                        }
+                    };
+                    let index = (y * width + x) * 4;
+                    rgba[index..index + 4].copy_from_slice(&color);
+                }
+            }
+
+            // Plenty of writers leave the unused byte of 32-bit pixels at zero
+            if header.compression == BI_RGB
+                && bits_per_pixel == 32
+                && rgba.chunks_exact(4).all(|pixel| pixel[3] == 0)
+            {
+                rgba.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+            }
+        }
+        _ => return Err(unsupported("unsupported BMP compression or bit depth")),
+    }
+
+    let opaque = rgba.chunks_exact(4).all(|pixel| pixel[3] == 255);
+    let image = DynamicImage::from_raw_parts(4, width as u32, height as u32, rgba);
+    if opaque {
+        return Ok(image.to_rgb8().into());
+    }
+    Ok(image)
+}
+
+// 24-bit for RGB, 32-bit BGRA with a V4 header for RGBA
+pub(crate) fn encode(image: DynamicImageRef<'_>) -> io::Result<Vec<u8>> {
+    // BMP stores 8-bit color
+    let rgb = image.with_rgb8();
+    let image = rgb.as_ref();
+    let channels = image.channels() as usize;
+
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let header_len = if channels == 4 { V4_HEADER_LEN } else { INFO_HEADER_LEN };
+    let row_len = (width * channels).next_multiple_of(4);
+    let data_start = FILE_HEADER_LEN + header_len;
+    let file_len = data_start + row_len * height;
+
+    let mut out = Vec::with_capacity(file_len);
+    out.extend_from_slice(b"BM");
+    out.extend_from_slice(&(file_len as u32).to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(data_start as u32).to_le_bytes());
+
+    out.extend_from_slice(&(header_len as u32).to_le_bytes());
+    out.extend_from_slice(&image.width().to_le_bytes());
+    out.extend_from_slice(&image.height().to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&(channels as u16 * 8).to_le_bytes());
+    out.extend_from_slice(&(if channels == 4 { BI_BITFIELDS } else { BI_RGB }).to_le_bytes());
+    out.extend_from_slice(&((row_len * height) as u32).to_le_bytes());
+    // 72 DPI
+    out.extend_from_slice(&2835u32.to_le_bytes());
+    out.extend_from_slice(&2835u32.to_le_bytes());
+    out.extend_from_slice(&[0; 8]);
+
+    if channels == 4 {
+        for mask in [0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+            out.extend_from_slice(&mask.to_le_bytes());
+        }
+        // sRGB color space, endpoints and gamma are unused
+        out.extend_from_slice(b"BGRs");
+        out.extend_from_slice(&[0; 48]);
+    }
+
+    for y in (0..height).rev() {
+        let row_start = out.len();
+        for pixel in image.as_bytes()[y * width * channels..(y + 1) * width * channels].chunks_exact(channels) {
+            out.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            if channels == 4 {
+                out.push(pixel[3]);
+            }
+        }
+        out.resize(row_start + row_len, 0);
+    }
+
+    Ok(out)
+}
+
+impl DynamicImage {
+    pub fn open_bmp(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode(&fs::read(path)?)?)
+    }
+
+    pub fn save_bmp(&self, path: &str) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_ref())?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn open_bmp(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_bmp(path)?.into_image())
+    }
+
+    pub fn save_bmp(&self, path: &str) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_dynamic().as_ref())?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A BITMAPINFOHEADER file, `extra` (palette or masks) sits between the header and the pixels
+    fn bmp(width: i32, height: i32, bits_per_pixel: u16, compression: u32, extra: &[u8], pixels: &[u8]) -> Vec<u8> {
+        let data_start = FILE_HEADER_LEN + INFO_HEADER_LEN + extra.len();
+        let mut out = b"BM".to_vec();
+        out.extend_from_slice(&((data_start + pixels.len()) as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(data_start as u32).to_le_bytes());
+        out.extend_from_slice(&(INFO_HEADER_LEN as u32).to_le_bytes());
+        out.extend_from_slice(&width.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&bits_per_pixel.to_le_bytes());
+        out.extend_from_slice(&compression.to_le_bytes());
+        out.extend_from_slice(&[0; 20]);
+        out.extend_from_slice(extra);
+        out.extend_from_slice(pixels);
+        out
+    }
+
+    // Blue, green, red and reserved, as BMP palettes store them
+    const PALETTE: [u8; 16] = [0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn rows_are_bottom_up_bgr_and_padded() {
+        // The bottom row comes first, each 3 byte row padded to 4
+        let bytes = bmp(1, 2, 24, BI_RGB, &[], &[1, 2, 3, 0, 4, 5, 6, 0]);
+        let image = decode(&bytes).unwrap();
+        assert_eq!((image.channels(), image.as_bytes()), (3, &[6, 5, 4, 3, 2, 1][..]));
+
+        let bytes = bmp(1, -2, 24, BI_RGB, &[], &[1, 2, 3, 0, 4, 5, 6, 0]);
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), [3, 2, 1, 6, 5, 4]);
+    }
+
+    #[test]
+    fn palettized_pixels_pack_from_the_high_bits() {
+        let bytes = bmp(3, 1, 4, BI_RGB, &PALETTE, &[0x01, 0x20, 0, 0]);
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), [255, 0, 0, 0, 255, 0, 0, 0, 255]);
+
+        let bytes = bmp(3, 1, 1, BI_RGB, &PALETTE[..8], &[0b0100_0000, 0, 0, 0]);
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), [255, 0, 0, 0, 255, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn rle_runs_literals_and_escapes() {
+        // Bottom row: a run of two 1s then the literal 2, 0, 1; top row: skipped by a delta
+        let data = [2, 1, 0, 3, 2, 0, 1, 0, 0, 0, 0, 2, 1, 0, 1, 2, 0, 1];
+        let bytes = bmp(5, 2, 8, BI_RLE8, &PALETTE, &data);
+        let image = decode(&bytes).unwrap();
+        let colors: Vec<u8> = image.as_bytes().chunks_exact(3).map(|pixel| pixel.iter().position(|&c| c == 255).map_or(9, |c| c as u8)).collect();
+        // Untouched pixels use index 0, red
+        assert_eq!(colors, [0, 2, 0, 0, 0, 1, 1, 2, 0, 1]);
+
+        // RLE4 runs alternate the two nibbles
+        let bytes = bmp(4, 1, 4, BI_RLE4, &PALETTE, &[4, 0x12, 0, 1]);
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), [0, 255, 0, 0, 0, 255, 0, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bitfield_masks_scale_to_8_bits() {
+        // 5-6-5 masks after the header, a pixel of full red and half green
+        let masks: Vec<u8> = [0xf800u32, 0x07e0, 0x001f].iter().flat_map(|mask| mask.to_le_bytes()).collect();
+        let bytes = bmp(1, 1, 16, BI_BITFIELDS, &masks, &[0x00, 0xfc, 0, 0]);
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), [255, 130, 0]);
+
+        // Plain 16-bit pixels are 5-5-5
+        let bytes = bmp(1, 1, 16, BI_RGB, &[], &[0x1f, 0x00, 0, 0]);
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), [0, 0, 255]);
+    }
+
+    #[test]
+    fn zero_reserved_bytes_in_32_bit_pixels_mean_opaque() {
+        let bytes = bmp(2, 1, 32, BI_RGB, &[], &[1, 2, 3, 0, 4, 5, 6, 0]);
+        let image = decode(&bytes).unwrap();
+        assert_eq!((image.channels(), image.as_bytes()), (3, &[3, 2, 1, 6, 5, 4][..]));
+    }
+
+    #[test]
+    fn alpha_is_written_as_32_bit_bgra_with_a_v4_header() {
+        let image = DynamicImage::from_raw_parts(4, 1, 1, vec![10, 20, 30, 40]);
+        let bytes = encode(image.as_ref()).unwrap();
+        assert_eq!(read_u32(&bytes, FILE_HEADER_LEN) as usize, V4_HEADER_LEN);
+        assert_eq!((read_u16(&bytes, 28), read_u32(&bytes, 30)), (32, BI_BITFIELDS));
+        assert_eq!(bytes[bytes.len() - 4..], [30, 20, 10, 40]);
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), [10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn huge_header_is_rejected_before_allocating() {
+        let bytes = bmp(i32::MAX, i32::MAX, 24, BI_RGB, &[], &[0; 16]);
+        assert_eq!(decode(&bytes).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,654 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use super::quantize::quantize;
+use super::{MAX_PIXELS, image_len, invalid_data, unsupported};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel};
+
+const MAX_CODE_WIDTH: u32 = 12;
+const MAX_CODES: usize = 1 << MAX_CODE_WIDTH;
+
+const DISPOSE_BACKGROUND: u8 = 2;
+const DISPOSE_PREVIOUS: u8 = 3;
+
+// Alpha below this becomes the transparent palette entry when encoding
+const ALPHA_THRESHOLD: u8 = 128;
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub image: DynamicImage,
+    pub delay_ms: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct GifOptions {
+    // None plays the animation once, Some(0) loops forever
+    pub loop_count: Option<u16>,
+    // Floyd-Steinberg dithering when a frame has more than 256 colors
+    pub dither: bool,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        GifOptions {
+            loop_count: Some(0),
+            dither: false,
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.pos + len > self.bytes.len() {
+            return Err(invalid_data("unexpected end of GIF data"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn palette(&mut self, packed: u8) -> io::Result<Vec<[u8; 3]>> {
+        let size = 2usize << (packed & 7);
+        Ok(self.take(size * 3)?.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect())
+    }
+
+    // Truncated files are common, so a cut-off block keeps whatever data is left
+    fn sub_blocks(&mut self) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some(&len) = self.bytes.get(self.pos) {
+            self.pos += 1;
+            if len == 0 {
+                break;
+            }
+            let end = (self.pos + len as usize).min(self.bytes.len());
+            data.extend_from_slice(&self.bytes[self.pos..end]);
+            self.pos = end;
+        }
+        data
+    }
+}
+
+// Writes up to `pixel_count` indices; truncated streams leave the rest at 0
+fn lzw_decode(data: &[u8], min_code_size: u8, pixel_count: usize) -> io::Result<Vec<u8>> {
+    if !(1..=11).contains(&min_code_size) {
+        return Err(invalid_data("invalid GIF LZW code size"));
+    }
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+
+    let mut prefix = [0u16; MAX_CODES];
+    let mut suffix = [0u8; MAX_CODES];
+    let mut first = [0u8; MAX_CODES];
+    let mut length = [0u16; MAX_CODES];
+    for code in 0..clear {
+        suffix[code] = code as u8;
+        first[code] = code as u8;
+        length[code] = 1;
+    }
+
+    let mut out = Vec::with_capacity(pixel_count);
+    let mut width = min_code_size as u32 + 1;
+    let mut next = end + 1;
+    let mut previous: Option<usize> = None;
+
+    let mut bit_buffer = 0u32;
+    let mut bit_count = 0u32;
+    let mut bytes = data.iter();
+
+    while out.len() < pixel_count {
+        while bit_count < width {
+            match bytes.next() {
+                Some(&byte) => {
+                    bit_buffer |= (byte as u32) << bit_count;
+                    bit_count += 8;
+                }
+                None => break,
+            }
+        }
+        if bit_count < width {
+            break;
+        }
+        let code = (bit_buffer & ((1 << width) - 1)) as usize;
+        bit_buffer >>= width;
+        bit_count -= width;
+
+        if code == clear {
+            width = min_code_size as u32 + 1;
+            next = end + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+
+        let Some(prev) = previous else {
+            if code >= clear {
+                return Err(invalid_data("invalid first GIF LZW code"));
+            }
+            out.push(code as u8);
+            previous = Some(code);
+            continue;
+        };
+
+        if code > next || (code == next && next >= MAX_CODES) {
+            return Err(invalid_data("invalid GIF LZW code"));
+        }
+
+        // A code equal to `next` is the previous string plus its own first byte
+        let known = code < next;
+        let emitted = if known { code } else { prev };
+        let start = out.len();
+        let emitted_len = length[emitted] as usize;
+        out.resize(start + emitted_len, 0);
+        let mut walk = emitted;
+        for i in (0..emitted_len).rev() {
+            out[start + i] = suffix[walk];
+            walk = prefix[walk] as usize;
+        }
+        if !known {
+            out.push(first[prev]);
+        }
+
+        if next < MAX_CODES {
+            prefix[next] = prev as u16;
+            suffix[next] = if known { first[code] } else { first[prev] };
+            first[next] = first[prev];
+            length[next] = length[prev] + 1;
+            next += 1;
+            if next == 1 << width && width < MAX_CODE_WIDTH {
+                width += 1;
+            }
+        }
+        previous = Some(code);
+    }
+
+    out.resize(pixel_count, 0);
+    Ok(out)
+}
+
+fn deinterlace(indices: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0u8; indices.len()];
+    let mut source_rows = indices.chunks_exact(width.max(1));
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        for y in (start..height).step_by(step) {
+            if let Some(row) = source_rows.next() {
+                out[y * width..(y + 1) * width].copy_from_slice(row);
+            }
+        }
+    }
+    out
+}
+
+struct Control {
+    disposal: u8,
+    delay_ms: u32,
+    transparent: Option<u8>,
+}
+
+pub(crate) fn decode_frames(bytes: &[u8]) -> io::Result<Vec<Frame>> {
+    if bytes.len() < 13 || (&bytes[..6] != b"GIF87a" && &bytes[..6] != b"GIF89a") {
+        return Err(invalid_data("not a GIF file"));
+    }
+
+    let screen_width = read_u16(bytes, 6) as usize;
+    let screen_height = read_u16(bytes, 8) as usize;
+    let packed = bytes[10];
+
+    let mut reader = Reader { bytes, pos: 13 };
+    let global_palette = if packed & 0x80 != 0 { Some(reader.palette(packed)?) } else { None };
+
+    // Codes take at least 2 bits and stand for at most `MAX_CODES` indices, so
+    // the file bounds how many pixels it can describe. The screen and every
+    // composited frame are held to that, however large the header says they are.
+    let pixel_budget = MAX_PIXELS.min(bytes.len().saturating_mul(4 * MAX_CODES));
+    let screen_pixels = image_len(screen_width, screen_height, 1)?;
+    if screen_pixels > pixel_budget {
+        return Err(invalid_data("GIF screen is larger than its data can cover"));
+    }
+
+    // Frames are composited onto a transparent RGBA canvas
+    let mut canvas = vec![0u8; screen_pixels * 4];
+    let mut frames = Vec::new();
+    let mut control = Control { disposal: 0, delay_ms: 0, transparent: None };
+
+    // A missing trailer is tolerated
+    while reader.pos < bytes.len() {
+        match reader.byte()? {
+            0x21 => {
+                let label = reader.byte()?;
+                let data = reader.sub_blocks();
+                if label == 0xf9 && data.len() >= 4 {
+                    control = Control {
+                        disposal: (data[0] >> 2) & 7,
+                        delay_ms: read_u16(&data, 1) as u32 * 10,
+                        transparent: if data[0] & 1 != 0 { Some(data[3]) } else { None },
+                    };
+                }
+            }
+            0x2c => {
+                let descriptor = reader.take(9)?;
+                let left = read_u16(descriptor, 0) as usize;
+                let top = read_u16(descriptor, 2) as usize;
+                let width = read_u16(descriptor, 4) as usize;
+                let height = read_u16(descriptor, 6) as usize;
+                let packed = descriptor[8];
+
+                let local_palette = if packed & 0x80 != 0 { Some(reader.palette(packed)?) } else { None };
+                let palette = local_palette
+                    .as_ref()
+                    .or(global_palette.as_ref())
+                    .ok_or_else(|| invalid_data("GIF frame has no color table"))?;
+
+                // Every frame keeps a copy of the canvas, on top of the canvas itself
+                if (frames.len() + 2).saturating_mul(screen_pixels) > pixel_budget {
+                    return Err(unsupported("GIF animation is too large"));
+                }
+
+                let min_code_size = reader.byte()?;
+                let data = reader.sub_blocks();
+                let pixel_count = image_len(width, height, 1)?;
+                if pixel_count / MAX_CODES > data.len().saturating_mul(4) {
+                    return Err(invalid_data("not enough GIF image data"));
+                }
+                let mut indices = lzw_decode(&data, min_code_size, pixel_count)?;
+                if packed & 0x40 != 0 {
+                    indices = deinterlace(&indices, width, height);
+                }
+
+                let saved = if control.disposal == DISPOSE_PREVIOUS { Some(canvas.clone()) } else { None };
+
+                for y in 0..height.min(screen_height.saturating_sub(top)) {
+                    for x in 0..width.min(screen_width.saturating_sub(left)) {
+                        let index = indices[y * width + x];
+                        if control.transparent == Some(index) {
+                            continue;
+                        }
+                        // Out of range indices are drawn black
+                        let [r, g, b] = palette.get(index as usize).copied().unwrap_or([0, 0, 0]);
+                        let pos = ((top + y) * screen_width + left + x) * 4;
+                        canvas[pos..pos + 4].copy_from_slice(&[r, g, b, 255]);
+                    }
+                }
+
+                frames.push(Frame {
+                    image: DynamicImage::from_raw_parts(4, screen_width as u32, screen_height as u32, canvas.clone()),
+                    delay_ms: control.delay_ms,
+                });
+
+                match (control.disposal, saved) {
+                    (DISPOSE_PREVIOUS, Some(saved)) => canvas = saved,
+                    (DISPOSE_BACKGROUND, _) => {
+                        for y in top..(top + height).min(screen_height) {
+                            for x in left..(left + width).min(screen_width) {
+                                let pos = (y * screen_width + x) * 4;
+                                canvas[pos..pos + 4].fill(0);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                control = Control { disposal: 0, delay_ms: 0, transparent: None };
+            }
+            0x3b => break,
+            _ => return Err(invalid_data("unknown GIF block")),
+        }
+    }
+
+    if frames.is_empty() {
+        return Err(invalid_data("GIF file contains no images"));
+    }
+
+    // Animations without any transparency are returned as RGB
+    let opaque = frames.iter().all(|frame| frame.image.as_bytes().chunks_exact(4).all(|p| p[3] == 255));
+    if opaque {
+        for frame in &mut frames {
+            frame.image = frame.image.to_rgb8().into();
+        }
+    }
+
+    Ok(frames)
+}
+
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut out = Vec::new();
+    let mut bit_buffer = 0u32;
+    let mut bit_count = 0u32;
+    let mut width = min_code_size as u32 + 1;
+    let mut emit = |code: u16, width: u32, out: &mut Vec<u8>| {
+        bit_buffer |= (code as u32) << bit_count;
+        bit_count += width;
+        while bit_count >= 8 {
+            out.push(bit_buffer as u8);
+            bit_buffer >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    emit(clear, width, &mut out);
+
+    let mut iter = indices.iter();
+    if let Some(&first) = iter.next() {
+        let mut current = first as u16;
+        for &index in iter {
+            if let Some(&code) = dictionary.get(&(current, index)) {
+                current = code;
+                continue;
+            }
+
+            emit(current, width, &mut out);
+            dictionary.insert((current, index), next);
+            next += 1;
+            if next as usize > 1 << width && width < MAX_CODE_WIDTH {
+                width += 1;
+            }
+            // Start over once the table is full
+            if next as usize == MAX_CODES {
+                emit(clear, width, &mut out);
+                dictionary.clear();
+                next = end + 1;
+                width = min_code_size as u32 + 1;
+            }
+            current = index as u16;
+        }
+        emit(current, width, &mut out);
+    }
+
+    emit(end, width, &mut out);
+    emit(0, 7, &mut out);
+    out
+}
+
+fn write_sub_blocks(out: &mut Vec<u8>, data: &[u8]) {
+    for block in data.chunks(255) {
+        out.push(block.len() as u8);
+        out.extend_from_slice(block);
+    }
+    out.push(0);
+}
+
+fn encode_frame(out: &mut Vec<u8>, (image, delay_ms): (DynamicImageRef<'_>, u32), dither: bool) -> io::Result<()> {
+    let rgb = image.with_rgb8();
+    let image = rgb.as_ref();
+    let channels = image.channels() as usize;
+    if image.width() > u16::MAX as u32 || image.height() > u16::MAX as u32 {
+        return Err(unsupported("GIF frames are limited to 65535x65535"));
+    }
+
+    let pixels: Vec<[u8; 3]> = image.as_bytes().chunks_exact(channels).map(|p| [p[0], p[1], p[2]]).collect();
+    let transparent: Vec<bool> = image.as_bytes().chunks_exact(channels).map(|p| channels == 4 && p[3] < ALPHA_THRESHOLD).collect();
+    let has_transparency = transparent.iter().any(|&t| t);
+
+    // Palette index 0 is reserved for transparency when the frame needs it
+    let reserved = has_transparency as usize;
+    let mut quantized = quantize(&pixels, &transparent, 256 - reserved, dither, image.width() as usize);
+    if has_transparency {
+        quantized.palette.insert(0, [0, 0, 0]);
+        for (index, &is_transparent) in quantized.indices.iter_mut().zip(&transparent) {
+            *index = if is_transparent { 0 } else { *index + 1 };
+        }
+    }
+
+    let table_bits = (quantized.palette.len().max(2) as u32).next_power_of_two().trailing_zeros();
+    quantized.palette.resize(1 << table_bits, [0, 0, 0]);
+
+    let delay = ((delay_ms + 5) / 10).min(u16::MAX as u32) as u16;
+    // Restore to background so transparent pixels never show the previous frame
+    let packed = (DISPOSE_BACKGROUND << 2) | has_transparency as u8;
+    out.extend_from_slice(&[0x21, 0xf9, 4, packed]);
+    out.extend_from_slice(&delay.to_le_bytes());
+    out.extend_from_slice(&[0, 0]);
+
+    out.push(0x2c);
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    out.extend_from_slice(&(image.width() as u16).to_le_bytes());
+    out.extend_from_slice(&(image.height() as u16).to_le_bytes());
+    out.push(0x80 | (table_bits - 1) as u8);
+    for color in &quantized.palette {
+        out.extend_from_slice(color);
+    }
+
+    let min_code_size = table_bits.max(2) as u8;
+    out.push(min_code_size);
+    write_sub_blocks(out, &lzw_encode(&quantized.indices, min_code_size));
+    Ok(())
+}
+
+// Frames are images with their delay in milliseconds
+pub(crate) fn encode_frames(frames: &[(DynamicImageRef<'_>, u32)], options: &GifOptions) -> io::Result<Vec<u8>> {
+    if frames.is_empty() {
+        return Err(invalid_data("GIF needs at least one frame"));
+    }
+
+    let width = frames.iter().map(|(image, _)| image.width()).max().unwrap_or(0);
+    let height = frames.iter().map(|(image, _)| image.height()).max().unwrap_or(0);
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(unsupported("GIF frames are limited to 65535x65535"));
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"GIF89a");
+    out.extend_from_slice(&(width as u16).to_le_bytes());
+    out.extend_from_slice(&(height as u16).to_le_bytes());
+    // No global color table, every frame carries its own palette
+    out.extend_from_slice(&[0x70, 0, 0]);
+
+    if let (Some(loop_count), true) = (options.loop_count, frames.len() > 1) {
+        out.extend_from_slice(&[0x21, 0xff, 11]);
+        out.extend_from_slice(b"NETSCAPE2.0");
+        out.extend_from_slice(&[3, 1]);
+        out.extend_from_slice(&loop_count.to_le_bytes());
+        out.push(0);
+    }
+
+    for &frame in frames {
+        encode_frame(&mut out, frame, options.dither)?;
+    }
+
+    out.push(0x3b);
+    Ok(out)
+}
+
+impl DynamicImage {
+    // First frame of the file
+    pub fn open_gif(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode_frames(&fs::read(path)?)?.swap_remove(0).image)
+    }
+
+    pub fn open_gif_frames(path: &str) -> Result<Vec<Frame>, ImageError> {
+        Ok(decode_frames(&fs::read(path)?)?)
+    }
+
+    pub fn save_gif(&self, path: &str) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode_frames(&[(self.as_ref(), 0)], &GifOptions::default())?)?)
+    }
+
+    pub fn save_gif_frames(path: &str, frames: &[Frame], options: &GifOptions) -> Result<(), ImageError> {
+        let frames: Vec<_> = frames.iter().map(|frame| (frame.image.as_ref(), frame.delay_ms)).collect();
+        Ok(fs::write(path, encode_frames(&frames, options)?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    // First frame of the file
+    pub fn open_gif(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_gif(path)?.into_image())
+    }
+
+    pub fn save_gif(&self, path: &str) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode_frames(&[(self.as_dynamic().as_ref(), 0)], &GifOptions::default())?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    // A GIF with a black, white, red and green global palette around `blocks`
+    fn gif(screen: (u16, u16), blocks: &[u8]) -> Vec<u8> {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend_from_slice(&screen.0.to_le_bytes());
+        bytes.extend_from_slice(&screen.1.to_le_bytes());
+        bytes.extend_from_slice(&[0x81, 0, 0]);
+        bytes.extend_from_slice(&[0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 255, 0]);
+        bytes.extend_from_slice(blocks);
+        bytes.push(0x3b);
+        bytes
+    }
+
+    fn control(disposal: u8, transparent: Option<u8>) -> Vec<u8> {
+        let packed = (disposal << 2) | transparent.is_some() as u8;
+        vec![0x21, 0xf9, 4, packed, 0, 0, transparent.unwrap_or(0), 0]
+    }
+
+    // An image block at (left, top), `packed` carries the interlace and local palette flags
+    fn image(left: u16, top: u16, width: u16, indices: &[u8], packed: u8, palette: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x2c];
+        for value in [left, top, width, indices.len() as u16 / width] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.push(packed);
+        bytes.extend_from_slice(palette);
+        bytes.push(2);
+        write_sub_blocks(&mut bytes, &lzw_encode(indices, 2));
+        bytes
+    }
+
+    fn pixels(frame: &Frame) -> Vec<[u8; 4]> {
+        frame.image.as_bytes().chunks_exact(4).map(|pixel| pixel.try_into().unwrap()).collect()
+    }
+
+    #[test]
+    fn lzw_known_answer() {
+        // The 10x10 sample image from the GIF article on Wikipedia
+        let data = [
+            0x8c, 0x2d, 0x99, 0x87, 0x2a, 0x1c, 0xdc, 0x33, 0xa0, 0x02, 0x75, 0xec, 0x95, 0xfa, 0xa8, 0xde, 0x60, 0x8c, 0x04,
+            0x91, 0x4c, 0x01,
+        ];
+        let rows: [&[u8; 10]; 10] = [
+            b"1111122222", b"1111122222", b"1111122222", b"1110000222", b"1110000222",
+            b"2220000111", b"2220000111", b"2222211111", b"2222211111", b"2222211111",
+        ];
+        let expected: Vec<u8> = rows.iter().flat_map(|row| row.map(|digit| digit - b'0')).collect();
+        assert_eq!(lzw_decode(&data, 2, 100).unwrap(), expected);
+    }
+
+    #[test]
+    fn lzw_table_restarts_when_full() {
+        let mut state = 0x2545_f491u32;
+        let indices: Vec<u8> = (0..50_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state % 7) as u8
+            })
+            .collect();
+        assert_eq!(lzw_decode(&lzw_encode(&indices, 3), 3, indices.len()).unwrap(), indices);
+    }
+
+    #[test]
+    fn disposal_methods_and_transparency_shape_the_canvas() {
+        let blocks = [
+            image(0, 0, 2, &[1, 1], 0, &[]),
+            control(DISPOSE_BACKGROUND, None),
+            image(1, 0, 1, &[2], 0, &[]),
+            control(DISPOSE_PREVIOUS, None),
+            image(0, 0, 1, &[3], 0, &[]),
+            control(0, Some(0)),
+            image(0, 0, 2, &[0, 0], 0, &[]),
+        ]
+        .concat();
+        let frames = decode_frames(&gif((2, 1), &blocks)).unwrap();
+        let canvases: Vec<Vec<[u8; 4]>> = frames.iter().map(pixels).collect();
+        assert_eq!(canvases, [[WHITE, WHITE], [WHITE, RED], [GREEN, CLEAR], [WHITE, CLEAR]]);
+    }
+
+    #[test]
+    fn interlaced_rows_are_put_back_in_order() {
+        // Stored rows 0, 4, 2, 1, 3 of a 1x5 frame
+        let blocks = image(0, 0, 1, &[0, 1, 2, 3, 0], 0x40, &[]);
+        let frames = decode_frames(&gif((1, 5), &blocks)).unwrap();
+        assert_eq!(frames[0].image.as_bytes(), [BLACK, GREEN, RED, BLACK, WHITE].map(|pixel| [pixel[0], pixel[1], pixel[2]]).concat());
+    }
+
+    #[test]
+    fn local_palettes_replace_the_global_one() {
+        let blocks = image(0, 0, 2, &[0, 1], 0x80, &[9, 8, 7, 6, 5, 4]);
+        let frames = decode_frames(&gif((2, 1), &blocks)).unwrap();
+        assert_eq!(frames[0].image.as_bytes(), [9, 8, 7, 6, 5, 4]);
+    }
+
+    #[test]
+    fn encoder_reserves_index_0_for_transparency() {
+        let image = DynamicImage::from_raw_parts(4, 3, 1, [RED, CLEAR, [0, 0, 255, 200]].concat());
+        let frames = [(image.as_ref(), 40), (image.as_ref(), 44)];
+        let bytes = encode_frames(&frames, &GifOptions { loop_count: Some(3), dither: false }).unwrap();
+        assert!(bytes.windows(15).any(|window| window == b"NETSCAPE2.0\x03\x01\x03\x00"));
+
+        let decoded = decode_frames(&bytes).unwrap();
+        assert_eq!(decoded.iter().map(|frame| frame.delay_ms).collect::<Vec<_>>(), [40, 40]);
+        assert_eq!(pixels(&decoded[1]), [RED, CLEAR, [0, 0, 255, 255]]);
+
+        // A still image doesn't get the looping extension
+        let still = encode_frames(&frames[..1], &GifOptions::default()).unwrap();
+        assert!(!still.windows(11).any(|window| window == b"NETSCAPE2.0"));
+    }
+
+    #[test]
+    fn frames_too_large_for_their_data_are_rejected() {
+        let blocks = [0x2c, 0, 0, 0, 0, 0x60, 0xea, 0x70, 0x17, 0, 2, 2, 0x44, 0x01, 0];
+        assert_eq!(decode_frames(&gif((16, 16), &blocks)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huge_screen_with_a_tiny_frame_is_rejected() {
+        let blocks = image(0, 0, 1, &[1], 0, &[]);
+        let bytes = gif((20000, 20000), &blocks);
+        assert_eq!(decode_frames(&bytes).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(decode_frames(&gif((300, 300), &blocks)).is_ok());
+    }
+
+    #[test]
+    fn canvas_copies_are_bounded_by_the_file() {
+        // Each 1x1 frame takes about 16 bytes but adds a copy of the whole
+        // 550x550 canvas, more than 16 bytes of codes can stand for
+        let frame = image(0, 0, 1, &[1], 0, &[]);
+        let few = gif((550, 550), &frame.repeat(2));
+        assert_eq!(decode_frames(&few).unwrap().len(), 2);
+        let many = gif((550, 550), &frame.repeat(40));
+        assert_eq!(decode_frames(&many).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn huge_screen_is_rejected_before_allocating() {
+        let blocks = image(0, 0, 1, &[1], 0, &[]);
+        let bytes = gif((u16::MAX, u16::MAX), &blocks);
+        assert_eq!(decode_frames(&bytes).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,61 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Builds huffman code lengths no longer than `max_bits`
+pub(crate) fn code_lengths(freqs: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+
+    loop {
+        let mut lengths = vec![0u8; freqs.len()];
+        let used: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+
+        if used.len() <= 1 {
+            // A lone symbol still needs a one bit code
+            for &symbol in &used {
+                lengths[symbol] = 1;
+            }
+            return lengths;
+        }
+
+        // Nodes past `freqs.len()` are internal, `parent` links let us compute depths
+        let mut parent = vec![usize::MAX; freqs.len() * 2];
+        let mut heap = BinaryHeap::new();
+        for &symbol in &used {
+            heap.push(Reverse((freqs[symbol] as u64, symbol)));
+        }
+
+        let mut next_node = freqs.len();
+        while heap.len() > 1 {
+            let Reverse((freq_a, a)) = heap.pop().unwrap();
+            let Reverse((freq_b, b)) = heap.pop().unwrap();
+            parent[a] = next_node;
+            parent[b] = next_node;
+            heap.push(Reverse((freq_a + freq_b, next_node)));
+            next_node += 1;
+        }
+
+        let mut too_long = false;
+        for &symbol in &used {
+            let mut depth = 0;
+            let mut node = symbol;
+            while parent[node] != usize::MAX {
+                node = parent[node];
+                depth += 1;
+            }
+            if depth > max_bits as usize {
+                too_long = true;
+                break;
+            }
+            lengths[symbol] = depth as u8;
+        }
+
+        if !too_long {
+            return lengths;
+        }
+
+        // Flatten the distribution and try again
+        for freq in freqs.iter_mut().filter(|freq| **freq > 0) {
+            *freq = (*freq >> 1) | 1;
+        }
+    }
+}
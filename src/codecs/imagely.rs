@@ -0,0 +1,283 @@
+use std::fs;
+use std::io::{self, Read};
+
+use super::zlib::crc32;
+use super::{image_len, invalid_data, unsupported};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel, SampleDepth};
+
+const MAGIC: [u8; 8] = [0x89, b'I', b'M', b'G', b'L', b'Y', b'\r', b'\n'];
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 24;
+const FLAG_CHECKSUM: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Gray = 1,
+    GrayAlpha = 2,
+    Rgb = 3,
+    Rgba = 4,
+}
+
+impl ChannelLayout {
+    fn from_code(code: u8) -> io::Result<ChannelLayout> {
+        match code {
+            1 => Ok(ChannelLayout::Gray),
+            2 => Ok(ChannelLayout::GrayAlpha),
+            3 => Ok(ChannelLayout::Rgb),
+            4 => Ok(ChannelLayout::Rgba),
+            _ => Err(invalid_data("unknown channel layout")),
+        }
+    }
+
+    pub fn channels(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Unknown = 0,
+    Srgb = 1,
+    LinearSrgb = 2,
+}
+
+impl ColorSpace {
+    fn from_code(code: u8) -> io::Result<ColorSpace> {
+        match code {
+            0 => Ok(ColorSpace::Unknown),
+            1 => Ok(ColorSpace::Srgb),
+            2 => Ok(ColorSpace::LinearSrgb),
+            _ => Err(invalid_data("unknown color space")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImagelyHeader {
+    pub version: u16,
+    pub width: u32,
+    pub height: u32,
+    pub layout: ChannelLayout,
+    pub bit_depth: u8,
+    pub color_space: ColorSpace,
+    pub has_checksum: bool,
+}
+
+impl ImagelyHeader {
+    fn parse(bytes: &[u8]) -> io::Result<ImagelyHeader> {
+        if !is_imagely(bytes) {
+            return Err(invalid_data("not an imagely file"));
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(invalid_data("truncated imagely header"));
+        }
+
+        let read_u16 = |pos: usize| u16::from_le_bytes([bytes[pos], bytes[pos + 1]]);
+        let read_u32 = |pos: usize| u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]);
+
+        let version = read_u16(8);
+        if version == 0 || version > VERSION {
+            return Err(unsupported("unsupported imagely version"));
+        }
+
+        Ok(ImagelyHeader {
+            version,
+            has_checksum: read_u16(10) & FLAG_CHECKSUM != 0,
+            width: read_u32(12),
+            height: read_u32(16),
+            layout: ChannelLayout::from_code(bytes[20])?,
+            bit_depth: bytes[21],
+            color_space: ColorSpace::from_code(bytes[22])?,
+        })
+    }
+
+    fn data_len(&self) -> io::Result<usize> {
+        let samples = image_len(self.width as usize, self.height as usize, self.layout.channels() as usize)?;
+        Ok(samples * self.bit_depth.div_ceil(8) as usize)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImagelyOptions {
+    pub color_space: ColorSpace,
+    // Appends a CRC32 of the header and pixel data
+    pub checksum: bool,
+}
+
+impl Default for ImagelyOptions {
+    fn default() -> Self {
+        ImagelyOptions {
+            color_space: ColorSpace::Srgb,
+            checksum: true,
+        }
+    }
+}
+
+pub(crate) fn is_imagely(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> io::Result<DynamicImage> {
+    let header = ImagelyHeader::parse(bytes)?;
+    if header.bit_depth != 8 && header.bit_depth != 16 {
+        return Err(unsupported("only 8 and 16-bit imagely files are supported"));
+    }
+
+    let data_end = HEADER_LEN + header.data_len()?;
+    let expected_len = data_end + if header.has_checksum { 4 } else { 0 };
+    if bytes.len() != expected_len {
+        return Err(invalid_data("imagely data length does not match header"));
+    }
+
+    if header.has_checksum {
+        let stored = u32::from_le_bytes([bytes[data_end], bytes[data_end + 1], bytes[data_end + 2], bytes[data_end + 3]]);
+        if crc32(&bytes[..data_end]) != stored {
+            return Err(invalid_data("imagely checksum mismatch"));
+        }
+    }
+
+    let pixels = &bytes[HEADER_LEN..data_end];
+    if header.bit_depth == 16 {
+        let samples = pixels.chunks_exact(2).map(|sample| u16::from_le_bytes([sample[0], sample[1]])).collect();
+        return Ok(DynamicImage::from_raw_parts16(header.layout.channels(), header.width, header.height, samples));
+    }
+    Ok(DynamicImage::from_raw_parts(header.layout.channels(), header.width, header.height, pixels.to_vec()))
+}
+
+// 8-bit images keep their depth, deeper ones are stored as 16-bit little-endian samples
+pub(crate) fn encode(image: DynamicImageRef<'_>, options: &ImagelyOptions) -> io::Result<Vec<u8>> {
+    let bit_depth: u8 = if image.depth() == SampleDepth::U8 { 8 } else { 16 };
+    let pixels = if bit_depth == 8 {
+        image.as_bytes().to_vec()
+    } else {
+        image.with_depth(SampleDepth::U16).as_ref().as_samples16().iter().flat_map(|sample| sample.to_le_bytes()).collect()
+    };
+    let layout = match image.channels() {
+        1 => ChannelLayout::Gray,
+        2 => ChannelLayout::GrayAlpha,
+        3 => ChannelLayout::Rgb,
+        _ => ChannelLayout::Rgba,
+    };
+    let flags = if options.checksum { FLAG_CHECKSUM } else { 0 };
+
+    let mut out = Vec::with_capacity(HEADER_LEN + pixels.len() + 4);
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&flags.to_le_bytes());
+    out.extend_from_slice(&image.width().to_le_bytes());
+    out.extend_from_slice(&image.height().to_le_bytes());
+    out.extend_from_slice(&[layout as u8, bit_depth, options.color_space as u8, 0]);
+    out.extend_from_slice(&pixels);
+
+    if options.checksum {
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+    }
+
+    Ok(out)
+}
+
+impl DynamicImage {
+    pub fn open_imagely(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode(&fs::read(path)?)?)
+    }
+
+    pub fn read_imagely_header(path: &str) -> Result<ImagelyHeader, ImageError> {
+        let mut bytes = [0u8; HEADER_LEN];
+        fs::File::open(path)?.read_exact(&mut bytes)?;
+        Ok(ImagelyHeader::parse(&bytes)?)
+    }
+
+    pub fn save_imagely(&self, path: &str) -> Result<(), ImageError> {
+        self.save_imagely_with_options(path, &ImagelyOptions::default())
+    }
+
+    pub fn save_imagely_with_options(&self, path: &str, options: &ImagelyOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_ref(), options)?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn open_imagely(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_imagely(path)?.into_image())
+    }
+
+    pub fn save_imagely(&self, path: &str) -> Result<(), ImageError> {
+        self.save_imagely_with_options(path, &ImagelyOptions::default())
+    }
+
+    pub fn save_imagely_with_options(&self, path: &str, options: &ImagelyOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_dynamic().as_ref(), options)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb_pair() -> DynamicImage {
+        DynamicImage::from_raw_parts(3, 2, 1, vec![1, 2, 3, 4, 5, 6])
+    }
+
+    #[test]
+    fn header_fields_are_little_endian_at_fixed_offsets() {
+        let options = ImagelyOptions { color_space: ColorSpace::LinearSrgb, checksum: false };
+        let bytes = encode(rgb_pair().as_ref(), &options).unwrap();
+        let expected = [
+            &MAGIC[..],
+            &[1, 0, 0, 0],
+            &[2, 0, 0, 0, 1, 0, 0, 0],
+            &[3, 8, 2, 0],
+            &[1, 2, 3, 4, 5, 6],
+        ]
+        .concat();
+        assert_eq!(bytes, expected);
+
+        let header = ImagelyHeader::parse(&bytes).unwrap();
+        assert_eq!((header.width, header.height, header.layout), (2, 1, ChannelLayout::Rgb));
+        assert_eq!((header.bit_depth, header.color_space, header.has_checksum), (8, ColorSpace::LinearSrgb, false));
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), rgb_pair().as_bytes());
+    }
+
+    #[test]
+    fn checksum_covers_header_and_pixels() {
+        let bytes = encode(rgb_pair().as_ref(), &ImagelyOptions::default()).unwrap();
+        assert_eq!(bytes[10], FLAG_CHECKSUM as u8);
+        assert_eq!(bytes[bytes.len() - 4..], crc32(&bytes[..bytes.len() - 4]).to_le_bytes());
+        assert!(decode(&bytes).is_ok());
+
+        // Flipping the color space is caught just like damaged pixels
+        for position in [22, HEADER_LEN + 5] {
+            let mut damaged = bytes.clone();
+            damaged[position] ^= 1;
+            assert_eq!(decode(&damaged).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn headers_that_disagree_with_the_data_are_rejected() {
+        let bytes = encode(rgb_pair().as_ref(), &ImagelyOptions::default()).unwrap();
+        assert_eq!(decode(&bytes[..bytes.len() - 1]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut newer = bytes.clone();
+        newer[8] = 2;
+        assert_eq!(decode(&newer).unwrap_err().kind(), io::ErrorKind::Unsupported);
+
+        let mut layout = bytes.clone();
+        layout[20] = 5;
+        assert_eq!(decode(&layout).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut huge = bytes;
+        huge[12..20].copy_from_slice(&[0xff; 8]);
+        assert_eq!(decode(&huge).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn deep_images_are_stored_as_16_bit_little_endian() {
+        let image = DynamicImage::from_raw_parts16(1, 2, 1, vec![0x1234, 0xfffe]);
+        let bytes = encode(image.as_ref(), &ImagelyOptions { checksum: false, ..ImagelyOptions::default() }).unwrap();
+        assert_eq!(bytes[21], 16);
+        assert_eq!(bytes[HEADER_LEN..], [0x34, 0x12, 0xfe, 0xff]);
+        assert_eq!(decode(&bytes).unwrap().as_ref().as_samples16(), [0x1234, 0xfffe]);
+    }
+}
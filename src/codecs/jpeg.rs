@@ -0,0 +1,49 @@
+mod decoder;
+mod encoder;
+
+use std::fs;
+
+use crate::{DynamicImage, Image, ImageError, Pixel};
+
+pub(crate) use decoder::decode;
+pub(crate) use encoder::encode;
+pub use encoder::{ChromaSubsampling, JpegOptions};
+
+// Natural (row-major) position of each coefficient in zigzag order
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27,
+    20, 13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58,
+    59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// Basis functions shared by the forward and inverse DCT, indexed by [sample][frequency]
+fn dct_cosines() -> [[f32; 8]; 8] {
+    let mut cosines = [[0f32; 8]; 8];
+    for (x, row) in cosines.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            let scale = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+            *value = scale / 2.0 * (((2 * x + 1) * u) as f32 * std::f32::consts::PI / 16.0).cos();
+        }
+    }
+    cosines
+}
+
+impl DynamicImage {
+    pub fn open_jpeg(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode(&fs::read(path)?)?)
+    }
+
+    pub fn save_jpeg(&self, path: &str, options: JpegOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_ref(), &options)?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn open_jpeg(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_jpeg(path)?.into_image())
+    }
+
+    pub fn save_jpeg(&self, path: &str, options: JpegOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_dynamic().as_ref(), &options)?)?)
+    }
+}
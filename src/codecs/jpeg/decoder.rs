@@ -0,0 +1,864 @@
+use std::io;
+
+use super::super::{image_len, invalid_data, unsupported};
+use super::{ZIGZAG, dct_cosines};
+use crate::DynamicImage;
+
+const SOF0: u8 = 0xc0;
+const SOF1: u8 = 0xc1;
+const SOF2: u8 = 0xc2;
+const DHT: u8 = 0xc4;
+const RST0: u8 = 0xd0;
+const RST7: u8 = 0xd7;
+const SOI: u8 = 0xd8;
+const EOI: u8 = 0xd9;
+const SOS: u8 = 0xda;
+const DQT: u8 = 0xdb;
+const DRI: u8 = 0xdd;
+const APP14: u8 = 0xee;
+
+struct Huffman {
+    // Indexed by the next 16 bits of input, entries are `length << 8 | symbol`
+    table: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8; 16], symbols: &[u8]) -> io::Result<Huffman> {
+        let mut table = vec![0u16; 1 << 16];
+        let mut code: u32 = 0;
+        let mut symbol_index = 0;
+
+        for (i, &count) in counts.iter().enumerate() {
+            let len = i as u32 + 1;
+            for _ in 0..count {
+                if code >= 1 << len {
+                    return Err(invalid_data("over-subscribed JPEG huffman table"));
+                }
+                let start = (code << (16 - len)) as usize;
+                let end = ((code + 1) << (16 - len)) as usize;
+                table[start..end].fill(((len as u16) << 8) | symbols[symbol_index] as u16);
+                symbol_index += 1;
+                code += 1;
+            }
+            code <<= 1;
+        }
+
+        Ok(Huffman { table })
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u64,
+    count: u32,
+    // Set once a marker is reached, the reader pads with zeros from then on
+    marker_hit: bool,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        BitReader { data, pos, bits: 0, count: 0, marker_hit: false }
+    }
+
+    fn fill(&mut self) {
+        while self.count <= 56 {
+            let mut byte = 0;
+            if !self.marker_hit && self.pos < self.data.len() {
+                byte = self.data[self.pos];
+                if byte == 0xff {
+                    match self.data.get(self.pos + 1) {
+                        Some(0x00) => self.pos += 2,
+                        _ => {
+                            self.marker_hit = true;
+                            byte = 0;
+                        }
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.bits |= (byte as u64) << (56 - self.count);
+            self.count += 8;
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        if self.count < n {
+            self.fill();
+        }
+        let value = (self.bits >> (64 - n)) as u32;
+        self.bits <<= n;
+        self.count -= n;
+        value
+    }
+
+    fn bit(&mut self) -> bool {
+        self.bits(1) == 1
+    }
+
+    // Reads `n` bits as a signed value (EXTEND in the spec)
+    fn receive_extend(&mut self, n: u32) -> i32 {
+        if n == 0 {
+            return 0;
+        }
+        let value = self.bits(n) as i32;
+        if value < 1 << (n - 1) { value - (1 << n) + 1 } else { value }
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> io::Result<u8> {
+        if self.count < 16 {
+            self.fill();
+        }
+        let entry = huffman.table[(self.bits >> 48) as usize];
+        let len = (entry >> 8) as u32;
+        if len == 0 {
+            return Err(invalid_data("invalid JPEG huffman code"));
+        }
+        self.bits <<= len;
+        self.count -= len;
+        Ok(entry as u8)
+    }
+
+    fn restart(&mut self) -> io::Result<()> {
+        self.bits = 0;
+        self.count = 0;
+        self.marker_hit = false;
+
+        // Skip any fill bytes up to the RST marker
+        while self.pos + 1 < self.data.len() && self.data[self.pos] == 0xff && self.data[self.pos + 1] == 0xff {
+            self.pos += 1;
+        }
+        match self.data.get(self.pos..self.pos + 2) {
+            Some(&[0xff, marker]) if (RST0..=RST7).contains(&marker) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err(invalid_data("missing JPEG restart marker")),
+        }
+    }
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant_table: usize,
+    dc_table: usize,
+    ac_table: usize,
+    // Blocks covering the component, padded out to whole MCUs
+    blocks_w: usize,
+    blocks_h: usize,
+    coefficients: Vec<i32>,
+    dc_pred: i32,
+}
+
+impl Component {
+    fn block(&mut self, block_x: usize, block_y: usize) -> &mut [i32] {
+        let start = (block_y * self.blocks_w + block_x) * 64;
+        &mut self.coefficients[start..start + 64]
+    }
+}
+
+struct Scan {
+    components: Vec<usize>,
+    spectral_start: usize,
+    spectral_end: usize,
+    approx_high: u32,
+    approx_low: u32,
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    width: usize,
+    height: usize,
+    progressive: bool,
+    components: Vec<Component>,
+    quant_tables: [[u16; 64]; 4],
+    dc_tables: [Option<Huffman>; 4],
+    ac_tables: [Option<Huffman>; 4],
+    restart_interval: usize,
+    adobe_transform: Option<u8>,
+    eob_run: u32,
+    h_max: usize,
+    v_max: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let byte = *self.data.get(self.pos).ok_or_else(|| invalid_data("unexpected end of JPEG data"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        Ok(((self.read_u8()? as u16) << 8) | self.read_u8()? as u16)
+    }
+
+    // Returns the body of a marker segment and moves past it
+    fn segment(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.read_u16()? as usize;
+        if len < 2 || self.pos + len - 2 > self.data.len() {
+            return Err(invalid_data("invalid JPEG segment length"));
+        }
+        let body = &self.data[self.pos..self.pos + len - 2];
+        self.pos += len - 2;
+        Ok(body)
+    }
+
+    fn next_marker(&mut self) -> io::Result<u8> {
+        // Skip anything that isn't a marker, e.g. leftover entropy coded data
+        loop {
+            if self.read_u8()? != 0xff {
+                continue;
+            }
+            let mut marker = self.read_u8()?;
+            while marker == 0xff {
+                marker = self.read_u8()?;
+            }
+            if marker != 0x00 && !(RST0..=RST7).contains(&marker) {
+                return Ok(marker);
+            }
+        }
+    }
+
+    fn read_quant_tables(&mut self, body: &[u8]) -> io::Result<()> {
+        let mut pos = 0;
+        while pos < body.len() {
+            let precision = body[pos] >> 4;
+            let id = (body[pos] & 0x0f) as usize;
+            pos += 1;
+            if id > 3 {
+                return Err(invalid_data("invalid JPEG quantization table id"));
+            }
+
+            let entry_len = if precision == 0 { 1 } else { 2 };
+            if pos + 64 * entry_len > body.len() {
+                return Err(invalid_data("truncated JPEG quantization table"));
+            }
+            for k in 0..64 {
+                self.quant_tables[id][k] = if precision == 0 {
+                    body[pos + k] as u16
+                } else {
+                    u16::from_be_bytes([body[pos + k * 2], body[pos + k * 2 + 1]])
+                };
+            }
+            pos += 64 * entry_len;
+        }
+        Ok(())
+    }
+
+    fn read_huffman_tables(&mut self, body: &[u8]) -> io::Result<()> {
+        let mut pos = 0;
+        while pos < body.len() {
+            if pos + 17 > body.len() {
+                return Err(invalid_data("truncated JPEG huffman table"));
+            }
+            let class = body[pos] >> 4;
+            let id = (body[pos] & 0x0f) as usize;
+            if id > 3 || class > 1 {
+                return Err(invalid_data("invalid JPEG huffman table id"));
+            }
+
+            let mut counts = [0u8; 16];
+            counts.copy_from_slice(&body[pos + 1..pos + 17]);
+            let total: usize = counts.iter().map(|&count| count as usize).sum();
+            pos += 17;
+            if pos + total > body.len() {
+                return Err(invalid_data("truncated JPEG huffman table"));
+            }
+
+            // DC symbols are bit counts of a difference, at most 11 for 8-bit samples,
+            // AC symbols keep theirs in the low nibble so they can't exceed 15
+            let symbols = &body[pos..pos + total];
+            if class == 0 && symbols.iter().any(|&symbol| symbol > 11) {
+                return Err(invalid_data("invalid JPEG DC huffman symbol"));
+            }
+            let table = Huffman::new(&counts, symbols)?;
+            pos += total;
+            if class == 0 {
+                self.dc_tables[id] = Some(table);
+            } else {
+                self.ac_tables[id] = Some(table);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 6 {
+            return Err(invalid_data("truncated JPEG frame header"));
+        }
+        if body[0] != 8 {
+            return Err(unsupported("only 8-bit JPEGs are supported"));
+        }
+        self.height = u16::from_be_bytes([body[1], body[2]]) as usize;
+        self.width = u16::from_be_bytes([body[3], body[4]]) as usize;
+        if self.width == 0 || self.height == 0 {
+            return Err(unsupported("JPEGs without dimensions in the frame header are not supported"));
+        }
+        image_len(self.width, self.height, 1)?;
+
+        let component_count = body[5] as usize;
+        if ![1, 3, 4].contains(&component_count) || body.len() < 6 + component_count * 3 {
+            return Err(unsupported("unsupported number of JPEG components"));
+        }
+
+        for i in 0..component_count {
+            let spec = &body[6 + i * 3..9 + i * 3];
+            let (h, v) = ((spec[1] >> 4) as usize, (spec[1] & 0x0f) as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || spec[2] > 3 {
+                return Err(invalid_data("invalid JPEG component"));
+            }
+            self.components.push(Component {
+                id: spec[0],
+                h,
+                v,
+                quant_table: spec[2] as usize,
+                dc_table: 0,
+                ac_table: 0,
+                blocks_w: 0,
+                blocks_h: 0,
+                coefficients: Vec::new(),
+                dc_pred: 0,
+            });
+        }
+
+        self.h_max = self.components.iter().map(|c| c.h).max().unwrap();
+        self.v_max = self.components.iter().map(|c| c.v).max().unwrap();
+        self.mcus_x = self.width.div_ceil(8 * self.h_max);
+        self.mcus_y = self.height.div_ceil(8 * self.v_max);
+
+        for component in &mut self.components {
+            component.blocks_w = self.mcus_x * component.h;
+            component.blocks_h = self.mcus_y * component.v;
+            component.coefficients = vec![0; component.blocks_w * component.blocks_h * 64];
+        }
+        Ok(())
+    }
+
+    fn read_scan_header(&mut self, body: &[u8]) -> io::Result<Scan> {
+        let count = *body.first().ok_or_else(|| invalid_data("truncated JPEG scan header"))? as usize;
+        if count == 0 || count > 4 || body.len() < 4 + count * 2 {
+            return Err(invalid_data("invalid JPEG scan header"));
+        }
+
+        let mut components = Vec::with_capacity(count);
+        for i in 0..count {
+            let (id, tables) = (body[1 + i * 2], body[2 + i * 2]);
+            let index = self
+                .components
+                .iter()
+                .position(|c| c.id == id)
+                .ok_or_else(|| invalid_data("JPEG scan references unknown component"))?;
+            let component = &mut self.components[index];
+            component.dc_table = (tables >> 4) as usize & 3;
+            component.ac_table = (tables & 0x0f) as usize & 3;
+            components.push(index);
+        }
+
+        let rest = &body[1 + count * 2..];
+        let scan = Scan {
+            components,
+            spectral_start: rest[0] as usize,
+            spectral_end: rest[1] as usize,
+            approx_high: (rest[2] >> 4) as u32,
+            approx_low: (rest[2] & 0x0f) as u32,
+        };
+
+        if self.progressive {
+            let valid = if scan.spectral_start == 0 {
+                scan.spectral_end == 0
+            } else {
+                scan.spectral_start <= scan.spectral_end && scan.spectral_end < 64 && scan.components.len() == 1
+            };
+            if !valid {
+                return Err(invalid_data("invalid progressive JPEG scan"));
+            }
+        }
+        Ok(scan)
+    }
+
+    fn decode_scan(&mut self, scan: &Scan) -> io::Result<()> {
+        let mut reader = BitReader::new(self.data, self.pos);
+        self.eob_run = 0;
+        for &index in &scan.components {
+            self.components[index].dc_pred = 0;
+        }
+
+        // A lone component is coded block by block rather than in MCUs
+        let single = scan.components.len() == 1;
+        let (units_x, units_y) = if single {
+            let component = &self.components[scan.components[0]];
+            (
+                (self.width * component.h).div_ceil(self.h_max).div_ceil(8),
+                (self.height * component.v).div_ceil(self.v_max).div_ceil(8),
+            )
+        } else {
+            (self.mcus_x, self.mcus_y)
+        };
+
+        let mut units_left = self.restart_interval;
+        for unit_y in 0..units_y {
+            for unit_x in 0..units_x {
+                if self.restart_interval > 0 {
+                    if units_left == 0 {
+                        reader.restart()?;
+                        self.eob_run = 0;
+                        for &index in &scan.components {
+                            self.components[index].dc_pred = 0;
+                        }
+                        units_left = self.restart_interval;
+                    }
+                    units_left -= 1;
+                }
+
+                for &index in &scan.components {
+                    let (h, v) = if single { (1, 1) } else { (self.components[index].h, self.components[index].v) };
+                    for block_y in 0..v {
+                        for block_x in 0..h {
+                            self.decode_block(&mut reader, scan, index, unit_x * h + block_x, unit_y * v + block_y)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.pos = reader.pos;
+        Ok(())
+    }
+
+    fn decode_block(
+        &mut self,
+        reader: &mut BitReader,
+        scan: &Scan,
+        index: usize,
+        block_x: usize,
+        block_y: usize,
+    ) -> io::Result<()> {
+        let component = &mut self.components[index];
+        let dc_table = self.dc_tables[component.dc_table].as_ref();
+        let ac_table = self.ac_tables[component.ac_table].as_ref();
+        let missing_table = || invalid_data("JPEG scan uses an undefined huffman table");
+
+        let low = scan.approx_low;
+
+        if !self.progressive {
+            let (dc_table, ac_table) = (dc_table.ok_or_else(missing_table)?, ac_table.ok_or_else(missing_table)?);
+            let size = reader.decode(dc_table)? as u32;
+            component.dc_pred = component.dc_pred.wrapping_add(reader.receive_extend(size));
+            let dc = component.dc_pred;
+
+            let block = component.block(block_x, block_y);
+            block[0] = dc;
+            let mut k = 1;
+            while k < 64 {
+                let rs = reader.decode(ac_table)?;
+                let (run, size) = ((rs >> 4) as usize, (rs & 0x0f) as u32);
+                if size == 0 {
+                    if run != 15 {
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run;
+                if k >= 64 {
+                    return Err(invalid_data("JPEG coefficient index out of range"));
+                }
+                block[k] = reader.receive_extend(size);
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        if scan.spectral_start == 0 {
+            if scan.approx_high == 0 {
+                let size = reader.decode(dc_table.ok_or_else(missing_table)?)? as u32;
+                component.dc_pred = component.dc_pred.wrapping_add(reader.receive_extend(size));
+                let dc = component.dc_pred;
+                component.block(block_x, block_y)[0] = dc << low;
+            } else if reader.bit() {
+                component.block(block_x, block_y)[0] |= 1 << low;
+            }
+            return Ok(());
+        }
+
+        let ac_table = ac_table.ok_or_else(missing_table)?;
+        let block = component.block(block_x, block_y);
+        let (start, end) = (scan.spectral_start, scan.spectral_end);
+
+        if scan.approx_high == 0 {
+            if self.eob_run > 0 {
+                self.eob_run -= 1;
+                return Ok(());
+            }
+
+            let mut k = start;
+            while k <= end {
+                let rs = reader.decode(ac_table)?;
+                let (run, size) = ((rs >> 4) as u32, (rs & 0x0f) as u32);
+                if size == 0 {
+                    if run < 15 {
+                        self.eob_run = (1 << run) - 1 + reader.bits(run);
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run as usize;
+                if k > end {
+                    return Err(invalid_data("JPEG coefficient index out of range"));
+                }
+                block[k] = reader.receive_extend(size) * (1 << low);
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        // Successive approximation refinement of AC coefficients
+        let positive = 1 << low;
+        let negative = -1 << low;
+        let refine = |coefficient: &mut i32, reader: &mut BitReader| {
+            if reader.bit() && *coefficient & positive == 0 {
+                *coefficient += if *coefficient >= 0 { positive } else { negative };
+            }
+        };
+
+        let mut k = start;
+        if self.eob_run == 0 {
+            while k <= end {
+                let rs = reader.decode(ac_table)?;
+                let (mut run, size) = ((rs >> 4) as i32, (rs & 0x0f) as u32);
+                let mut value = 0;
+                if size == 0 {
+                    if run < 15 {
+                        self.eob_run = (1 << run) + reader.bits(run as u32);
+                        break;
+                    }
+                } else {
+                    value = if reader.bit() { positive } else { negative };
+                }
+
+                while k <= end {
+                    if block[k] != 0 {
+                        refine(&mut block[k], reader);
+                    } else {
+                        if run == 0 {
+                            break;
+                        }
+                        run -= 1;
+                    }
+                    k += 1;
+                }
+
+                if value != 0 && k <= end {
+                    block[k] = value;
+                }
+                k += 1;
+            }
+        }
+
+        if self.eob_run > 0 {
+            while k <= end {
+                if block[k] != 0 {
+                    refine(&mut block[k], reader);
+                }
+                k += 1;
+            }
+            self.eob_run -= 1;
+        }
+        Ok(())
+    }
+
+    // Dequantizes and inverse transforms every block into a sample plane
+    fn component_plane(&self, component: &Component) -> Vec<u8> {
+        let plane_w = component.blocks_w * 8;
+        let mut plane = vec![0u8; plane_w * component.blocks_h * 8];
+        let quant = &self.quant_tables[component.quant_table];
+        let cosines = dct_cosines();
+
+        let mut coefficients = [0f32; 64];
+        let mut temp = [0f32; 64];
+        for block_y in 0..component.blocks_h {
+            for block_x in 0..component.blocks_w {
+                let start = (block_y * component.blocks_w + block_x) * 64;
+                let block = &component.coefficients[start..start + 64];
+                for k in 0..64 {
+                    coefficients[ZIGZAG[k]] = block[k] as f32 * quant[k] as f32;
+                }
+
+                // Rows then columns of the separable 2D IDCT
+                for y in 0..8 {
+                    for x in 0..8 {
+                        temp[y * 8 + x] = (0..8).map(|u| cosines[x][u] * coefficients[y * 8 + u]).sum();
+                    }
+                }
+                for x in 0..8 {
+                    for (y, cosine_row) in cosines.iter().enumerate() {
+                        let value: f32 = (0..8).map(|v| cosine_row[v] * temp[v * 8 + x]).sum();
+                        let index = (block_y * 8 + y) * plane_w + block_x * 8 + x;
+                        plane[index] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+            }
+        }
+        plane
+    }
+
+    fn to_image(&self) -> DynamicImage {
+        let (width, height) = (self.width, self.height);
+
+        // Upsample every component to full resolution, centering chroma samples
+        let planes: Vec<Vec<u8>> = self
+            .components
+            .iter()
+            .map(|component| {
+                let plane = self.component_plane(component);
+                if component.h == self.h_max && component.v == self.v_max {
+                    let plane_w = component.blocks_w * 8;
+                    return (0..height).flat_map(|y| plane[y * plane_w..y * plane_w + width].to_vec()).collect();
+                }
+                upsample(&plane, component, self.h_max, self.v_max, width, height)
+            })
+            .collect();
+
+        // A single component is grayscale
+        if planes.len() == 1 {
+            return DynamicImage::from_raw_parts(1, width as u32, height as u32, planes.into_iter().next().unwrap());
+        }
+
+        let mut data = Vec::with_capacity(width * height * 3);
+        for i in 0..width * height {
+            match planes.len() {
+                3 => {
+                    let (a, b, c) = (planes[0][i], planes[1][i], planes[2][i]);
+                    // Adobe transform 0 means the channels are stored as RGB
+                    if self.adobe_transform == Some(0) || self.is_rgb_by_id() {
+                        data.extend_from_slice(&[a, b, c]);
+                    } else {
+                        data.extend_from_slice(&ycbcr_to_rgb(a, b, c));
+                    }
+                }
+                _ => {
+                    // Adobe writes inverted CMYK, with YCCK when the transform is 2
+                    let (mut c, mut m, mut y) = (planes[0][i], planes[1][i], planes[2][i]);
+                    let k = planes[3][i] as u32;
+                    if self.adobe_transform == Some(2) {
+                        let [r, g, b] = ycbcr_to_rgb(c, m, y);
+                        (c, m, y) = (255 - r, 255 - g, 255 - b);
+                    }
+                    let blend = |value: u8| ((value as u32 * k + 127) / 255) as u8;
+                    data.extend_from_slice(&[blend(c), blend(m), blend(y)]);
+                }
+            }
+        }
+
+        DynamicImage::from_raw_parts(3, width as u32, height as u32, data)
+    }
+
+    fn is_rgb_by_id(&self) -> bool {
+        self.adobe_transform.is_none() && self.components.iter().map(|c| c.id).eq(*b"RGB")
+    }
+}
+
+fn upsample(plane: &[u8], component: &Component, h_max: usize, v_max: usize, width: usize, height: usize) -> Vec<u8> {
+    let plane_w = component.blocks_w * 8;
+    let plane_h = component.blocks_h * 8;
+    let scale_x = component.h as f32 / h_max as f32;
+    let scale_y = component.v as f32 / v_max as f32;
+
+    // Last valid sample of the component, so edges don't blend in padding
+    let max_x = ((width * component.h).div_ceil(h_max) - 1).min(plane_w - 1);
+    let max_y = ((height * component.v).div_ceil(v_max) - 1).min(plane_h - 1);
+
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let source_y = ((y as f32 + 0.5) * scale_y - 0.5).max(0.0);
+        let y0 = (source_y as usize).min(max_y);
+        let y1 = (y0 + 1).min(max_y);
+        let fy = source_y - y0 as f32;
+
+        for x in 0..width {
+            let source_x = ((x as f32 + 0.5) * scale_x - 0.5).max(0.0);
+            let x0 = (source_x as usize).min(max_x);
+            let x1 = (x0 + 1).min(max_x);
+            let fx = source_x - x0 as f32;
+
+            let sample = |sx: usize, sy: usize| plane[sy * plane_w + sx] as f32;
+            let top = sample(x0, y0) * (1.0 - fx) + sample(x1, y0) * fx;
+            let bottom = sample(x0, y1) * (1.0 - fx) + sample(x1, y1) * fx;
+            out.push((top * (1.0 - fy) + bottom * fy).round() as u8);
+        }
+    }
+    out
+}
+
+fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let (y, cb, cr) = (y as f32, cb as f32 - 128.0, cr as f32 - 128.0);
+    let clamp = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+    [
+        clamp(y + 1.402 * cr),
+        clamp(y - 0.344136 * cb - 0.714136 * cr),
+        clamp(y + 1.772 * cb),
+    ]
+}
+
+pub(crate) fn decode(data: &[u8]) -> io::Result<DynamicImage> {
+    if data.len() < 4 || data[0] != 0xff || data[1] != SOI {
+        return Err(invalid_data("not a JPEG file"));
+    }
+
+    let mut decoder = Decoder {
+        data,
+        pos: 2,
+        width: 0,
+        height: 0,
+        progressive: false,
+        components: Vec::new(),
+        quant_tables: [[1; 64]; 4],
+        dc_tables: [None, None, None, None],
+        ac_tables: [None, None, None, None],
+        restart_interval: 0,
+        adobe_transform: None,
+        eob_run: 0,
+        h_max: 1,
+        v_max: 1,
+        mcus_x: 0,
+        mcus_y: 0,
+    };
+
+    loop {
+        let marker = decoder.next_marker()?;
+        match marker {
+            SOF0 | SOF1 | SOF2 => {
+                if !decoder.components.is_empty() {
+                    return Err(invalid_data("multiple JPEG frames"));
+                }
+                decoder.progressive = marker == SOF2;
+                let body = decoder.segment()?;
+                decoder.read_frame(body)?;
+            }
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err(unsupported("lossless, hierarchical and arithmetic coded JPEGs are not supported"));
+            }
+            DHT => {
+                let body = decoder.segment()?;
+                decoder.read_huffman_tables(body)?;
+            }
+            DQT => {
+                let body = decoder.segment()?;
+                decoder.read_quant_tables(body)?;
+            }
+            DRI => {
+                let body = decoder.segment()?;
+                if body.len() < 2 {
+                    return Err(invalid_data("truncated JPEG restart interval"));
+                }
+                decoder.restart_interval = u16::from_be_bytes([body[0], body[1]]) as usize;
+            }
+            APP14 => {
+                let body = decoder.segment()?;
+                if body.len() >= 12 && body.starts_with(b"Adobe") {
+                    decoder.adobe_transform = Some(body[11]);
+                }
+            }
+            SOS => {
+                if decoder.components.is_empty() {
+                    return Err(invalid_data("JPEG scan before frame header"));
+                }
+                let body = decoder.segment()?;
+                let scan = decoder.read_scan_header(body)?;
+                decoder.decode_scan(&scan)?;
+            }
+            EOI => break,
+            _ => {
+                decoder.segment()?;
+            }
+        }
+    }
+
+    if decoder.components.is_empty() {
+        return Err(invalid_data("JPEG has no frame"));
+    }
+    Ok(decoder.to_image())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::jpeg::{ChromaSubsampling, JpegOptions, encode};
+
+    fn segment(out: &mut Vec<u8>, marker: u8, body: &[u8]) {
+        out.extend_from_slice(&[0xff, marker]);
+        out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(body);
+    }
+
+    // A grayscale frame whose only DC code is category 4 and only AC code is
+    // end of block, both a single 0 bit, with every quantizer at 8
+    fn gray_jpeg(width: u16, restart_interval: Option<u16>, scan: &[u8]) -> Vec<u8> {
+        let mut out = vec![0xff, SOI];
+        segment(&mut out, DQT, &[[0].as_slice(), &[8; 64]].concat());
+        let mut frame = vec![8];
+        frame.extend_from_slice(&8u16.to_be_bytes());
+        frame.extend_from_slice(&width.to_be_bytes());
+        frame.extend_from_slice(&[1, 1, 0x11, 0]);
+        segment(&mut out, SOF0, &frame);
+
+        let mut counts = [0u8; 16];
+        counts[0] = 1;
+        segment(&mut out, DHT, &[&[0x00][..], &counts, &[4]].concat());
+        segment(&mut out, DHT, &[&[0x10][..], &counts, &[0]].concat());
+        if let Some(interval) = restart_interval {
+            segment(&mut out, DRI, &interval.to_be_bytes());
+        }
+        segment(&mut out, SOS, &[1, 1, 0x00, 0, 63, 0]);
+        out.extend_from_slice(scan);
+        out.extend_from_slice(&[0xff, EOI]);
+        out
+    }
+
+    #[test]
+    fn dc_only_block_decodes_to_its_level() {
+        // DC code 0, difference 1111 (15), end of block 0, padded with ones:
+        // 15 * 8 / 8 above the 128 level shift
+        let image = decode(&gray_jpeg(8, None, &[0b0111_1011])).unwrap();
+        assert_eq!((image.width(), image.height(), image.channels()), (8, 8, 1));
+        assert!(image.as_bytes().iter().all(|&value| value == 143));
+    }
+
+    #[test]
+    fn restart_markers_reset_the_dc_prediction() {
+        // Without the reset the second block would add its difference to the first
+        let image = decode(&gray_jpeg(16, Some(1), &[0b0111_1011, 0xff, RST0, 0b0111_1011])).unwrap();
+        assert!(image.as_bytes().iter().all(|&value| value == 143));
+
+        let image = decode(&gray_jpeg(16, None, &[0b0111_1001, 0b1110_1111])).unwrap();
+        assert_eq!(image.as_bytes()[..16], [[143; 8], [158; 8]].concat());
+    }
+
+    #[test]
+    fn progressive_scans_add_up_to_the_baseline_coefficients() {
+        let data = (0..24 * 16 * 3).map(|i| ((i * 37) % 251) as u8).collect();
+        let image = DynamicImage::from_raw_parts(3, 24, 16, data);
+        let options = JpegOptions { quality: 90, subsampling: ChromaSubsampling::Yuv444, ..JpegOptions::default() };
+        let baseline = decode(&encode(image.as_ref(), &options).unwrap()).unwrap();
+        let progressive = decode(&encode(image.as_ref(), &JpegOptions { progressive: true, ..options }).unwrap()).unwrap();
+        assert_eq!(baseline.as_bytes(), progressive.as_bytes());
+    }
+
+    #[test]
+    fn dc_symbol_past_category_11_is_rejected() {
+        let mut bytes = gray_jpeg(8, None, &[0b0111_1011]);
+        let dht = bytes.windows(2).position(|marker| marker == [0xff, DHT]).unwrap();
+        bytes[dht + 4 + 17] = 0x40;
+        assert_eq!(decode(&bytes).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
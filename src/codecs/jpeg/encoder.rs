@@ -0,0 +1,639 @@
+use std::io;
+
+use super::super::{huffman, unsupported};
+use super::{ZIGZAG, dct_cosines};
+use crate::DynamicImageRef;
+
+const LUMA_QUANT: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69,
+    56, 14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104,
+    113, 92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+const CHROMA_QUANT: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99,
+    99, 47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// Standard huffman tables from Annex K of the JPEG spec, as (counts per length, symbols)
+const LUMA_DC_COUNTS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const CHROMA_DC_COUNTS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_SYMBOLS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const LUMA_AC_COUNTS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const LUMA_AC_SYMBOLS: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61,
+    0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52,
+    0xd1, 0xf0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25,
+    0x26, 0x27, 0x28, 0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45,
+    0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64,
+    0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83,
+    0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+    0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6,
+    0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3,
+    0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8,
+    0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+];
+
+const CHROMA_AC_COUNTS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_SYMBOLS: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61,
+    0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33,
+    0x52, 0xf0, 0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18,
+    0x19, 0x1a, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44,
+    0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63,
+    0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a,
+    0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+    0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4,
+    0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca,
+    0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7,
+    0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+];
+
+const MAX_EOB_RUN: u32 = 0x7fff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    Yuv444,
+    Yuv422,
+    Yuv420,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JpegOptions {
+    // 1 (smallest) to 100 (best)
+    pub quality: u8,
+    pub subsampling: ChromaSubsampling,
+    pub progressive: bool,
+    // Builds huffman tables from the image instead of the standard ones, always on for progressive
+    pub optimize_huffman: bool,
+    // Color that transparent pixels are flattened against
+    pub background: (u8, u8, u8),
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        JpegOptions {
+            quality: 85,
+            subsampling: ChromaSubsampling::Yuv420,
+            progressive: false,
+            optimize_huffman: false,
+            background: (255, 255, 255),
+        }
+    }
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    blocks_w: usize,
+    // Blocks actually covering the image, used by single component scans
+    used_w: usize,
+    used_h: usize,
+    // Quantized coefficients in zigzag order, block by block
+    coefficients: Vec<i32>,
+}
+
+impl Component {
+    fn block(&self, block_x: usize, block_y: usize) -> &[i32] {
+        let start = (block_y * self.blocks_w + block_x) * 64;
+        &self.coefficients[start..start + 64]
+    }
+
+    // Luma uses table 0, chroma table 1
+    fn table(&self) -> usize {
+        if self.id == 1 { 0 } else { 1 }
+    }
+}
+
+// One huffman coded symbol followed by `len` raw bits
+#[derive(Clone, Copy)]
+struct Symbol {
+    // 0 and 1 are DC tables, 2 and 3 AC tables
+    table: u8,
+    value: u8,
+    bits: u16,
+    len: u8,
+}
+
+struct HuffmanTable {
+    counts: [u8; 16],
+    symbols: Vec<u8>,
+    // (code, length) for each symbol value
+    codes: Vec<(u16, u8)>,
+}
+
+impl HuffmanTable {
+    fn new(counts: [u8; 16], symbols: Vec<u8>) -> HuffmanTable {
+        let mut codes = vec![(0, 0); 256];
+        let mut code: u16 = 0;
+        let mut index = 0;
+        for (i, &count) in counts.iter().enumerate() {
+            for _ in 0..count {
+                codes[symbols[index] as usize] = (code, i as u8 + 1);
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+        HuffmanTable { counts, symbols, codes }
+    }
+
+    fn from_frequencies(freqs: &[u32; 256]) -> HuffmanTable {
+        // A reserved symbol with the lowest frequency keeps the all ones code out of use
+        let mut with_reserved = freqs.to_vec();
+        with_reserved.push(1);
+        let mut lengths = huffman::code_lengths(&with_reserved, 16);
+
+        let max_len = *lengths.iter().max().unwrap();
+        if lengths[256] != max_len {
+            let swap = lengths.iter().rposition(|&len| len == max_len).unwrap();
+            lengths.swap(256, swap);
+        }
+
+        let mut counts = [0u8; 16];
+        let mut symbols = Vec::new();
+        for len in 1..=16u8 {
+            for (symbol, _) in lengths[..256].iter().enumerate().filter(|&(_, &l)| l == len) {
+                counts[len as usize - 1] += 1;
+                symbols.push(symbol as u8);
+            }
+        }
+        HuffmanTable::new(counts, symbols)
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, len: u32) {
+        if len == 0 {
+            return;
+        }
+        self.bits = (self.bits << len) | (bits & ((1 << len) - 1));
+        self.count += len;
+        while self.count >= 8 {
+            let byte = (self.bits >> (self.count - 8)) as u8;
+            self.out.push(byte);
+            // 0xff in entropy coded data is followed by a stuffed zero
+            if byte == 0xff {
+                self.out.push(0);
+            }
+            self.count -= 8;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.write(0x7f, 8 - self.count);
+        }
+    }
+}
+
+fn bit_size(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+// Negative values are stored as the one's complement of their magnitude
+fn value_bits(value: i32, size: u8) -> u16 {
+    if value < 0 { (value - 1) as u16 & ((1u32 << size) - 1) as u16 } else { value as u16 }
+}
+
+fn scaled_quant_table(base: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+    base.map(|value| ((value as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+// `plane` is (samples, width, height), `frame` is (MCUs across, MCUs down, used blocks across, used blocks down)
+fn build_component(
+    plane: (&[f32], usize, usize),
+    id: u8,
+    h: usize,
+    v: usize,
+    frame: (usize, usize, usize, usize),
+    quant: &[u16; 64],
+) -> Component {
+    let (plane, plane_w, plane_h) = plane;
+    let (mcus_x, mcus_y, used_w, used_h) = frame;
+    let (blocks_w, blocks_h) = (mcus_x * h, mcus_y * v);
+    let cosines = dct_cosines();
+
+    let mut coefficients = vec![0; blocks_w * blocks_h * 64];
+    let mut samples = [0f32; 64];
+    let mut temp = [0f32; 64];
+
+    for block_y in 0..blocks_h {
+        for block_x in 0..blocks_w {
+            // Pad partial blocks by repeating the edge samples
+            for y in 0..8 {
+                let source_y = (block_y * 8 + y).min(plane_h - 1);
+                for x in 0..8 {
+                    let source_x = (block_x * 8 + x).min(plane_w - 1);
+                    samples[y * 8 + x] = plane[source_y * plane_w + source_x] - 128.0;
+                }
+            }
+
+            for y in 0..8 {
+                for u in 0..8 {
+                    temp[y * 8 + u] = (0..8).map(|x| cosines[x][u] * samples[y * 8 + x]).sum();
+                }
+            }
+
+            let start = (block_y * blocks_w + block_x) * 64;
+            for (k, &natural) in ZIGZAG.iter().enumerate() {
+                let (v_freq, u_freq) = (natural / 8, natural % 8);
+                let value: f32 = (0..8).map(|y| cosines[y][v_freq] * temp[y * 8 + u_freq]).sum();
+                coefficients[start + k] = (value / quant[natural] as f32).round() as i32;
+            }
+        }
+    }
+
+    Component { id, h, v, blocks_w, used_w, used_h, coefficients }
+}
+
+// Averages `h_factor` x `v_factor` areas of a full resolution plane
+fn downsample(plane: &[f32], width: usize, height: usize, h_factor: usize, v_factor: usize) -> (Vec<f32>, usize, usize) {
+    if h_factor == 1 && v_factor == 1 {
+        return (plane.to_vec(), width, height);
+    }
+
+    let (out_w, out_h) = (width.div_ceil(h_factor), height.div_ceil(v_factor));
+    let mut out = Vec::with_capacity(out_w * out_h);
+    for y in 0..out_h {
+        for x in 0..out_w {
+            let mut sum = 0.0;
+            let mut count = 0.0;
+            for sy in y * v_factor..((y + 1) * v_factor).min(height) {
+                for sx in x * h_factor..((x + 1) * h_factor).min(width) {
+                    sum += plane[sy * width + sx];
+                    count += 1.0;
+                }
+            }
+            out.push(sum / count);
+        }
+    }
+    (out, out_w, out_h)
+}
+
+fn encode_dc_scan(components: &[&Component], mcus: (usize, usize), symbols: &mut Vec<Symbol>) {
+    let mut predictions = vec![0; components.len()];
+    let single = components.len() == 1;
+    let (units_x, units_y) = if single { (components[0].used_w, components[0].used_h) } else { mcus };
+
+    for unit_y in 0..units_y {
+        for unit_x in 0..units_x {
+            for (i, component) in components.iter().enumerate() {
+                let (h, v) = if single { (1, 1) } else { (component.h, component.v) };
+                for block_y in 0..v {
+                    for block_x in 0..h {
+                        let dc = component.block(unit_x * h + block_x, unit_y * v + block_y)[0];
+                        let diff = dc - predictions[i];
+                        predictions[i] = dc;
+                        let size = bit_size(diff);
+                        symbols.push(Symbol { table: component.table() as u8, value: size, bits: value_bits(diff, size), len: size });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn push_ac(symbols: &mut Vec<Symbol>, table: u8, run: usize, value: i32) {
+    let size = bit_size(value);
+    symbols.push(Symbol { table, value: ((run as u8) << 4) | size, bits: value_bits(value, size), len: size });
+}
+
+fn push_zero_runs(symbols: &mut Vec<Symbol>, table: u8, run: &mut usize) {
+    while *run > 15 {
+        symbols.push(Symbol { table, value: 0xf0, bits: 0, len: 0 });
+        *run -= 16;
+    }
+}
+
+fn encode_baseline_scan(components: &[&Component], mcus: (usize, usize), symbols: &mut Vec<Symbol>) {
+    let mut predictions = vec![0; components.len()];
+    let single = components.len() == 1;
+    let (units_x, units_y) = if single { (components[0].used_w, components[0].used_h) } else { mcus };
+
+    for unit_y in 0..units_y {
+        for unit_x in 0..units_x {
+            for (i, component) in components.iter().enumerate() {
+                let (h, v) = if single { (1, 1) } else { (component.h, component.v) };
+                let dc_table = component.table() as u8;
+                let ac_table = dc_table + 2;
+
+                for block_y in 0..v {
+                    for block_x in 0..h {
+                        let block = component.block(unit_x * h + block_x, unit_y * v + block_y);
+
+                        let diff = block[0] - predictions[i];
+                        predictions[i] = block[0];
+                        let size = bit_size(diff);
+                        symbols.push(Symbol { table: dc_table, value: size, bits: value_bits(diff, size), len: size });
+
+                        let mut run = 0;
+                        for &value in &block[1..] {
+                            if value == 0 {
+                                run += 1;
+                                continue;
+                            }
+                            push_zero_runs(symbols, ac_table, &mut run);
+                            push_ac(symbols, ac_table, run, value);
+                            run = 0;
+                        }
+                        if run > 0 {
+                            symbols.push(Symbol { table: ac_table, value: 0x00, bits: 0, len: 0 });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// First pass of a progressive AC band for a single component
+fn encode_ac_scan(component: &Component, start: usize, end: usize, symbols: &mut Vec<Symbol>) {
+    let table = component.table() as u8 + 2;
+    let mut eob_run: u32 = 0;
+
+    let flush_eob_run = |symbols: &mut Vec<Symbol>, eob_run: &mut u32| {
+        if *eob_run > 0 {
+            let size = (31 - eob_run.leading_zeros()) as u8;
+            symbols.push(Symbol { table, value: size << 4, bits: (*eob_run - (1 << size)) as u16, len: size });
+            *eob_run = 0;
+        }
+    };
+
+    for block_y in 0..component.used_h {
+        for block_x in 0..component.used_w {
+            let block = component.block(block_x, block_y);
+            let mut run = 0;
+            for &value in &block[start..=end] {
+                if value == 0 {
+                    run += 1;
+                    continue;
+                }
+                flush_eob_run(symbols, &mut eob_run);
+                push_zero_runs(symbols, table, &mut run);
+                push_ac(symbols, table, run, value);
+                run = 0;
+            }
+
+            if run > 0 {
+                eob_run += 1;
+                if eob_run == MAX_EOB_RUN {
+                    flush_eob_run(symbols, &mut eob_run);
+                }
+            }
+        }
+    }
+    flush_eob_run(symbols, &mut eob_run);
+}
+
+fn write_marker(out: &mut Vec<u8>, marker: u8, body: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+fn write_huffman_table(body: &mut Vec<u8>, class: u8, id: u8, table: &HuffmanTable) {
+    body.push((class << 4) | id);
+    body.extend_from_slice(&table.counts);
+    body.extend_from_slice(&table.symbols);
+}
+
+// Writes the huffman tables and entropy coded data for one scan
+fn write_scan(out: &mut Vec<u8>, scan_header: &[u8], symbols: &[Symbol], optimize: bool) {
+    let tables: Vec<Option<HuffmanTable>> = (0..4u8)
+        .map(|table| {
+            let mut freqs = [0u32; 256];
+            let mut used = false;
+            for symbol in symbols.iter().filter(|symbol| symbol.table == table) {
+                freqs[symbol.value as usize] += 1;
+                used = true;
+            }
+            if !used {
+                return None;
+            }
+            Some(if optimize {
+                HuffmanTable::from_frequencies(&freqs)
+            } else {
+                match table {
+                    0 => HuffmanTable::new(LUMA_DC_COUNTS, DC_SYMBOLS.to_vec()),
+                    1 => HuffmanTable::new(CHROMA_DC_COUNTS, DC_SYMBOLS.to_vec()),
+                    2 => HuffmanTable::new(LUMA_AC_COUNTS, LUMA_AC_SYMBOLS.to_vec()),
+                    _ => HuffmanTable::new(CHROMA_AC_COUNTS, CHROMA_AC_SYMBOLS.to_vec()),
+                }
+            })
+        })
+        .collect();
+
+    let mut body = Vec::new();
+    for (index, table) in tables.iter().enumerate() {
+        if let Some(table) = table {
+            write_huffman_table(&mut body, index as u8 / 2, index as u8 % 2, table);
+        }
+    }
+    write_marker(out, 0xc4, &body);
+    write_marker(out, 0xda, scan_header);
+
+    let mut writer = BitWriter { out: Vec::with_capacity(symbols.len()), bits: 0, count: 0 };
+    for symbol in symbols {
+        let table = tables[symbol.table as usize].as_ref().unwrap();
+        let (code, len) = table.codes[symbol.value as usize];
+        writer.write(code as u32, len as u32);
+        writer.write(symbol.bits as u32, symbol.len as u32);
+    }
+    writer.flush();
+    out.extend(writer.out);
+}
+
+fn scan_header(components: &[&Component], start: u8, end: u8) -> Vec<u8> {
+    let mut header = vec![components.len() as u8];
+    for component in components {
+        let table = component.table() as u8;
+        header.extend_from_slice(&[component.id, (table << 4) | table]);
+    }
+    header.extend_from_slice(&[start, end, 0]);
+    header
+}
+
+pub(crate) fn encode(image: DynamicImageRef<'_>, options: &JpegOptions) -> io::Result<Vec<u8>> {
+    // Baseline JPEG is 8-bit, grayscale is written as color
+    let rgb = image.with_rgb8();
+    let image = rgb.as_ref();
+    let channels = image.channels() as usize;
+    if image.width() == 0 || image.height() == 0 || image.width() > 65535 || image.height() > 65535 {
+        return Err(unsupported("JPEG dimensions must be between 1 and 65535"));
+    }
+
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let background = [options.background.0, options.background.1, options.background.2].map(|c| c as f32);
+
+    let mut planes = [vec![0f32; width * height], vec![0f32; width * height], vec![0f32; width * height]];
+    for (i, pixel) in image.as_bytes().chunks_exact(channels).enumerate() {
+        let alpha = if channels == 4 { pixel[3] as f32 / 255.0 } else { 1.0 };
+        let [r, g, b] = [0, 1, 2].map(|c| pixel[c] as f32 * alpha + background[c] * (1.0 - alpha));
+
+        planes[0][i] = 0.299 * r + 0.587 * g + 0.114 * b;
+        planes[1][i] = -0.168736 * r - 0.331264 * g + 0.5 * b + 128.0;
+        planes[2][i] = 0.5 * r - 0.418688 * g - 0.081312 * b + 128.0;
+    }
+
+    let (h_max, v_max) = match options.subsampling {
+        ChromaSubsampling::Yuv444 => (1, 1),
+        ChromaSubsampling::Yuv422 => (2, 1),
+        ChromaSubsampling::Yuv420 => (2, 2),
+    };
+    let mcus = (width.div_ceil(8 * h_max), height.div_ceil(8 * v_max));
+
+    let luma_quant = scaled_quant_table(&LUMA_QUANT, options.quality);
+    let chroma_quant = scaled_quant_table(&CHROMA_QUANT, options.quality);
+
+    let luma = build_component(
+        (&planes[0], width, height),
+        1,
+        h_max,
+        v_max,
+        (mcus.0, mcus.1, width.div_ceil(8), height.div_ceil(8)),
+        &luma_quant,
+    );
+    let chroma: Vec<Component> = [2u8, 3]
+        .iter()
+        .map(|&id| {
+            let (plane, plane_w, plane_h) = downsample(&planes[id as usize - 1], width, height, h_max, v_max);
+            build_component(
+                (&plane, plane_w, plane_h),
+                id,
+                1,
+                1,
+                (mcus.0, mcus.1, plane_w.div_ceil(8), plane_h.div_ceil(8)),
+                &chroma_quant,
+            )
+        })
+        .collect();
+    let components = [&luma, &chroma[0], &chroma[1]];
+
+    let mut out = vec![0xff, 0xd8];
+    write_marker(&mut out, 0xe0, b"JFIF\0\x01\x01\x00\x00\x01\x00\x01\x00\x00");
+
+    let mut quant_body = vec![0];
+    quant_body.extend(ZIGZAG.iter().map(|&natural| luma_quant[natural] as u8));
+    quant_body.push(1);
+    quant_body.extend(ZIGZAG.iter().map(|&natural| chroma_quant[natural] as u8));
+    write_marker(&mut out, 0xdb, &quant_body);
+
+    let mut frame = vec![8];
+    frame.extend_from_slice(&(height as u16).to_be_bytes());
+    frame.extend_from_slice(&(width as u16).to_be_bytes());
+    frame.push(3);
+    for component in components {
+        frame.extend_from_slice(&[component.id, ((component.h as u8) << 4) | component.v as u8, component.table() as u8]);
+    }
+    write_marker(&mut out, if options.progressive { 0xc2 } else { 0xc0 }, &frame);
+
+    if options.progressive {
+        // Progressive scans need EOB runs, which the standard tables can't code
+        let mut symbols = Vec::new();
+        encode_dc_scan(&components, mcus, &mut symbols);
+        write_scan(&mut out, &scan_header(&components, 0, 0), &symbols, true);
+
+        for (component, bands) in [(&luma, &[(1, 5), (6, 63)][..]), (&chroma[0], &[(1, 63)][..]), (&chroma[1], &[(1, 63)][..])] {
+            for &(start, end) in bands {
+                symbols.clear();
+                encode_ac_scan(component, start as usize, end as usize, &mut symbols);
+                write_scan(&mut out, &scan_header(&[component], start, end), &symbols, true);
+            }
+        }
+    } else {
+        let mut symbols = Vec::new();
+        encode_baseline_scan(&components, mcus, &mut symbols);
+        write_scan(&mut out, &scan_header(&components, 0, 63), &symbols, options.optimize_huffman);
+    }
+
+    out.extend_from_slice(&[0xff, 0xd9]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::jpeg::decode;
+    use crate::DynamicImage;
+
+    fn frame_header(bytes: &[u8]) -> &[u8] {
+        let start = bytes.windows(2).position(|marker| marker == [0xff, 0xc0] || marker == [0xff, 0xc2]).unwrap();
+        &bytes[start + 4..]
+    }
+
+    fn noise(width: u32, height: u32) -> DynamicImage {
+        let data = (0..width * height * 3).map(|i| ((i * 37) % 251) as u8).collect();
+        DynamicImage::from_raw_parts(3, width, height, data)
+    }
+
+    fn mean_error(a: &DynamicImage, b: &DynamicImage) -> f64 {
+        let total: u64 = a.as_bytes().iter().zip(b.as_bytes()).map(|(&a, &b)| a.abs_diff(b) as u64).sum();
+        total as f64 / a.as_bytes().len() as f64
+    }
+
+    #[test]
+    fn quality_scales_the_standard_tables() {
+        assert_eq!(scaled_quant_table(&LUMA_QUANT, 50), LUMA_QUANT);
+        assert_eq!(scaled_quant_table(&LUMA_QUANT, 100), [1; 64]);
+        assert_eq!(scaled_quant_table(&LUMA_QUANT, 1).iter().max(), Some(&255));
+
+        let bytes = encode(noise(8, 8).as_ref(), &JpegOptions { quality: 50, ..JpegOptions::default() }).unwrap();
+        let dqt = bytes.windows(2).position(|marker| marker == [0xff, 0xdb]).unwrap();
+        let zigzagged: Vec<u8> = ZIGZAG.iter().map(|&natural| LUMA_QUANT[natural] as u8).collect();
+        assert_eq!(bytes[dqt + 5..dqt + 69], zigzagged);
+    }
+
+    #[test]
+    fn subsampling_sets_the_luma_sampling_factors() {
+        let image = DynamicImage::from_raw_parts(3, 17, 9, [200, 40, 90].repeat(17 * 9));
+        for (subsampling, factors) in [(ChromaSubsampling::Yuv444, 0x11), (ChromaSubsampling::Yuv422, 0x21), (ChromaSubsampling::Yuv420, 0x22)] {
+            let bytes = encode(image.as_ref(), &JpegOptions { subsampling, ..JpegOptions::default() }).unwrap();
+            let frame = frame_header(&bytes);
+            assert_eq!(frame[5], 3);
+            assert_eq!([frame[7], frame[10], frame[13]], [factors, 0x11, 0x11]);
+
+            // A flat color survives any subsampling, partial MCUs included
+            let decoded = decode(&bytes).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (17, 9));
+            assert!(mean_error(&decoded, &image) < 2.0, "{subsampling:?}");
+        }
+    }
+
+    #[test]
+    fn higher_quality_costs_bytes_and_lowers_error() {
+        let image = noise(32, 32);
+        let encoded = |quality| encode(image.as_ref(), &JpegOptions { quality, ..JpegOptions::default() }).unwrap();
+        let (low, high) = (encoded(20), encoded(95));
+        assert!(low.len() < high.len());
+        assert!(mean_error(&decode(&high).unwrap(), &image) < mean_error(&decode(&low).unwrap(), &image));
+    }
+
+    #[test]
+    fn optimized_tables_only_change_the_size() {
+        let image = noise(32, 32);
+        let standard = encode(image.as_ref(), &JpegOptions::default()).unwrap();
+        let optimized = encode(image.as_ref(), &JpegOptions { optimize_huffman: true, ..JpegOptions::default() }).unwrap();
+        assert!(optimized.len() < standard.len());
+        assert_eq!(decode(&optimized).unwrap().as_bytes(), decode(&standard).unwrap().as_bytes());
+    }
+
+    #[test]
+    fn transparency_is_flattened_against_the_background() {
+        let image = DynamicImage::from_raw_parts(4, 8, 8, [255, 0, 0, 0].repeat(64));
+        let options = JpegOptions { background: (0, 0, 255), subsampling: ChromaSubsampling::Yuv444, ..JpegOptions::default() };
+        let decoded = decode(&encode(image.as_ref(), &options).unwrap()).unwrap();
+        let blue = DynamicImage::from_raw_parts(3, 8, 8, [0, 0, 255].repeat(64));
+        assert!(mean_error(&decoded, &blue) < 2.0);
+    }
+}
@@ -0,0 +1,412 @@
+use std::fs;
+use std::io;
+
+use super::{image_len, invalid_data, unsupported};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel, SampleDepth};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetpbmFormat {
+    // Black and white
+    Pbm,
+    Pgm,
+    Ppm,
+    // Arbitrary tuple types, keeps the alpha channel
+    Pam,
+}
+
+#[derive(Debug, Clone)]
+pub struct NetpbmOptions {
+    pub format: NetpbmFormat,
+    // Plain (ASCII) variant, ignored for PAM which only has a binary form
+    pub ascii: bool,
+}
+
+struct Tokenizer<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn skip_whitespace_and_comments(&mut self) {
+        while self.pos < self.bytes.len() {
+            match self.bytes[self.pos] {
+                b'#' => {
+                    while self.pos < self.bytes.len() && self.bytes[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                byte if byte.is_ascii_whitespace() => self.pos += 1,
+                _ => return,
+            }
+        }
+    }
+
+    fn token(&mut self) -> io::Result<&'a [u8]> {
+        self.skip_whitespace_and_comments();
+        let start = self.pos;
+        while self.pos < self.bytes.len() && !self.bytes[self.pos].is_ascii_whitespace() && self.bytes[self.pos] != b'#' {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(invalid_data("unexpected end of netpbm data"));
+        }
+        Ok(&self.bytes[start..self.pos])
+    }
+
+    fn number(&mut self) -> io::Result<u32> {
+        std::str::from_utf8(self.token()?)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| invalid_data("invalid number in netpbm data"))
+    }
+
+    // Plain PBM allows bits without separating whitespace
+    fn bit(&mut self) -> io::Result<u8> {
+        self.skip_whitespace_and_comments();
+        match self.bytes.get(self.pos) {
+            Some(&byte @ (b'0' | b'1')) => {
+                self.pos += 1;
+                Ok(byte - b'0')
+            }
+            _ => Err(invalid_data("invalid bit in plain PBM data")),
+        }
+    }
+
+    // Binary rasters start after exactly one whitespace byte
+    fn raster(&self) -> &'a [u8] {
+        &self.bytes[(self.pos + 1).min(self.bytes.len())..]
+    }
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: usize,
+    maxval: u32,
+}
+
+fn parse_pam_header(tokens: &mut Tokenizer) -> io::Result<Header> {
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+    let mut tuple_type = Vec::new();
+
+    loop {
+        let key = tokens.token()?;
+        match key {
+            b"WIDTH" => width = Some(tokens.number()? as usize),
+            b"HEIGHT" => height = Some(tokens.number()? as usize),
+            b"DEPTH" => depth = Some(tokens.number()? as usize),
+            b"MAXVAL" => maxval = Some(tokens.number()?),
+            b"TUPLTYPE" => tuple_type = tokens.token()?.to_vec(),
+            b"ENDHDR" => break,
+            _ => return Err(invalid_data("unknown PAM header field")),
+        }
+    }
+
+    let depth = depth.ok_or_else(|| invalid_data("missing PAM DEPTH"))?;
+    // The depth decides the channel layout, a tuple type only has to agree with it
+    let known = matches!(
+        (tuple_type.as_slice(), depth),
+        (b"BLACKANDWHITE" | b"GRAYSCALE", 1)
+            | (b"BLACKANDWHITE_ALPHA" | b"GRAYSCALE_ALPHA", 2)
+            | (b"RGB", 3)
+            | (b"RGB_ALPHA", 4)
+            | (b"", 1..=4)
+    );
+    if !known {
+        return Err(unsupported("unsupported PAM tuple type"));
+    }
+
+    Ok(Header {
+        width: width.ok_or_else(|| invalid_data("missing PAM WIDTH"))?,
+        height: height.ok_or_else(|| invalid_data("missing PAM HEIGHT"))?,
+        depth,
+        maxval: maxval.ok_or_else(|| invalid_data("missing PAM MAXVAL"))?,
+    })
+}
+
+// Rescales to 0..=255, or to 0..=65535 for a maxval past 255
+fn scale_sample(value: u32, maxval: u32) -> io::Result<u16> {
+    if value > maxval {
+        return Err(invalid_data("netpbm sample exceeds maxval"));
+    }
+    let max = if maxval > 255 { 65535 } else { 255 };
+    Ok(((value * max + maxval / 2) / maxval) as u16)
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> io::Result<DynamicImage> {
+    if bytes.len() < 2 || bytes[0] != b'P' {
+        return Err(invalid_data("not a netpbm file"));
+    }
+    let kind = bytes[1];
+    let mut tokens = Tokenizer { bytes, pos: 2 };
+
+    let header = match kind {
+        b'7' => parse_pam_header(&mut tokens)?,
+        b'1'..=b'6' => {
+            let width = tokens.number()? as usize;
+            let height = tokens.number()? as usize;
+            let maxval = if kind == b'1' || kind == b'4' { 1 } else { tokens.number()? };
+            let depth = if kind == b'3' || kind == b'6' { 3 } else { 1 };
+            Header { width, height, depth, maxval }
+        }
+        _ => return Err(invalid_data("unknown netpbm magic number")),
+    };
+
+    if header.maxval == 0 || header.maxval > 65535 {
+        return Err(invalid_data("netpbm maxval out of range"));
+    }
+
+    let sample_count = image_len(header.width, header.height, header.depth)?;
+    // Every sample takes up at least a bit of the file
+    if sample_count / 8 > bytes.len() {
+        return Err(invalid_data("not enough netpbm data"));
+    }
+    let mut samples: Vec<u16> = Vec::with_capacity(sample_count);
+
+    match kind {
+        b'1' => {
+            for _ in 0..sample_count {
+                // 1 is black in PBM
+                samples.push(if tokens.bit()? == 1 { 0 } else { 255 });
+            }
+        }
+        b'2' | b'3' => {
+            for _ in 0..sample_count {
+                samples.push(scale_sample(tokens.number()?, header.maxval)?);
+            }
+        }
+        b'4' => {
+            let raster = tokens.raster();
+            let row_len = header.width.div_ceil(8);
+            if raster.len() < row_len * header.height {
+                return Err(invalid_data("not enough PBM data"));
+            }
+            for row in raster.chunks_exact(row_len.max(1)).take(header.height) {
+                for x in 0..header.width {
+                    let bit = (row[x / 8] >> (7 - x % 8)) & 1;
+                    samples.push(if bit == 1 { 0 } else { 255 });
+                }
+            }
+        }
+        _ => {
+            let raster = tokens.raster();
+            let sample_len = if header.maxval > 255 { 2 } else { 1 };
+            if raster.len() < sample_count * sample_len {
+                return Err(invalid_data("not enough netpbm data"));
+            }
+            for sample in raster.chunks_exact(sample_len).take(sample_count) {
+                let value = if sample_len == 2 { u16::from_be_bytes([sample[0], sample[1]]) as u32 } else { sample[0] as u32 };
+                samples.push(scale_sample(value, header.maxval)?);
+            }
+        }
+    }
+
+    let (channels, width, height) = (header.depth as u8, header.width as u32, header.height as u32);
+    if header.maxval > 255 {
+        return Ok(DynamicImage::from_raw_parts16(channels, width, height, samples));
+    }
+    Ok(DynamicImage::from_raw_parts(channels, width, height, samples.into_iter().map(|sample| sample as u8).collect()))
+}
+
+fn write_ascii_rows<T: ToString>(out: &mut Vec<u8>, values: &[T], width: usize) {
+    for row in values.chunks(width.max(1)) {
+        let line: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+        out.extend_from_slice(line.join(" ").as_bytes());
+        out.push(b'\n');
+    }
+}
+
+// Binary samples take two big-endian bytes when maxval is past 255
+fn write_binary_samples(out: &mut Vec<u8>, values: &[u16], maxval: u16) {
+    if maxval > 255 {
+        out.extend(values.iter().flat_map(|value| value.to_be_bytes()));
+    } else {
+        out.extend(values.iter().map(|&value| value as u8));
+    }
+}
+
+pub(crate) fn encode(image: DynamicImageRef<'_>, options: &NetpbmOptions) -> io::Result<Vec<u8>> {
+    // 8-bit images are written with maxval 255, deeper ones with 65535
+    let sixteen = image.depth() != SampleDepth::U8 && options.format != NetpbmFormat::Pbm;
+    let maxval: u16 = if sixteen { 65535 } else { 255 };
+    let samples: Vec<u16> = if sixteen {
+        image.with_depth(SampleDepth::U16).as_ref().as_samples16().to_vec()
+    } else {
+        image.with_depth(SampleDepth::U8).as_ref().as_bytes().iter().map(|&sample| sample as u16).collect()
+    };
+    let channels = image.channels() as usize;
+
+    let width = image.width() as usize;
+    let pixels = samples.chunks_exact(channels);
+    let gray = || {
+        pixels.clone().map(move |p| if channels < 3 { p[0] } else { ((p[0] as u32 + p[1] as u32 + p[2] as u32) / 3) as u16 })
+    };
+
+    let mut out = Vec::new();
+    match (options.format, options.ascii) {
+        (NetpbmFormat::Pbm, ascii) => {
+            let bits: Vec<u8> = gray().map(|g| (g < 128) as u8).collect();
+            if ascii {
+                out.extend_from_slice(format!("P1\n{} {}\n", image.width(), image.height()).as_bytes());
+                write_ascii_rows(&mut out, &bits, width);
+            } else {
+                out.extend_from_slice(format!("P4\n{} {}\n", image.width(), image.height()).as_bytes());
+                for row in bits.chunks(width.max(1)) {
+                    for byte_bits in row.chunks(8) {
+                        let byte = byte_bits.iter().enumerate().fold(0u8, |acc, (i, &bit)| acc | (bit << (7 - i)));
+                        out.push(byte);
+                    }
+                }
+            }
+        }
+        (NetpbmFormat::Pgm, ascii) => {
+            let values: Vec<u16> = gray().collect();
+            out.extend_from_slice(format!("P{}\n{} {}\n{maxval}\n", if ascii { 2 } else { 5 }, image.width(), image.height()).as_bytes());
+            if ascii {
+                write_ascii_rows(&mut out, &values, width);
+            } else {
+                write_binary_samples(&mut out, &values, maxval);
+            }
+        }
+        (NetpbmFormat::Ppm, ascii) => {
+            let values: Vec<u16> = pixels.flat_map(|p| if channels < 3 { [p[0]; 3] } else { [p[0], p[1], p[2]] }).collect();
+            out.extend_from_slice(format!("P{}\n{} {}\n{maxval}\n", if ascii { 3 } else { 6 }, image.width(), image.height()).as_bytes());
+            if ascii {
+                write_ascii_rows(&mut out, &values, width * 3);
+            } else {
+                write_binary_samples(&mut out, &values, maxval);
+            }
+        }
+        (NetpbmFormat::Pam, _) => {
+            let tuple_type = ["GRAYSCALE", "GRAYSCALE_ALPHA", "RGB", "RGB_ALPHA"][channels - 1];
+            out.extend_from_slice(
+                format!(
+                    "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+                    image.width(), image.height(), channels, maxval, tuple_type
+                )
+                .as_bytes(),
+            );
+            write_binary_samples(&mut out, &samples, maxval);
+        }
+    }
+
+    Ok(out)
+}
+
+impl DynamicImage {
+    pub fn open_netpbm(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode(&fs::read(path)?)?)
+    }
+
+    // PAM for images with alpha, binary PPM otherwise
+    pub fn save_netpbm(&self, path: &str) -> Result<(), ImageError> {
+        let format = if self.channels() == 4 { NetpbmFormat::Pam } else { NetpbmFormat::Ppm };
+        self.save_netpbm_with_options(path, &NetpbmOptions { format, ascii: false })
+    }
+
+    pub fn save_netpbm_with_options(&self, path: &str, options: &NetpbmOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_ref(), options)?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn open_netpbm(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_netpbm(path)?.into_image())
+    }
+
+    pub fn save_netpbm(&self, path: &str) -> Result<(), ImageError> {
+        let format = if self.channels() == 4 { NetpbmFormat::Pam } else { NetpbmFormat::Ppm };
+        self.save_netpbm_with_options(path, &NetpbmOptions { format, ascii: false })
+    }
+
+    pub fn save_netpbm_with_options(&self, path: &str, options: &NetpbmOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_dynamic().as_ref(), options)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_with(image: &DynamicImage, format: NetpbmFormat, ascii: bool) -> Vec<u8> {
+        encode(image.as_ref(), &NetpbmOptions { format, ascii }).unwrap()
+    }
+
+    #[test]
+    fn plain_headers_allow_comments_anywhere() {
+        let image = decode(b"P2\n# made by hand\n3 # width\n1\n10\n0 5\n10\n").unwrap();
+        assert_eq!(image.as_bytes(), [0, 128, 255]);
+
+        let image = decode(b"P3 1 1 255 # one pixel\n 7 8 9").unwrap();
+        assert_eq!((image.channels(), image.as_bytes()), (3, &[7, 8, 9][..]));
+    }
+
+    #[test]
+    fn plain_pbm_bits_need_no_separators_and_one_is_black() {
+        let image = decode(b"P1\n3 2\n101\n0 1 0\n").unwrap();
+        assert_eq!(image.as_bytes(), [0, 255, 0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn binary_pbm_rows_are_padded_to_whole_bytes() {
+        let mut samples = vec![255; 20];
+        samples[0] = 0;
+        samples[9] = 0;
+        samples[10] = 0;
+        let image = DynamicImage::from_raw_parts(1, 10, 2, samples);
+
+        let bytes = encode_with(&image, NetpbmFormat::Pbm, false);
+        assert_eq!(bytes, b"P4\n10 2\n\x80\x40\x80\x00");
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), image.as_bytes());
+    }
+
+    #[test]
+    fn gray_formats_average_color_pixels() {
+        let image = DynamicImage::from_raw_parts(3, 2, 1, vec![30, 60, 90, 255, 255, 0]);
+        assert_eq!(encode_with(&image, NetpbmFormat::Pgm, true), b"P2\n2 1\n255\n60 170\n");
+        assert_eq!(encode_with(&image, NetpbmFormat::Pgm, false), b"P5\n2 1\n255\n\x3c\xaa");
+        assert_eq!(encode_with(&image, NetpbmFormat::Ppm, true), b"P3\n2 1\n255\n30 60 90 255 255 0\n");
+    }
+
+    #[test]
+    fn pam_tuple_type_has_to_match_the_depth() {
+        let image = DynamicImage::from_raw_parts(2, 1, 1, vec![40, 200]);
+        let bytes = encode_with(&image, NetpbmFormat::Pam, false);
+        assert_eq!(bytes, b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\x28\xc8");
+        assert_eq!(decode(&bytes).unwrap().as_bytes(), [40, 200]);
+
+        let image = decode(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 1\nTUPLTYPE BLACKANDWHITE_ALPHA\nENDHDR\n\x01\x00").unwrap();
+        assert_eq!(image.as_bytes(), [255, 0]);
+
+        let mismatched = decode(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nTUPLTYPE RGB\nENDHDR\n\x00");
+        assert_eq!(mismatched.unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn samples_past_maxval_are_rejected() {
+        assert_eq!(decode(b"P2\n1 1\n10\n11\n").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decode(b"P5\n1 1\n10\n\x0b").unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn wide_maxval_scales_to_16_bits() {
+        let image = decode(b"P5\n2 1\n1000\n\x00\x00\x03\xe8").unwrap();
+        assert_eq!(image.as_ref().as_samples16(), [0, 65535]);
+    }
+
+    #[test]
+    fn deep_images_are_written_with_maxval_65535() {
+        let image = DynamicImage::from_raw_parts16(3, 1, 1, vec![0x0102, 0, 0xffff]);
+        let bytes = encode_with(&image, NetpbmFormat::Ppm, false);
+        assert_eq!(bytes, b"P6\n1 1\n65535\n\x01\x02\x00\x00\xff\xff");
+        assert_eq!(decode(&bytes).unwrap().as_ref().as_samples16(), [0x0102, 0, 0xffff]);
+        assert_eq!(encode_with(&image, NetpbmFormat::Ppm, true), b"P3\n1 1\n65535\n258 0 65535\n");
+    }
+
+    #[test]
+    fn zero_width_pbm_decodes_empty() {
+        let image = decode(b"P4\n0 1\n\0").unwrap();
+        assert_eq!((image.width(), image.height()), (0, 1));
+        assert!(image.as_bytes().is_empty());
+    }
+}
@@ -0,0 +1,647 @@
+use std::fs;
+use std::io;
+
+use super::zlib::{crc32, crc32_update, zlib_compress, zlib_decompress};
+use super::{image_len, invalid_data, unsupported};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel, SampleDepth};
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+const IDAT_CHUNK_SIZE: usize = 1 << 16;
+
+// (x offset, y offset, x step, y step) of each Adam7 pass
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+    // Picks the filter with the smallest sum of absolute differences per row
+    Adaptive,
+}
+
+#[derive(Debug, Clone)]
+pub struct PngOptions {
+    // 0 (no compression) to 9 (smallest output)
+    pub compression: u8,
+    pub filter: PngFilter,
+    pub interlaced: bool,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        PngOptions {
+            compression: 6,
+            filter: PngFilter::Adaptive,
+            interlaced: false,
+        }
+    }
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> io::Result<Header> {
+        if data.len() != 13 {
+            return Err(invalid_data("invalid IHDR length"));
+        }
+
+        let header = Header {
+            width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize,
+            height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize,
+            bit_depth: data[8],
+            color_type: data[9],
+            interlaced: match data[12] {
+                0 => false,
+                1 => true,
+                _ => return Err(invalid_data("unknown interlace method")),
+            },
+        };
+
+        if header.width == 0 || header.height == 0 {
+            return Err(invalid_data("image has no pixels"));
+        }
+        if data[10] != 0 || data[11] != 0 {
+            return Err(invalid_data("unknown compression or filter method"));
+        }
+
+        let valid_depths: &[u8] = match header.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err(invalid_data("unknown color type")),
+        };
+        if !valid_depths.contains(&header.bit_depth) {
+            return Err(invalid_data("invalid bit depth for color type"));
+        }
+
+        Ok(header)
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.samples_per_pixel() * self.bit_depth as usize).div_ceil(8)
+    }
+
+    fn passes(&self) -> Vec<(usize, usize, usize, usize)> {
+        if self.interlaced {
+            ADAM7_PASSES.to_vec()
+        } else {
+            vec![(0, 0, 1, 1)]
+        }
+    }
+
+    // Decompressed stream length, each row of each pass starts with a filter byte
+    fn raw_len(&self) -> usize {
+        self.passes()
+            .into_iter()
+            .filter(|&(x0, y0, _, _)| x0 < self.width && y0 < self.height)
+            .map(|(x0, y0, dx, dy)| (1 + self.row_bytes((self.width - x0).div_ceil(dx))) * (self.height - y0).div_ceil(dy))
+            .sum()
+    }
+
+    // Filters operate on whole bytes, so sub-byte depths use a distance of 1
+    fn filter_distance(&self) -> usize {
+        (self.samples_per_pixel() * self.bit_depth as usize).div_ceil(8)
+    }
+}
+
+fn read_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        8 => row[index] as u16,
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            (row[bit / 8] >> shift) as u16 & ((1 << bit_depth) - 1)
+        }
+    }
+}
+
+// 16-bit samples are kept as they are, lower depths are scaled up to 8 bits
+fn scale_sample(value: u16, bit_depth: u8) -> u16 {
+    match bit_depth {
+        8 | 16 => value,
+        _ => value * 255 / ((1 << bit_depth) - 1),
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], distance: usize) -> io::Result<()> {
+    match filter {
+        0 => {}
+        1 => {
+            for i in distance..row.len() {
+                row[i] = row[i].wrapping_add(row[i - distance]);
+            }
+        }
+        2 => {
+            for i in 0..row.len() {
+                row[i] = row[i].wrapping_add(previous[i]);
+            }
+        }
+        3 => {
+            for i in 0..row.len() {
+                let left = if i >= distance { row[i - distance] } else { 0 };
+                row[i] = row[i].wrapping_add(((left as u16 + previous[i] as u16) / 2) as u8);
+            }
+        }
+        4 => {
+            for i in 0..row.len() {
+                let (left, upper_left) = if i >= distance {
+                    (row[i - distance], previous[i - distance])
+                } else {
+                    (0, 0)
+                };
+                row[i] = row[i].wrapping_add(paeth(left, previous[i], upper_left));
+            }
+        }
+        _ => return Err(invalid_data("unknown filter type")),
+    }
+    Ok(())
+}
+
+fn filter_row(filter: u8, row: &[u8], previous: &[u8], distance: usize, out: &mut Vec<u8>) {
+    out.push(filter);
+    for i in 0..row.len() {
+        let left = if i >= distance { row[i - distance] } else { 0 };
+        let upper_left = if i >= distance { previous[i - distance] } else { 0 };
+
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => previous[i],
+            3 => ((left as u16 + previous[i] as u16) / 2) as u8,
+            _ => paeth(left, previous[i], upper_left),
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+fn read_chunks(bytes: &[u8]) -> io::Result<Vec<([u8; 4], &[u8])>> {
+    if bytes.len() < 8 || bytes[..8] != SIGNATURE {
+        return Err(invalid_data("not a PNG file"));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    loop {
+        if pos + 8 > bytes.len() {
+            return Err(invalid_data("unexpected end of PNG file"));
+        }
+        let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let kind = [bytes[pos + 4], bytes[pos + 5], bytes[pos + 6], bytes[pos + 7]];
+
+        if pos + 12 + len > bytes.len() {
+            return Err(invalid_data("unexpected end of PNG file"));
+        }
+        let data = &bytes[pos + 8..pos + 8 + len];
+        let crc_pos = pos + 8 + len;
+        let expected = u32::from_be_bytes([bytes[crc_pos], bytes[crc_pos + 1], bytes[crc_pos + 2], bytes[crc_pos + 3]]);
+        if crc32(&bytes[pos + 4..crc_pos]) != expected {
+            return Err(invalid_data("PNG chunk checksum mismatch"));
+        }
+
+        chunks.push((kind, data));
+        pos = crc_pos + 4;
+
+        if &kind == b"IEND" {
+            return Ok(chunks);
+        }
+    }
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> io::Result<DynamicImage> {
+    let chunks = read_chunks(bytes)?;
+
+    let (first_kind, first_data) = chunks[0];
+    if &first_kind != b"IHDR" {
+        return Err(invalid_data("PNG does not start with IHDR"));
+    }
+    let header = Header::parse(first_data)?;
+
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut transparency: Option<&[u8]> = None;
+    let mut compressed = Vec::new();
+
+    for &(kind, data) in &chunks[1..] {
+        match &kind {
+            b"PLTE" => {
+                if data.len() % 3 != 0 || data.len() > 256 * 3 {
+                    return Err(invalid_data("invalid PLTE length"));
+                }
+                palette = data.chunks_exact(3).map(|c| [c[0], c[1], c[2], 255]).collect();
+            }
+            b"tRNS" => transparency = Some(data),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => {}
+            _ => {
+                // Unknown critical chunks (uppercase first letter) can't be ignored safely
+                if kind[0].is_ascii_uppercase() {
+                    return Err(unsupported("unknown critical PNG chunk"));
+                }
+            }
+        }
+    }
+
+    if header.color_type == 3 {
+        if palette.is_empty() {
+            return Err(invalid_data("missing PLTE for indexed image"));
+        }
+        if let Some(alphas) = transparency {
+            for (entry, &alpha) in palette.iter_mut().zip(alphas) {
+                entry[3] = alpha;
+            }
+        }
+        // Out of range indices decode as opaque black
+        palette.resize(256, [0, 0, 0, 255]);
+    }
+
+    // Color key for grayscale and truecolor images
+    let color_key: Option<Vec<u16>> = match (header.color_type, transparency) {
+        (0, Some(t)) if t.len() >= 2 => Some(vec![u16::from_be_bytes([t[0], t[1]])]),
+        (2, Some(t)) if t.len() >= 6 => Some(
+            t[..6].chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect(),
+        ),
+        _ => None,
+    };
+
+    let has_alpha = matches!(header.color_type, 4 | 6)
+        || color_key.is_some()
+        || (header.color_type == 3 && transparency.is_some());
+    let gray = matches!(header.color_type, 0 | 4);
+    let channels: usize = match (gray, has_alpha) {
+        (true, false) => 1,
+        (true, true) => 2,
+        (false, false) => 3,
+        (false, true) => 4,
+    };
+
+    let pixel_len = image_len(header.width, header.height, channels)?;
+    let raw_len = header.raw_len();
+    // Deflate expands data by at most 1032:1, anything claiming more is cut short
+    if raw_len > compressed.len().saturating_mul(1032) {
+        return Err(invalid_data("not enough image data"));
+    }
+
+    let raw = zlib_decompress(&compressed, raw_len)?;
+
+    // Palette entries are 8-bit, so only indexed images can't be 16-bit
+    let sixteen = header.bit_depth == 16;
+    let opaque = if sixteen { u16::MAX } else { 255 };
+
+    let mut data = vec![0u16; pixel_len];
+
+    let bit_depth = header.bit_depth;
+    let samples = header.samples_per_pixel();
+    let distance = header.filter_distance();
+
+    let mut pos = 0;
+    for (x0, y0, dx, dy) in header.passes() {
+        if x0 >= header.width || y0 >= header.height {
+            continue;
+        }
+        let pass_width = (header.width - x0).div_ceil(dx);
+        let pass_height = (header.height - y0).div_ceil(dy);
+        let row_len = header.row_bytes(pass_width);
+
+        let mut previous = vec![0u8; row_len];
+        for pass_y in 0..pass_height {
+            if pos + 1 + row_len > raw.len() {
+                return Err(invalid_data("not enough image data"));
+            }
+            let filter = raw[pos];
+            let mut row = raw[pos + 1..pos + 1 + row_len].to_vec();
+            unfilter(filter, &mut row, &previous, distance)?;
+            pos += 1 + row_len;
+
+            let y = y0 + pass_y * dy;
+            for pass_x in 0..pass_width {
+                let sample = |i: usize| read_sample(&row, pass_x * samples + i, bit_depth);
+
+                let rgba = match header.color_type {
+                    0 => {
+                        let gray = scale_sample(sample(0), bit_depth);
+                        let alpha = if color_key.as_deref() == Some(&[sample(0)]) { 0 } else { opaque };
+                        [gray, gray, gray, alpha]
+                    }
+                    2 => {
+                        let values = [sample(0), sample(1), sample(2)];
+                        let alpha = if color_key.as_deref() == Some(&values) { 0 } else { opaque };
+                        [
+                            scale_sample(values[0], bit_depth),
+                            scale_sample(values[1], bit_depth),
+                            scale_sample(values[2], bit_depth),
+                            alpha,
+                        ]
+                    }
+                    3 => palette[sample(0) as usize].map(u16::from),
+                    4 => {
+                        let gray = scale_sample(sample(0), bit_depth);
+                        [gray, gray, gray, scale_sample(sample(1), bit_depth)]
+                    }
+                    _ => [
+                        scale_sample(sample(0), bit_depth),
+                        scale_sample(sample(1), bit_depth),
+                        scale_sample(sample(2), bit_depth),
+                        scale_sample(sample(3), bit_depth),
+                    ],
+                };
+
+                let x = x0 + pass_x * dx;
+                let index = (y * header.width + x) * channels;
+                if gray {
+                    data[index..index + channels].copy_from_slice(&[rgba[0], rgba[3]][..channels]);
+                } else {
+                    data[index..index + channels].copy_from_slice(&rgba[..channels]);
+                }
+            }
+
+            previous = row;
+        }
+    }
+
+    let (width, height) = (header.width as u32, header.height as u32);
+    if sixteen {
+        Ok(DynamicImage::from_raw_parts16(channels as u8, width, height, data))
+    } else {
+        let data = data.into_iter().map(|sample| sample as u8).collect();
+        Ok(DynamicImage::from_raw_parts(channels as u8, width, height, data))
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc32_update(crc32(kind), data).to_be_bytes());
+}
+
+fn filter_rows(rows: &[Vec<u8>], distance: usize, filter: PngFilter, out: &mut Vec<u8>) {
+    let mut previous = vec![0u8; rows.first().map_or(0, |row| row.len())];
+    let mut candidate = Vec::new();
+
+    for row in rows {
+        let filter_type = match filter {
+            PngFilter::None => 0,
+            PngFilter::Sub => 1,
+            PngFilter::Up => 2,
+            PngFilter::Average => 3,
+            PngFilter::Paeth => 4,
+            PngFilter::Adaptive => {
+                // Smaller signed residuals tend to compress better
+                (0..5)
+                    .min_by_key(|&filter_type| {
+                        candidate.clear();
+                        filter_row(filter_type, row, &previous, distance, &mut candidate);
+                        candidate[1..].iter().map(|&b| (b as i8).unsigned_abs() as u64).sum::<u64>()
+                    })
+                    .unwrap()
+            }
+        };
+
+        filter_row(filter_type, row, &previous, distance, out);
+        previous.clone_from(row);
+    }
+}
+
+pub(crate) fn encode(image: DynamicImageRef<'_>, options: &PngOptions) -> io::Result<Vec<u8>> {
+    let color_type = match image.channels() {
+        1 => 0,
+        2 => 4,
+        3 => 2,
+        _ => 6,
+    };
+
+    // 16-bit and float images are written with 16-bit samples
+    let converted = match image.depth() {
+        SampleDepth::U8 => image.with_depth(SampleDepth::U8),
+        _ => image.with_depth(SampleDepth::U16),
+    };
+    let image = converted.as_ref();
+    let bit_depth: u8 = if image.depth() == SampleDepth::U16 { 16 } else { 8 };
+    let sample_bytes = bit_depth as usize / 8;
+
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let channels = image.channels() as usize;
+
+    let passes: Vec<(usize, usize, usize, usize)> = if options.interlaced {
+        ADAM7_PASSES.to_vec()
+    } else {
+        vec![(0, 0, 1, 1)]
+    };
+
+    let mut raw = Vec::with_capacity((width * channels * sample_bytes + 1) * height);
+    for (x0, y0, dx, dy) in passes {
+        if x0 >= width || y0 >= height {
+            continue;
+        }
+
+        let rows: Vec<Vec<u8>> = (y0..height)
+            .step_by(dy)
+            .map(|y| {
+                let mut row = Vec::with_capacity(width.div_ceil(dx) * channels * sample_bytes);
+                for x in (x0..width).step_by(dx) {
+                    let index = (y * width + x) * channels;
+                    if sample_bytes == 2 {
+                        for sample in &image.as_samples16()[index..index + channels] {
+                            row.extend_from_slice(&sample.to_be_bytes());
+                        }
+                    } else {
+                        row.extend_from_slice(&image.as_bytes()[index..index + channels]);
+                    }
+                }
+                row
+            })
+            .collect();
+
+        filter_rows(&rows, channels * sample_bytes, options.filter, &mut raw);
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&image.width().to_be_bytes());
+    ihdr.extend_from_slice(&image.height().to_be_bytes());
+    ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, options.interlaced as u8]);
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    for chunk in zlib_compress(&raw, options.compression).chunks(IDAT_CHUNK_SIZE) {
+        write_chunk(&mut out, b"IDAT", chunk);
+    }
+    write_chunk(&mut out, b"IEND", &[]);
+
+    Ok(out)
+}
+
+impl DynamicImage {
+    pub fn open_png(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode(&fs::read(path)?)?)
+    }
+
+    pub fn save_png(&self, path: &str) -> Result<(), ImageError> {
+        self.save_png_with_options(path, &PngOptions::default())
+    }
+
+    pub fn save_png_with_options(&self, path: &str, options: &PngOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_ref(), options)?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn open_png(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_png(path)?.into_image())
+    }
+
+    pub fn save_png(&self, path: &str) -> Result<(), ImageError> {
+        self.save_png_with_options(path, &PngOptions::default())
+    }
+
+    pub fn save_png_with_options(&self, path: &str, options: &PngOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_dynamic().as_ref(), options)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = out.len();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        let crc = crc32(&out[start..]);
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    // `raw` holds the filtered rows, `chunks` go between IHDR and IDAT
+    fn png(width: u32, height: u32, [bit_depth, color_type]: [u8; 2], interlaced: bool, chunks: &[(&[u8; 4], &[u8])], raw: &[u8]) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, interlaced as u8]);
+
+        let mut out = SIGNATURE.to_vec();
+        chunk(&mut out, b"IHDR", &ihdr);
+        for (kind, data) in chunks {
+            chunk(&mut out, kind, data);
+        }
+        chunk(&mut out, b"IDAT", &zlib_compress(raw, 6));
+        chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    #[test]
+    fn palette_with_trns_decodes_to_rgba() {
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        // 2-bit indices 0, 1, 2 packed from the high bits
+        let bytes = png(3, 1, [2, 3], false, &[(b"PLTE", &palette), (b"tRNS", &[0, 128])], &[0, 0b0001_1000]);
+        let image = decode(&bytes).unwrap();
+        assert_eq!(image.channels(), 4);
+        assert_eq!(image.as_bytes(), [255, 0, 0, 0, 0, 255, 0, 128, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn low_depth_gray_scales_and_honours_the_color_key() {
+        // 4-bit samples 0, 5 and 15, with 5 marked transparent
+        let bytes = png(3, 1, [4, 0], false, &[(b"tRNS", &[0, 5])], &[0, 0x05, 0xf0]);
+        let image = decode(&bytes).unwrap();
+        assert_eq!(image.channels(), 2);
+        assert_eq!(image.as_bytes(), [0, 255, 85, 0, 255, 255]);
+    }
+
+    #[test]
+    fn sixteen_bit_samples_are_big_endian() {
+        let bytes = png(2, 1, [16, 0], false, &[], &[0, 0x12, 0x34, 0xab, 0xcd]);
+        let image = decode(&bytes).unwrap();
+        assert_eq!(image.depth(), SampleDepth::U16);
+        assert_eq!(image.as_ref().as_samples16(), [0x1234, 0xabcd]);
+    }
+
+    #[test]
+    fn every_filter_type_reconstructs_the_row() {
+        // The second row is [15, 25, 40] under each filter, above [10, 20, 30]
+        let filtered: [(u8, [u8; 3]); 5] = [(0, [15, 25, 40]), (1, [15, 10, 15]), (2, [5, 5, 10]), (3, [10, 8, 13]), (4, [5, 5, 10])];
+        for (filter, row) in filtered {
+            let raw = [&[0, 10, 20, 30][..], &[filter], &row].concat();
+            let image = decode(&png(3, 2, [8, 0], false, &[], &raw)).unwrap();
+            assert_eq!(image.as_bytes(), [10, 20, 30, 15, 25, 40], "filter {filter}");
+        }
+    }
+
+    #[test]
+    fn adam7_passes_land_on_their_pixels() {
+        // A 3x3 image only has pixels in passes 1, 4, 5, 6 and 7
+        let raw = [0, 0, 0, 2, 0, 6, 8, 0, 1, 0, 7, 0, 3, 4, 5];
+        let image = decode(&png(3, 3, [8, 0], true, &[], &raw)).unwrap();
+        assert_eq!(image.as_bytes(), [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let options = PngOptions { interlaced: true, filter: PngFilter::None, compression: 0 };
+        let encoded = encode(image.as_ref(), &options).unwrap();
+        assert_eq!(decode(&encoded).unwrap().as_bytes(), image.as_bytes());
+        assert!(encoded.windows(raw.len()).any(|window| window == raw));
+    }
+
+    #[test]
+    fn encoder_writes_the_color_type_of_each_layout() {
+        let cases = [(1, 0), (2, 4), (3, 2), (4, 6)];
+        for (channels, color_type) in cases {
+            let data: Vec<u16> = (0..5 * 3 * channels as u16).map(|i| i.wrapping_mul(4099)).collect();
+            let image = DynamicImage::from_raw_parts16(channels, 5, 3, data);
+            let encoded = encode(image.as_ref(), &PngOptions::default()).unwrap();
+            assert_eq!(encoded[24..26], [16, color_type]);
+            assert_eq!(decode(&encoded).unwrap().as_ref().as_samples16(), image.as_ref().as_samples16());
+        }
+    }
+
+    #[test]
+    fn huge_header_is_rejected_before_allocating() {
+        let bytes = png(1 << 31, 1 << 31, [16, 6], false, &[], &[0; 16]);
+        assert_eq!(decode(&bytes).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn header_larger_than_payload_is_rejected() {
+        let bytes = png(20000, 20000, [16, 6], false, &[], &[0; 16]);
+        assert_eq!(decode(&bytes).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
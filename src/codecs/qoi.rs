@@ -0,0 +1,241 @@
+use std::fs;
+use std::io;
+
+use super::{image_len, invalid_data};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel};
+
+const MAGIC: [u8; 4] = *b"qoif";
+const HEADER_LEN: usize = 14;
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const MASK: u8 = 0xc0;
+
+fn index_position(pixel: [u8; 4]) -> usize {
+    let [r, g, b, a] = pixel.map(|value| value as usize);
+    (r * 3 + g * 5 + b * 7 + a * 11) % 64
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> io::Result<DynamicImage> {
+    if bytes.len() < HEADER_LEN || bytes[..4] != MAGIC {
+        return Err(invalid_data("not a QOI file"));
+    }
+
+    let width = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let height = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    let channels = bytes[12];
+    if channels != 3 && channels != 4 {
+        return Err(invalid_data("invalid QOI channel count"));
+    }
+    if bytes[13] > 1 {
+        return Err(invalid_data("invalid QOI color space"));
+    }
+
+    let pixel_count = image_len(width as usize, height as usize, 1)?;
+    // A single run byte covers at most 62 pixels
+    if pixel_count / 62 > bytes.len() - HEADER_LEN {
+        return Err(invalid_data("not enough QOI data"));
+    }
+
+    let mut data = Vec::with_capacity(pixel_count * channels as usize);
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0, 0, 0, 255];
+    let mut pos = HEADER_LEN;
+    let mut run = 0;
+
+    let next = |pos: &mut usize| -> io::Result<u8> {
+        let byte = *bytes.get(*pos).ok_or_else(|| invalid_data("unexpected end of QOI data"))?;
+        *pos += 1;
+        Ok(byte)
+    };
+
+    for _ in 0..pixel_count {
+        if run > 0 {
+            run -= 1;
+        } else {
+            let op = next(&mut pos)?;
+            match op {
+                OP_RGB => {
+                    pixel[0] = next(&mut pos)?;
+                    pixel[1] = next(&mut pos)?;
+                    pixel[2] = next(&mut pos)?;
+                }
+                OP_RGBA => {
+                    pixel[0] = next(&mut pos)?;
+                    pixel[1] = next(&mut pos)?;
+                    pixel[2] = next(&mut pos)?;
+                    pixel[3] = next(&mut pos)?;
+                }
+                _ => match op & MASK {
+                    OP_INDEX => pixel = index[op as usize],
+                    OP_DIFF => {
+                        pixel[0] = pixel[0].wrapping_add((op >> 4) & 3).wrapping_sub(2);
+                        pixel[1] = pixel[1].wrapping_add((op >> 2) & 3).wrapping_sub(2);
+                        pixel[2] = pixel[2].wrapping_add(op & 3).wrapping_sub(2);
+                    }
+                    OP_LUMA => {
+                        let second = next(&mut pos)?;
+                        let dg = (op & 0x3f).wrapping_sub(32);
+                        pixel[0] = pixel[0].wrapping_add(dg).wrapping_add(second >> 4).wrapping_sub(8);
+                        pixel[1] = pixel[1].wrapping_add(dg);
+                        pixel[2] = pixel[2].wrapping_add(dg).wrapping_add(second & 0x0f).wrapping_sub(8);
+                    }
+                    _ => run = op & 0x3f,
+                },
+            }
+            index[index_position(pixel)] = pixel;
+        }
+        data.extend_from_slice(&pixel[..channels as usize]);
+    }
+
+    Ok(DynamicImage::from_raw_parts(channels, width, height, data))
+}
+
+pub(crate) fn encode(image: DynamicImageRef<'_>) -> io::Result<Vec<u8>> {
+    let rgb = image.with_rgb8();
+    let image = rgb.as_ref();
+    let channels = image.channels() as usize;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + image.as_bytes().len() / 2 + END_MARKER.len());
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&image.width().to_be_bytes());
+    out.extend_from_slice(&image.height().to_be_bytes());
+    // sRGB with linear alpha
+    out.extend_from_slice(&[image.channels(), 0]);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut previous = [0, 0, 0, 255];
+    let mut run = 0u8;
+
+    for chunk in image.as_bytes().chunks_exact(channels) {
+        let pixel = [chunk[0], chunk[1], chunk[2], if channels == 4 { chunk[3] } else { 255 }];
+
+        if pixel == previous {
+            run += 1;
+            if run == 62 {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let position = index_position(pixel);
+        if index[position] == pixel {
+            out.push(OP_INDEX | position as u8);
+        } else {
+            index[position] = pixel;
+
+            if pixel[3] == previous[3] {
+                let dr = pixel[0].wrapping_sub(previous[0]) as i8;
+                let dg = pixel[1].wrapping_sub(previous[1]) as i8;
+                let db = pixel[2].wrapping_sub(previous[2]) as i8;
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+
+                if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                    out.push(OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+                } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                    out.push(OP_LUMA | (dg + 32) as u8);
+                    out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[OP_RGB, pixel[0], pixel[1], pixel[2]]);
+                }
+            } else {
+                out.push(OP_RGBA);
+                out.extend_from_slice(&pixel);
+            }
+        }
+        previous = pixel;
+    }
+
+    if run > 0 {
+        out.push(OP_RUN | (run - 1));
+    }
+    out.extend_from_slice(&END_MARKER);
+
+    Ok(out)
+}
+
+impl DynamicImage {
+    pub fn open_qoi(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode(&fs::read(path)?)?)
+    }
+
+    pub fn save_qoi(&self, path: &str) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_ref())?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn open_qoi(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_qoi(path)?.into_image())
+    }
+
+    pub fn save_qoi(&self, path: &str) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_dynamic().as_ref())?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qoi(width: u32, height: u32, channels: u8, ops: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&width.to_be_bytes());
+        out.extend_from_slice(&height.to_be_bytes());
+        out.extend_from_slice(&[channels, 0]);
+        out.extend_from_slice(ops);
+        out.extend_from_slice(&END_MARKER);
+        out
+    }
+
+    #[test]
+    fn each_op_codes_its_pixel() {
+        let pixels = [[10, 20, 30], [11, 19, 30], [21, 29, 42], [21, 29, 42], [21, 29, 42], [10, 20, 30]];
+        let image = DynamicImage::from_raw_parts(3, 6, 1, pixels.concat());
+        // RGB, DIFF (+1, -1, 0), LUMA (green +10, red and blue +0 and +2 from it), a run of 2, INDEX 9
+        let expected = qoi(6, 1, 3, &[OP_RGB, 10, 20, 30, 0x76, 0xaa, 0x8a, 0xc1, 0x09]);
+        assert_eq!(encode(image.as_ref()).unwrap(), expected);
+        assert_eq!(decode(&expected).unwrap().as_bytes(), image.as_bytes());
+    }
+
+    #[test]
+    fn differences_wrap_around() {
+        // From the implicit black start pixel, red -1 wraps to 255
+        let image = decode(&qoi(1, 1, 3, &[0x5b])).unwrap();
+        assert_eq!(image.as_bytes(), [255, 0, 1]);
+    }
+
+    #[test]
+    fn runs_stop_at_62_pixels() {
+        // The start pixel is opaque black, so every pixel continues a run
+        let image = DynamicImage::from_raw_parts(3, 100, 1, vec![0; 300]);
+        assert_eq!(encode(image.as_ref()).unwrap(), qoi(100, 1, 3, &[0xfd, 0xe5]));
+    }
+
+    #[test]
+    fn alpha_changes_need_the_rgba_op() {
+        let image = DynamicImage::from_raw_parts(4, 2, 1, vec![0, 0, 0, 255, 1, 1, 1, 128]);
+        let expected = qoi(2, 1, 4, &[0xc0, OP_RGBA, 1, 1, 1, 128]);
+        assert_eq!(encode(image.as_ref()).unwrap(), expected);
+        assert_eq!(decode(&expected).unwrap().as_bytes(), image.as_bytes());
+    }
+
+    #[test]
+    fn header_larger_than_payload_is_rejected() {
+        let bytes = qoi(1 << 14, 1 << 14, 3, &[0xfd]);
+        assert_eq!(decode(&bytes).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let bytes = qoi(u32::MAX, u32::MAX, 3, &[0xfd]);
+        assert_eq!(decode(&bytes).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+pub(crate) struct Quantized {
+    pub(crate) palette: Vec<[u8; 3]>,
+    // One palette index per pixel
+    pub(crate) indices: Vec<u8>,
+}
+
+struct ColorBox {
+    colors: Vec<([u8; 3], u32)>,
+}
+
+impl ColorBox {
+    fn ranges(&self) -> [u8; 3] {
+        let mut min = [255u8; 3];
+        let mut max = [0u8; 3];
+        for (color, _) in &self.colors {
+            for channel in 0..3 {
+                min[channel] = min[channel].min(color[channel]);
+                max[channel] = max[channel].max(color[channel]);
+            }
+        }
+        [max[0] - min[0], max[1] - min[1], max[2] - min[2]]
+    }
+
+    fn widest(&self) -> (usize, u8) {
+        let ranges = self.ranges();
+        (0..3).map(|channel| (channel, ranges[channel])).max_by_key(|&(_, range)| range).unwrap()
+    }
+
+    fn average(&self) -> [u8; 3] {
+        let mut sums = [0u64; 3];
+        let mut total = 0u64;
+        for (color, count) in &self.colors {
+            for channel in 0..3 {
+                sums[channel] += color[channel] as u64 * *count as u64;
+            }
+            total += *count as u64;
+        }
+        sums.map(|sum| ((sum + total / 2) / total.max(1)) as u8)
+    }
+
+    // Splits at the population-weighted median of the widest channel
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest();
+        self.colors.sort_unstable_by_key(|(color, _)| color[channel]);
+
+        let total: u64 = self.colors.iter().map(|(_, count)| *count as u64).sum();
+        let mut seen = 0u64;
+        let mut cut = 1;
+        for (i, (_, count)) in self.colors.iter().enumerate() {
+            seen += *count as u64;
+            if seen * 2 >= total {
+                cut = i + 1;
+                break;
+            }
+        }
+        let cut = cut.clamp(1, self.colors.len() - 1);
+
+        let upper = self.colors.split_off(cut);
+        (self, ColorBox { colors: upper })
+    }
+}
+
+fn median_cut(histogram: HashMap<[u8; 3], u32>, max_colors: usize) -> Vec<[u8; 3]> {
+    if histogram.len() <= max_colors {
+        return histogram.into_keys().collect();
+    }
+
+    let mut boxes = vec![ColorBox { colors: histogram.into_iter().collect() }];
+    while boxes.len() < max_colors {
+        let candidate = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.colors.len() > 1)
+            .max_by_key(|(_, color_box)| {
+                let population: u64 = color_box.colors.iter().map(|(_, count)| *count as u64).sum();
+                color_box.widest().1 as u64 * population
+            })
+            .map(|(i, _)| i);
+        let Some(i) = candidate else { break };
+
+        let (lower, upper) = boxes.swap_remove(i).split();
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(ColorBox::average).collect()
+}
+
+fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |entry: &[u8; 3]| -> i32 {
+        (0..3).map(|channel| (entry[channel] as i32 - color[channel] as i32).pow(2)).sum()
+    };
+    (0..palette.len()).min_by_key(|&i| distance(&palette[i])).unwrap_or(0) as u8
+}
+
+// Reduces RGB pixels to at most `max_colors` palette entries. Pixels where
+// `skip` is true get index 0 and do not contribute to the palette.
+pub(crate) fn quantize(pixels: &[[u8; 3]], skip: &[bool], max_colors: usize, dither: bool, width: usize) -> Quantized {
+    let mut histogram: HashMap<[u8; 3], u32> = HashMap::new();
+    for (pixel, _) in pixels.iter().zip(skip).filter(|(_, skipped)| !**skipped) {
+        *histogram.entry(*pixel).or_insert(0) += 1;
+    }
+    let exact = histogram.len() <= max_colors;
+    let palette = median_cut(histogram, max_colors.max(1));
+
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut lookup = |color: [u8; 3]| *cache.entry(color).or_insert_with(|| nearest(&palette, color));
+
+    let mut indices = vec![0u8; pixels.len()];
+    if !dither || exact || width == 0 {
+        for (i, pixel) in pixels.iter().enumerate() {
+            if !skip[i] {
+                indices[i] = lookup(*pixel);
+            }
+        }
+        return Quantized { palette, indices };
+    }
+
+    // Floyd-Steinberg error diffusion, the error buffers hold the current and next row
+    let mut current = vec![[0f32; 3]; width + 2];
+    let mut next = vec![[0f32; 3]; width + 2];
+    for (y, row) in pixels.chunks(width).enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            let i = y * width + x;
+            if skip[i] {
+                continue;
+            }
+            let error = current[x + 1];
+            let wanted: [f32; 3] = std::array::from_fn(|channel| (pixel[channel] as f32 + error[channel]).clamp(0.0, 255.0));
+            let index = lookup(wanted.map(|value| value.round() as u8));
+            indices[i] = index;
+
+            let chosen = palette[index as usize];
+            for channel in 0..3 {
+                let diff = wanted[channel] - chosen[channel] as f32;
+                current[x + 2][channel] += diff * 7.0 / 16.0;
+                next[x][channel] += diff * 3.0 / 16.0;
+                next[x + 1][channel] += diff * 5.0 / 16.0;
+                next[x + 2][channel] += diff / 16.0;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|error| *error = [0.0; 3]);
+    }
+
+    Quantized { palette, indices }
+}
@@ -0,0 +1,322 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+
+use super::{bmp, gif, imagely, jpeg, netpbm, png, qoi, tiff, webp};
+use super::{GifOptions, ImagelyOptions, JpegOptions, NetpbmFormat, NetpbmOptions, PngOptions, TiffOptions};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel};
+
+// An image format that `Image::open` and `Image::save` can dispatch to
+pub trait Codec: Send + Sync {
+    // Lowercase file extensions without the leading dot
+    fn extensions(&self) -> &[&str];
+
+    // Whether the start of a file looks like this format
+    fn matches(&self, bytes: &[u8]) -> bool;
+
+    fn decode(&self, bytes: &[u8]) -> Result<DynamicImage, ImageError>;
+
+    fn encode(&self, image: DynamicImageRef<'_>) -> Result<Vec<u8>, ImageError>;
+}
+
+// The codecs shipped with the crate, encoding with their default options
+struct BuiltIn {
+    extensions: &'static [&'static str],
+    matches: fn(&[u8]) -> bool,
+    decode: fn(&[u8]) -> io::Result<DynamicImage>,
+    encode: fn(DynamicImageRef<'_>) -> io::Result<Vec<u8>>,
+}
+
+impl Codec for BuiltIn {
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    fn matches(&self, bytes: &[u8]) -> bool {
+        (self.matches)(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+        Ok((self.decode)(bytes)?)
+    }
+
+    fn encode(&self, image: DynamicImageRef<'_>) -> Result<Vec<u8>, ImageError> {
+        Ok((self.encode)(image)?)
+    }
+}
+
+fn netpbm_encode(image: DynamicImageRef<'_>, format: NetpbmFormat) -> io::Result<Vec<u8>> {
+    netpbm::encode(image, &NetpbmOptions { format, ascii: false })
+}
+
+fn built_in_codecs() -> Vec<Arc<dyn Codec>> {
+    let codecs = [
+        BuiltIn {
+            extensions: &["png"],
+            matches: |bytes| bytes.starts_with(b"\x89PNG\r\n\x1a\n"),
+            decode: png::decode,
+            encode: |image| png::encode(image, &PngOptions::default()),
+        },
+        BuiltIn {
+            extensions: &["jpg", "jpeg", "jpe", "jfif"],
+            matches: |bytes| bytes.starts_with(&[0xff, 0xd8, 0xff]),
+            decode: jpeg::decode,
+            encode: |image| jpeg::encode(image, &JpegOptions::default()),
+        },
+        BuiltIn {
+            extensions: &["gif"],
+            matches: |bytes| bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a"),
+            decode: |bytes| Ok(gif::decode_frames(bytes)?.swap_remove(0).image),
+            encode: |image| gif::encode_frames(&[(image, 0)], &GifOptions::default()),
+        },
+        BuiltIn {
+            extensions: &["bmp", "dib"],
+            matches: |bytes| bytes.starts_with(b"BM"),
+            decode: bmp::decode,
+            encode: bmp::encode,
+        },
+        BuiltIn {
+            extensions: &["tif", "tiff"],
+            matches: |bytes| bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*"),
+            decode: |bytes| Ok(tiff::decode_pages(bytes)?.swap_remove(0)),
+            encode: |image| tiff::encode_pages(&[image], &TiffOptions::default()),
+        },
+        BuiltIn {
+            extensions: &["webp"],
+            matches: |bytes| bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP",
+            decode: webp::decode,
+            encode: webp::encode,
+        },
+        BuiltIn {
+            extensions: &["qoi"],
+            matches: |bytes| bytes.starts_with(b"qoif"),
+            decode: qoi::decode,
+            encode: qoi::encode,
+        },
+        BuiltIn {
+            extensions: &["imgly"],
+            matches: imagely::is_imagely,
+            decode: imagely::decode,
+            encode: |image| imagely::encode(image, &ImagelyOptions::default()),
+        },
+        // Every Netpbm variant decodes with the same reader, the extension
+        // only picks the flavour to write
+        BuiltIn {
+            extensions: &["pbm"],
+            matches: |bytes| bytes.starts_with(b"P1") || bytes.starts_with(b"P4"),
+            decode: netpbm::decode,
+            encode: |image| netpbm_encode(image, NetpbmFormat::Pbm),
+        },
+        BuiltIn {
+            extensions: &["pgm"],
+            matches: |bytes| bytes.starts_with(b"P2") || bytes.starts_with(b"P5"),
+            decode: netpbm::decode,
+            encode: |image| netpbm_encode(image, NetpbmFormat::Pgm),
+        },
+        BuiltIn {
+            extensions: &["ppm", "pnm"],
+            matches: |bytes| bytes.starts_with(b"P3") || bytes.starts_with(b"P6"),
+            decode: netpbm::decode,
+            encode: |image| netpbm_encode(image, NetpbmFormat::Ppm),
+        },
+        BuiltIn {
+            extensions: &["pam"],
+            matches: |bytes| bytes.starts_with(b"P7"),
+            decode: netpbm::decode,
+            encode: |image| netpbm_encode(image, NetpbmFormat::Pam),
+        },
+    ];
+    codecs.into_iter().map(|codec| Arc::new(codec) as Arc<dyn Codec>).collect()
+}
+
+fn registry() -> &'static RwLock<Vec<Arc<dyn Codec>>> {
+    static REGISTRY: OnceLock<RwLock<Vec<Arc<dyn Codec>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(built_in_codecs()))
+}
+
+// Makes a codec available to `Image::open` and `Image::save`. Codecs
+// registered later are tried first, so they can override built-in formats.
+pub fn register_codec<C: Codec + 'static>(codec: C) {
+    registry().write().unwrap_or_else(PoisonError::into_inner).insert(0, Arc::new(codec));
+}
+
+fn find_codec(predicate: impl Fn(&dyn Codec) -> bool) -> Option<Arc<dyn Codec>> {
+    let codecs = registry().read().unwrap_or_else(PoisonError::into_inner);
+    codecs.iter().find(|codec| predicate(codec.as_ref())).cloned()
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path).extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase)
+}
+
+fn codec_for_extension(extension: &str) -> Option<Arc<dyn Codec>> {
+    find_codec(|codec| codec.extensions().contains(&extension))
+}
+
+impl DynamicImage {
+    // Picks the decoder from the file contents, falling back to the extension
+    // for formats without a recognizable signature
+    pub fn open(path: &str) -> Result<DynamicImage, ImageError> {
+        let bytes = fs::read(path)?;
+        let codec = find_codec(|codec| codec.matches(&bytes))
+            .or_else(|| extension(path).and_then(|extension| codec_for_extension(&extension)))
+            .ok_or_else(|| ImageError::Unsupported("unrecognized image format".to_string()))?;
+        codec.decode(&bytes)
+    }
+
+    // Picks the encoder from the file extension
+    pub fn save(&self, path: &str) -> Result<(), ImageError> {
+        self.as_ref().save(path)
+    }
+}
+
+impl DynamicImageRef<'_> {
+    pub fn save(self, path: &str) -> Result<(), ImageError> {
+        let extension = extension(path).ok_or_else(|| {
+            ImageError::Unsupported("cannot pick an image format without a file extension".to_string())
+        })?;
+        let codec = codec_for_extension(&extension)
+            .ok_or_else(|| ImageError::Unsupported(format!("no image format registered for .{extension} files")))?;
+        Ok(fs::write(path, codec.encode(self)?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    // Decodes any supported format and converts it to this pixel type
+    pub fn open(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open(path)?.into_image())
+    }
+
+    // Images of the built-in pixel types are encoded without copying them
+    pub fn save(&self, path: &str) -> Result<(), ImageError> {
+        self.as_dynamic().as_ref().save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Luma8;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("imagely-registry-{}-{name}", std::process::id())).to_string_lossy().into_owned()
+    }
+
+    fn gray_pair() -> DynamicImage {
+        DynamicImage::from_raw_parts(1, 2, 1, vec![0x20, 0xe0])
+    }
+
+    // Stores the width, height and samples of a grayscale image behind a marker,
+    // optionally without any way to recognize the contents
+    struct Plain {
+        extension: &'static str,
+        signature: bool,
+    }
+
+    impl Codec for Plain {
+        fn extensions(&self) -> &[&str] {
+            std::slice::from_ref(&self.extension)
+        }
+
+        fn matches(&self, bytes: &[u8]) -> bool {
+            self.signature && bytes.starts_with(b"PLAIN")
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+            let pixels = bytes[7..].to_vec();
+            Ok(DynamicImage::from_raw_parts(1, bytes[5] as u32, bytes[6] as u32, pixels))
+        }
+
+        fn encode(&self, image: DynamicImageRef<'_>) -> Result<Vec<u8>, ImageError> {
+            let image = image.to_image::<Luma8>();
+            Ok([&b"PLAIN"[..], &[image.width as u8, image.height as u8], image.as_bytes()].concat())
+        }
+    }
+
+    #[test]
+    fn contents_are_sniffed_before_the_extension() {
+        // A PNG named like a JPEG still decodes losslessly
+        let path = temp_path("mislabelled.jpg");
+        fs::write(&path, png::encode(gray_pair().as_ref(), &PngOptions::default()).unwrap()).unwrap();
+        let image = DynamicImage::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(image.unwrap().as_bytes(), [0x20, 0xe0]);
+    }
+
+    #[test]
+    fn every_built_in_signature_is_recognized() {
+        let image = gray_pair();
+        for (extension, signature) in [
+            ("png", &b"\x89PNG"[..]),
+            ("jpeg", b"\xff\xd8\xff"),
+            ("gif", b"GIF89a"),
+            ("dib", b"BM"),
+            ("tiff", b"II*\0"),
+            ("webp", b"RIFF"),
+            ("qoi", b"qoif"),
+            ("imgly", b"\x89IMGLY"),
+            ("pbm", b"P4"),
+            ("pgm", b"P5"),
+            ("pnm", b"P6"),
+            ("pam", b"P7"),
+        ] {
+            let codec = codec_for_extension(extension).unwrap();
+            let bytes = codec.encode(image.as_ref()).unwrap();
+            assert!(bytes.starts_with(signature), "{extension}");
+            let sniffed = find_codec(|codec| codec.matches(&bytes)).unwrap();
+            assert!(sniffed.extensions().contains(&extension), "{extension}");
+        }
+    }
+
+    #[test]
+    fn save_picks_the_encoder_from_the_extension() {
+        let path = temp_path("upper.PGM");
+        gray_pair().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let image = Image::<Luma8>::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(bytes.starts_with(b"P5"));
+        assert_eq!(image.unwrap().as_bytes(), [0x20, 0xe0]);
+
+        for name in ["no-extension", "unknown.xyz"] {
+            let error = gray_pair().save(&temp_path(name)).unwrap_err();
+            assert!(matches!(error, ImageError::Unsupported(_)), "{name}");
+        }
+    }
+
+    #[test]
+    fn unrecognized_contents_are_unsupported() {
+        let path = temp_path("garbage.bin");
+        fs::write(&path, b"not an image").unwrap();
+        let error = DynamicImage::open(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(error, ImageError::Unsupported(_)));
+        assert!(matches!(DynamicImage::open(&temp_path("missing.png")).unwrap_err(), ImageError::Io(_)));
+    }
+
+    #[test]
+    fn registered_codecs_are_used_for_both_directions() {
+        register_codec(Plain { extension: "plain", signature: true });
+        let path = temp_path("custom.plain");
+        gray_pair().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        // Sniffing finds the codec even under another name
+        let renamed = temp_path("custom.dat");
+        fs::rename(&path, &renamed).unwrap();
+        let image = DynamicImage::open(&renamed);
+        fs::remove_file(&renamed).unwrap();
+        assert_eq!(bytes, b"PLAIN\x02\x01\x20\xe0");
+        assert_eq!(image.unwrap().as_bytes(), [0x20, 0xe0]);
+    }
+
+    #[test]
+    fn formats_without_a_signature_fall_back_to_the_extension() {
+        register_codec(Plain { extension: "unsigned", signature: false });
+        let path = temp_path("fallback.unsigned");
+        gray_pair().save(&path).unwrap();
+        let image = DynamicImage::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(image.unwrap().as_bytes(), [0x20, 0xe0]);
+    }
+}
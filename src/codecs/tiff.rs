@@ -0,0 +1,1104 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use super::zlib::{zlib_compress, zlib_decompress};
+use super::{image_len, invalid_data, unsupported};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel, SampleDepth};
+
+const TAG_NEW_SUBFILE_TYPE: u16 = 254;
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC: u16 = 262;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_X_RESOLUTION: u16 = 282;
+const TAG_Y_RESOLUTION: u16 = 283;
+const TAG_PLANAR_CONFIG: u16 = 284;
+const TAG_RESOLUTION_UNIT: u16 = 296;
+const TAG_PAGE_NUMBER: u16 = 297;
+const TAG_PREDICTOR: u16 = 317;
+const TAG_COLOR_MAP: u16 = 320;
+const TAG_TILE_WIDTH: u16 = 322;
+const TAG_TILE_LENGTH: u16 = 323;
+const TAG_TILE_OFFSETS: u16 = 324;
+const TAG_TILE_BYTE_COUNTS: u16 = 325;
+const TAG_EXTRA_SAMPLES: u16 = 338;
+const TAG_SAMPLE_FORMAT: u16 = 339;
+
+const TYPE_BYTE: u16 = 1;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+const TYPE_UNDEFINED: u16 = 7;
+
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_LZW: u32 = 5;
+const COMPRESSION_DEFLATE: u32 = 8;
+const COMPRESSION_ADOBE_DEFLATE: u32 = 32946;
+const COMPRESSION_PACKBITS: u32 = 32773;
+
+const PHOTOMETRIC_WHITE_IS_ZERO: u32 = 0;
+const PHOTOMETRIC_BLACK_IS_ZERO: u32 = 1;
+const PHOTOMETRIC_RGB: u32 = 2;
+const PHOTOMETRIC_PALETTE: u32 = 3;
+const PHOTOMETRIC_SEPARATED: u32 = 5;
+
+const EXTRA_SAMPLE_ASSOCIATED_ALPHA: u32 = 1;
+const EXTRA_SAMPLE_UNASSOCIATED_ALPHA: u32 = 2;
+
+const LZW_CLEAR: usize = 256;
+const LZW_END: usize = 257;
+const LZW_MAX_CODES: usize = 4096;
+
+// Uncompressed strips are kept around this size when writing
+const STRIP_TARGET_BYTES: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+    PackBits,
+}
+
+#[derive(Debug, Clone)]
+pub struct TiffOptions {
+    pub compression: TiffCompression,
+    // Horizontal differencing, only used with LZW and Deflate
+    pub predictor: bool,
+    // Square tiles of this size (rounded up to a multiple of 16) instead of strips
+    pub tile_size: Option<u32>,
+}
+
+impl Default for TiffOptions {
+    fn default() -> Self {
+        TiffOptions {
+            compression: TiffCompression::Lzw,
+            predictor: true,
+            tile_size: None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn u16_at(&self, pos: usize) -> io::Result<u16> {
+        let b = self.bytes.get(pos..pos + 2).ok_or_else(|| invalid_data("unexpected end of TIFF data"))?;
+        Ok(if self.big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) })
+    }
+
+    fn u32_at(&self, pos: usize) -> io::Result<u32> {
+        let b = self.bytes.get(pos..pos + 4).ok_or_else(|| invalid_data("unexpected end of TIFF data"))?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    // Returns the IFD's integer fields and the offset of the next IFD
+    fn ifd(&self, offset: usize) -> io::Result<(HashMap<u16, Vec<u32>>, usize)> {
+        let count = self.u16_at(offset)? as usize;
+        let mut fields = HashMap::new();
+
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            let tag = self.u16_at(entry)?;
+            let kind = self.u16_at(entry + 2)?;
+            let value_count = self.u32_at(entry + 4)? as usize;
+
+            let size = match kind {
+                TYPE_BYTE | TYPE_UNDEFINED => 1,
+                TYPE_SHORT => 2,
+                TYPE_LONG => 4,
+                // Other types are not needed for decoding pixels
+                _ => continue,
+            };
+            let total = size * value_count;
+            let start = if total <= 4 { entry + 8 } else { self.u32_at(entry + 8)? as usize };
+            if start.checked_add(total).is_none_or(|end| end > self.bytes.len()) {
+                return Err(invalid_data("TIFF field points outside the file"));
+            }
+
+            let values = (0..value_count)
+                .map(|j| match size {
+                    1 => Ok(self.bytes[start + j] as u32),
+                    2 => self.u16_at(start + j * 2).map(|value| value as u32),
+                    _ => self.u32_at(start + j * 4),
+                })
+                .collect::<io::Result<Vec<u32>>>()?;
+            fields.insert(tag, values);
+        }
+
+        let next = self.u32_at(offset + 2 + count * 12)? as usize;
+        Ok((fields, next))
+    }
+}
+
+fn lzw_decode(data: &[u8], expected_len: usize) -> io::Result<Vec<u8>> {
+    // Pre-6.0 TIFF LZW used LSB-first bit order
+    if data.len() >= 2 && data[0] == 0 && data[1] & 1 != 0 {
+        return Err(unsupported("old-style TIFF LZW is not supported"));
+    }
+
+    let mut prefix = [0u16; LZW_MAX_CODES];
+    let mut suffix = [0u8; LZW_MAX_CODES];
+    let mut first = [0u8; LZW_MAX_CODES];
+    let mut length = [0u16; LZW_MAX_CODES];
+    for code in 0..LZW_CLEAR {
+        suffix[code] = code as u8;
+        first[code] = code as u8;
+        length[code] = 1;
+    }
+
+    let mut out = Vec::with_capacity(expected_len);
+    let mut width = 9u32;
+    let mut next = LZW_END + 1;
+    let mut previous: Option<usize> = None;
+
+    let mut bit_buffer = 0u32;
+    let mut bit_count = 0u32;
+    let mut bytes = data.iter();
+
+    // Anything past the chunk is dropped, so decoding stops there
+    while out.len() < expected_len {
+        while bit_count < width {
+            match bytes.next() {
+                Some(&byte) => {
+                    bit_buffer = (bit_buffer << 8) | byte as u32;
+                    bit_count += 8;
+                }
+                None => break,
+            }
+        }
+        if bit_count < width {
+            break;
+        }
+        let code = ((bit_buffer >> (bit_count - width)) & ((1 << width) - 1)) as usize;
+        bit_count -= width;
+
+        if code == LZW_CLEAR {
+            width = 9;
+            next = LZW_END + 1;
+            previous = None;
+            continue;
+        }
+        if code == LZW_END {
+            break;
+        }
+
+        let Some(prev) = previous else {
+            if code >= LZW_CLEAR {
+                return Err(invalid_data("invalid first TIFF LZW code"));
+            }
+            out.push(code as u8);
+            previous = Some(code);
+            continue;
+        };
+
+        if code > next || (code == next && next >= LZW_MAX_CODES) {
+            return Err(invalid_data("invalid TIFF LZW code"));
+        }
+
+        // A code equal to `next` is the previous string plus its own first byte
+        let known = code < next;
+        let emitted = if known { code } else { prev };
+        let start = out.len();
+        let emitted_len = length[emitted] as usize;
+        out.resize(start + emitted_len, 0);
+        let mut walk = emitted;
+        for i in (0..emitted_len).rev() {
+            out[start + i] = suffix[walk];
+            walk = prefix[walk] as usize;
+        }
+        if !known {
+            out.push(first[prev]);
+        }
+
+        if next < LZW_MAX_CODES {
+            prefix[next] = prev as u16;
+            suffix[next] = if known { first[code] } else { first[prev] };
+            first[next] = first[prev];
+            length[next] = length[prev] + 1;
+            next += 1;
+            // TIFF switches to the wider code one entry early
+            if next + 1 == 1 << width && width < 12 {
+                width += 1;
+            }
+        }
+        previous = Some(code);
+    }
+
+    Ok(out)
+}
+
+fn packbits_decode(data: &[u8], expected_len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(expected_len);
+    let mut pos = 0;
+    while pos < data.len() && out.len() < expected_len {
+        let header = data[pos] as i8;
+        pos += 1;
+        match header {
+            0..=127 => {
+                let end = (pos + header as usize + 1).min(data.len());
+                out.extend_from_slice(&data[pos..end]);
+                pos = end;
+            }
+            -127..=-1 => {
+                if let Some(&byte) = data.get(pos) {
+                    out.extend(std::iter::repeat_n(byte, (1 - header as isize) as usize));
+                    pos += 1;
+                }
+            }
+            // -128 is a no-op
+            _ => {}
+        }
+    }
+    out
+}
+
+struct Layout {
+    width: usize,
+    bits: usize,
+    samples: usize,
+    planar: bool,
+    big_endian: bool,
+}
+
+impl Layout {
+    // Samples stored next to each other in one plane
+    fn samples_per_plane(&self) -> usize {
+        if self.planar { 1 } else { self.samples }
+    }
+
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.samples_per_plane() * self.bits).div_ceil(8)
+    }
+
+    fn sample(&self, planes: &[Vec<u8>], x: usize, y: usize, channel: usize) -> u16 {
+        let (plane, index) = if self.planar { (channel, x) } else { (0, x * self.samples + channel) };
+        let row = &planes[plane][y * self.row_bytes(self.width)..];
+        match self.bits {
+            8 => row[index] as u16,
+            16 => {
+                let b = [row[index * 2], row[index * 2 + 1]];
+                if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) }
+            }
+            bits => {
+                let bit = index * bits;
+                ((row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1) as u8) as u16
+            }
+        }
+    }
+
+    fn to_u8(&self, value: u16) -> u8 {
+        let max = (1u32 << self.bits) - 1;
+        ((value as u32 * 255 + max / 2) / max) as u8
+    }
+}
+
+fn undo_predictor(chunk: &mut [u8], row_len: usize, layout: &Layout) -> io::Result<()> {
+    let stride = layout.samples_per_plane();
+    for row in chunk.chunks_mut(row_len.max(1)) {
+        match layout.bits {
+            8 => {
+                for i in stride..row.len() {
+                    row[i] = row[i].wrapping_add(row[i - stride]);
+                }
+            }
+            16 => {
+                let read = |row: &[u8], i: usize| {
+                    let b = [row[i * 2], row[i * 2 + 1]];
+                    if layout.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) }
+                };
+                for i in stride..row.len() / 2 {
+                    let value = read(row, i).wrapping_add(read(row, i - stride));
+                    let b = if layout.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+                    row[i * 2..i * 2 + 2].copy_from_slice(&b);
+                }
+            }
+            _ => return Err(unsupported("TIFF predictor needs 8 or 16-bit samples")),
+        }
+    }
+    Ok(())
+}
+
+fn decode_page(reader: &Reader, fields: &HashMap<u16, Vec<u32>>) -> io::Result<DynamicImage> {
+    let field = |tag: u16| fields.get(&tag).and_then(|values| values.first().copied());
+    let required = |tag: u16, name: &str| field(tag).ok_or_else(|| invalid_data(&format!("missing TIFF {name}")));
+
+    let width = required(TAG_IMAGE_WIDTH, "ImageWidth")? as usize;
+    let height = required(TAG_IMAGE_LENGTH, "ImageLength")? as usize;
+    let samples = field(TAG_SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+    let bits = field(TAG_BITS_PER_SAMPLE).unwrap_or(1) as usize;
+    let compression = field(TAG_COMPRESSION).unwrap_or(COMPRESSION_NONE);
+    let photometric = required(TAG_PHOTOMETRIC, "PhotometricInterpretation")?;
+    let planar = field(TAG_PLANAR_CONFIG).unwrap_or(1) == 2 && samples > 1;
+    let predictor = field(TAG_PREDICTOR).unwrap_or(1);
+
+    if !matches!(bits, 1 | 2 | 4 | 8 | 16) {
+        return Err(unsupported("unsupported TIFF bit depth"));
+    }
+    if fields.get(&TAG_BITS_PER_SAMPLE).is_some_and(|values| values.iter().any(|&b| b as usize != bits)) {
+        return Err(unsupported("TIFF samples with different bit depths are not supported"));
+    }
+    if field(TAG_SAMPLE_FORMAT).unwrap_or(1) != 1 {
+        return Err(unsupported("only unsigned integer TIFF samples are supported"));
+    }
+    if predictor != 1 && predictor != 2 {
+        return Err(unsupported("unsupported TIFF predictor"));
+    }
+
+    let color_samples = match photometric {
+        PHOTOMETRIC_WHITE_IS_ZERO | PHOTOMETRIC_BLACK_IS_ZERO | PHOTOMETRIC_PALETTE => 1,
+        PHOTOMETRIC_RGB => 3,
+        PHOTOMETRIC_SEPARATED => 4,
+        _ => return Err(unsupported("unsupported TIFF photometric interpretation")),
+    };
+    if samples < color_samples {
+        return Err(invalid_data("too few TIFF samples per pixel"));
+    }
+    if photometric == PHOTOMETRIC_RGB && bits < 8 {
+        return Err(unsupported("RGB TIFF images need 8 or 16-bit samples"));
+    }
+
+    // The first extra sample is alpha when declared as such, or for RGBA without the tag
+    let extra = fields.get(&TAG_EXTRA_SAMPLES).and_then(|values| values.first().copied());
+    let alpha = match extra {
+        Some(EXTRA_SAMPLE_ASSOCIATED_ALPHA | EXTRA_SAMPLE_UNASSOCIATED_ALPHA) if samples > color_samples => Some(color_samples),
+        None if photometric == PHOTOMETRIC_RGB && samples == 4 => Some(3),
+        _ => None,
+    };
+    let premultiplied = extra == Some(EXTRA_SAMPLE_ASSOCIATED_ALPHA);
+
+    // Bounds every byte size worked out below
+    image_len(width, height, samples)?;
+    let layout = Layout { width, bits, samples, planar, big_endian: reader.big_endian };
+    let plane_count = if planar { samples } else { 1 };
+    let plane_row_bytes = layout.row_bytes(width);
+
+    // Strips are treated as tiles spanning the full width
+    let tiled = fields.contains_key(&TAG_TILE_OFFSETS);
+    let (chunk_width, chunk_height, offsets, byte_counts) = if tiled {
+        (
+            required(TAG_TILE_WIDTH, "TileWidth")? as usize,
+            required(TAG_TILE_LENGTH, "TileLength")? as usize,
+            &fields[&TAG_TILE_OFFSETS],
+            fields.get(&TAG_TILE_BYTE_COUNTS).ok_or_else(|| invalid_data("missing TIFF TileByteCounts"))?,
+        )
+    } else {
+        (
+            width,
+            (field(TAG_ROWS_PER_STRIP).unwrap_or(u32::MAX) as usize).min(height),
+            fields.get(&TAG_STRIP_OFFSETS).ok_or_else(|| invalid_data("missing TIFF StripOffsets"))?,
+            fields.get(&TAG_STRIP_BYTE_COUNTS).ok_or_else(|| invalid_data("missing TIFF StripByteCounts"))?,
+        )
+    };
+    if chunk_width == 0 || chunk_height == 0 {
+        return Err(invalid_data("invalid TIFF strip or tile size"));
+    }
+
+    image_len(chunk_width, chunk_height, samples)?;
+
+    let across = width.div_ceil(chunk_width);
+    let down = height.div_ceil(chunk_height);
+    let chunk_count = across
+        .checked_mul(down)
+        .and_then(|count| count.checked_mul(plane_count))
+        .ok_or_else(|| invalid_data("too many TIFF strips or tiles"))?;
+    if offsets.len() < chunk_count || byte_counts.len() < offsets.len() {
+        return Err(invalid_data("not enough TIFF strips or tiles"));
+    }
+    let chunk_data = |index: usize| {
+        let start = offsets[index] as usize;
+        let end = start.saturating_add(byte_counts[index] as usize).min(reader.bytes.len());
+        reader.bytes.get(start..end).unwrap_or(&[])
+    };
+
+    // Nothing decompresses past these ratios, so a header asking for more than
+    // the chunks could ever hold is rejected before allocating for it
+    let max_ratio = match compression {
+        COMPRESSION_NONE => 1,
+        COMPRESSION_PACKBITS => 64,
+        COMPRESSION_LZW => 4096,
+        COMPRESSION_DEFLATE | COMPRESSION_ADOBE_DEFLATE => 1032,
+        _ => return Err(unsupported("unsupported TIFF compression")),
+    };
+    let chunk_row_bytes = layout.row_bytes(chunk_width);
+    // Tiles are always stored whole, the last strip only holds the rows left
+    let needed = if tiled {
+        chunk_row_bytes.checked_mul(chunk_height).and_then(|len| len.checked_mul(chunk_count))
+    } else {
+        plane_row_bytes.checked_mul(height).and_then(|len| len.checked_mul(plane_count))
+    }
+    .ok_or_else(|| invalid_data("TIFF strips or tiles are too large"))?;
+    let available: usize = (0..chunk_count).map(|index| chunk_data(index).len()).sum();
+    if needed > available.saturating_mul(max_ratio) {
+        return Err(invalid_data("not enough TIFF image data"));
+    }
+
+    let mut planes = vec![vec![0u8; plane_row_bytes * height]; plane_count];
+    for (plane_index, plane) in planes.iter_mut().enumerate() {
+        for chunk_y in 0..down {
+            for chunk_x in 0..across {
+                let index = plane_index * across * down + chunk_y * across + chunk_x;
+                let data = chunk_data(index);
+
+                let rows = if tiled { chunk_height } else { chunk_height.min(height - chunk_y * chunk_height) };
+                let expected_len = chunk_row_bytes * rows;
+                let mut chunk = match compression {
+                    COMPRESSION_NONE => data.to_vec(),
+                    COMPRESSION_LZW => lzw_decode(data, expected_len)?,
+                    COMPRESSION_DEFLATE | COMPRESSION_ADOBE_DEFLATE => zlib_decompress(data, expected_len)?,
+                    _ => packbits_decode(data, expected_len),
+                };
+                // Short chunks are padded so damaged files still decode
+                chunk.resize(expected_len, 0);
+                if predictor == 2 {
+                    undo_predictor(&mut chunk, chunk_row_bytes, &layout)?;
+                }
+
+                let x_start = layout.row_bytes(chunk_x * chunk_width);
+                let copy_len = chunk_row_bytes.min(plane_row_bytes - x_start);
+                for (row, source) in chunk.chunks_exact(chunk_row_bytes.max(1)).enumerate() {
+                    let y = chunk_y * chunk_height + row;
+                    if y >= height {
+                        break;
+                    }
+                    let dest = y * plane_row_bytes + x_start;
+                    plane[dest..dest + copy_len].copy_from_slice(&source[..copy_len]);
+                }
+            }
+        }
+    }
+
+    let palette = if photometric == PHOTOMETRIC_PALETTE {
+        let map = fields.get(&TAG_COLOR_MAP).ok_or_else(|| invalid_data("missing TIFF ColorMap"))?;
+        let size = 1 << bits;
+        if map.len() < size * 3 {
+            return Err(invalid_data("TIFF ColorMap is too short"));
+        }
+        (0..size).map(|i| [map[i] >> 8, map[size + i] >> 8, map[size * 2 + i] >> 8].map(|value| value as u8)).collect()
+    } else {
+        Vec::new()
+    };
+
+    // 16-bit samples stay 16-bit, palette entries and lower depths become 8-bit
+    let sixteen = bits == 16 && photometric != PHOTOMETRIC_PALETTE;
+    let max: u32 = if sixteen { 65535 } else { 255 };
+
+    let gray = matches!(photometric, PHOTOMETRIC_WHITE_IS_ZERO | PHOTOMETRIC_BLACK_IS_ZERO);
+    let color_channels = if gray { 1 } else { 3 };
+    let channels = color_channels + alpha.is_some() as u8;
+    let mut data: Vec<u16> = Vec::with_capacity(width * height * channels as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let value = |channel| {
+                let sample = layout.sample(&planes, x, y, channel);
+                if sixteen { sample as u32 } else { layout.to_u8(sample) as u32 }
+            };
+            let rgb = match photometric {
+                PHOTOMETRIC_WHITE_IS_ZERO => [max - value(0); 3],
+                PHOTOMETRIC_BLACK_IS_ZERO => [value(0); 3],
+                PHOTOMETRIC_PALETTE => palette[layout.sample(&planes, x, y, 0) as usize].map(u32::from),
+                PHOTOMETRIC_SEPARATED => {
+                    let k = max - value(3);
+                    [0, 1, 2].map(|channel| (max - value(channel)) * k / max)
+                }
+                _ => [value(0), value(1), value(2)],
+            };
+
+            match alpha {
+                Some(channel) => {
+                    let a = value(channel);
+                    let rgb = if premultiplied && a > 0 && a < max {
+                        rgb.map(|c| ((c * max + a / 2) / a).min(max))
+                    } else {
+                        rgb
+                    };
+                    data.extend(rgb[..color_channels as usize].iter().map(|&c| c as u16));
+                    data.push(a as u16);
+                }
+                None => data.extend(rgb[..color_channels as usize].iter().map(|&c| c as u16)),
+            }
+        }
+    }
+
+    let (width, height) = (width as u32, height as u32);
+    if sixteen {
+        Ok(DynamicImage::from_raw_parts16(channels, width, height, data))
+    } else {
+        let data = data.into_iter().map(|sample| sample as u8).collect();
+        Ok(DynamicImage::from_raw_parts(channels, width, height, data))
+    }
+}
+
+pub(crate) fn decode_pages(bytes: &[u8]) -> io::Result<Vec<DynamicImage>> {
+    let big_endian = match bytes.get(..4) {
+        Some([b'I', b'I', 42, 0]) => false,
+        Some([b'M', b'M', 0, 42]) => true,
+        Some([b'I', b'I', 43, 0] | [b'M', b'M', 0, 43]) => return Err(unsupported("BigTIFF is not supported")),
+        _ => return Err(invalid_data("not a TIFF file")),
+    };
+    let reader = Reader { bytes, big_endian };
+
+    let mut pages = Vec::new();
+    let mut visited = Vec::new();
+    let mut offset = reader.u32_at(4)? as usize;
+    while offset != 0 {
+        if visited.contains(&offset) {
+            return Err(invalid_data("TIFF IFD chain loops"));
+        }
+        visited.push(offset);
+
+        let (fields, next) = reader.ifd(offset)?;
+        // Reduced resolution copies (thumbnails) are skipped after the first page
+        let reduced = fields.get(&TAG_NEW_SUBFILE_TYPE).and_then(|values| values.first()).is_some_and(|kind| kind & 1 != 0);
+        if pages.is_empty() || !reduced {
+            pages.push(decode_page(&reader, &fields)?);
+        }
+        offset = next;
+    }
+
+    if pages.is_empty() {
+        return Err(invalid_data("TIFF file contains no images"));
+    }
+    Ok(pages)
+}
+
+struct CodeWriter {
+    out: Vec<u8>,
+    bit_buffer: u32,
+    bit_count: u32,
+    width: u32,
+}
+
+impl CodeWriter {
+    fn emit(&mut self, code: usize) {
+        self.bit_buffer = (self.bit_buffer << self.width) | code as u32;
+        self.bit_count += self.width;
+        while self.bit_count >= 8 {
+            self.out.push((self.bit_buffer >> (self.bit_count - 8)) as u8);
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push((self.bit_buffer << (8 - self.bit_count)) as u8);
+        }
+        self.out
+    }
+}
+
+// Called after every emitted code, mirroring the decoder's table growth
+fn lzw_grow(writer: &mut CodeWriter, dictionary: &mut HashMap<(u16, u8), u16>, next: &mut usize) {
+    *next += 1;
+    if *next == LZW_MAX_CODES - 2 {
+        writer.emit(LZW_CLEAR);
+        dictionary.clear();
+        *next = LZW_END + 1;
+        writer.width = 9;
+    } else if *next == 1 << writer.width {
+        writer.width += 1;
+    }
+}
+
+fn lzw_encode(data: &[u8]) -> Vec<u8> {
+    let mut writer = CodeWriter { out: Vec::new(), bit_buffer: 0, bit_count: 0, width: 9 };
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = LZW_END + 1;
+    writer.emit(LZW_CLEAR);
+
+    let mut iter = data.iter();
+    if let Some(&first) = iter.next() {
+        let mut current = first as u16;
+        for &byte in iter {
+            if let Some(&code) = dictionary.get(&(current, byte)) {
+                current = code;
+                continue;
+            }
+            writer.emit(current as usize);
+            dictionary.insert((current, byte), next as u16);
+            lzw_grow(&mut writer, &mut dictionary, &mut next);
+            current = byte as u16;
+        }
+        writer.emit(current as usize);
+        lzw_grow(&mut writer, &mut dictionary, &mut next);
+    }
+
+    writer.emit(LZW_END);
+    writer.finish()
+}
+
+fn packbits_encode(data: &[u8], row_len: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    // Runs never cross rows
+    for row in data.chunks(row_len.max(1)) {
+        let mut pos = 0;
+        while pos < row.len() {
+            let run = row[pos..].iter().take(128).take_while(|&&byte| byte == row[pos]).count();
+            if run >= 2 {
+                out.push((1 - run as isize) as u8);
+                out.push(row[pos]);
+                pos += run;
+                continue;
+            }
+
+            let start = pos;
+            while pos < row.len() && pos - start < 128 {
+                if pos + 2 < row.len() && row[pos] == row[pos + 1] && row[pos] == row[pos + 2] {
+                    break;
+                }
+                pos += 1;
+            }
+            out.push((pos - start - 1) as u8);
+            out.extend_from_slice(&row[start..pos]);
+        }
+    }
+    out
+}
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    values: Vec<u32>,
+}
+
+impl Entry {
+    fn short(tag: u16, values: Vec<u32>) -> Entry {
+        Entry { tag, kind: TYPE_SHORT, values }
+    }
+
+    fn long(tag: u16, values: Vec<u32>) -> Entry {
+        Entry { tag, kind: TYPE_LONG, values }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self.kind {
+            TYPE_SHORT => self.values.iter().flat_map(|&value| (value as u16).to_le_bytes()).collect(),
+            _ => self.values.iter().flat_map(|&value| value.to_le_bytes()).collect(),
+        }
+    }
+
+    fn count(&self) -> u32 {
+        if self.kind == TYPE_RATIONAL { self.values.len() as u32 / 2 } else { self.values.len() as u32 }
+    }
+}
+
+fn write_ifd(out: &mut Vec<u8>, mut entries: Vec<Entry>) -> usize {
+    entries.sort_by_key(|entry| entry.tag);
+
+    let ifd_offset = out.len();
+    let mut extra_offset = ifd_offset + 2 + entries.len() * 12 + 4;
+    let mut extra = Vec::new();
+
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for entry in &entries {
+        out.extend_from_slice(&entry.tag.to_le_bytes());
+        out.extend_from_slice(&entry.kind.to_le_bytes());
+        out.extend_from_slice(&entry.count().to_le_bytes());
+
+        let mut value = entry.bytes();
+        if value.len() <= 4 {
+            value.resize(4, 0);
+            out.extend_from_slice(&value);
+        } else {
+            out.extend_from_slice(&(extra_offset as u32).to_le_bytes());
+            // Values stay word aligned
+            if value.len() % 2 == 1 {
+                value.push(0);
+            }
+            extra_offset += value.len();
+            extra.extend(value);
+        }
+    }
+
+    // Next IFD offset, patched when another page follows
+    out.extend_from_slice(&[0; 4]);
+    out.extend(extra);
+    ifd_offset
+}
+
+fn encode_page(out: &mut Vec<u8>, image: DynamicImageRef<'_>, page: usize, page_count: usize, options: &TiffOptions) -> io::Result<usize> {
+    let channels = image.channels() as usize;
+
+    // 16-bit and float images are written with 16-bit samples, in the file's
+    // little-endian byte order
+    let converted = match image.depth() {
+        SampleDepth::U8 => image.with_depth(SampleDepth::U8),
+        _ => image.with_depth(SampleDepth::U16),
+    };
+    let image = converted.as_ref();
+    let sample_bytes = if image.depth() == SampleDepth::U16 { 2 } else { 1 };
+    let bytes: Vec<u8> = if sample_bytes == 2 {
+        image.as_samples16().iter().flat_map(|sample| sample.to_le_bytes()).collect()
+    } else {
+        image.as_bytes().to_vec()
+    };
+
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let pixel_bytes = channels * sample_bytes;
+    let row_bytes = width * pixel_bytes;
+    let use_predictor = options.predictor && matches!(options.compression, TiffCompression::Lzw | TiffCompression::Deflate);
+
+    let (chunk_width, chunk_height) = match options.tile_size {
+        Some(size) => {
+            let size = (size.max(1) as usize).div_ceil(16) * 16;
+            (size, size)
+        }
+        None => (width, (STRIP_TARGET_BYTES / row_bytes.max(1)).clamp(1, height.max(1))),
+    };
+    let across = if options.tile_size.is_some() { width.div_ceil(chunk_width) } else { 1 };
+    let down = height.div_ceil(chunk_height);
+    let chunk_row_bytes = chunk_width * pixel_bytes;
+
+    let mut offsets = Vec::new();
+    let mut byte_counts = Vec::new();
+    for chunk_y in 0..down {
+        for chunk_x in 0..across {
+            // Tiles past the image edge are zero padded, strips just end early
+            let rows = if options.tile_size.is_some() { chunk_height } else { chunk_height.min(height - chunk_y * chunk_height) };
+            let mut chunk = vec![0u8; chunk_row_bytes * rows];
+            let x_start = chunk_x * chunk_row_bytes;
+            let copy_len = chunk_row_bytes.min(row_bytes - x_start);
+            for row in 0..rows {
+                let y = chunk_y * chunk_height + row;
+                if y >= height {
+                    break;
+                }
+                let source = y * row_bytes + x_start;
+                chunk[row * chunk_row_bytes..row * chunk_row_bytes + copy_len].copy_from_slice(&bytes[source..source + copy_len]);
+            }
+
+            if use_predictor {
+                for row in chunk.chunks_mut(chunk_row_bytes.max(1)) {
+                    if sample_bytes == 2 {
+                        for i in (channels..row.len() / 2).rev() {
+                            let value = u16::from_le_bytes([row[i * 2], row[i * 2 + 1]]);
+                            let left = u16::from_le_bytes([row[(i - channels) * 2], row[(i - channels) * 2 + 1]]);
+                            row[i * 2..i * 2 + 2].copy_from_slice(&value.wrapping_sub(left).to_le_bytes());
+                        }
+                    } else {
+                        for i in (channels..row.len()).rev() {
+                            row[i] = row[i].wrapping_sub(row[i - channels]);
+                        }
+                    }
+                }
+            }
+
+            let compressed = match options.compression {
+                TiffCompression::None => chunk,
+                TiffCompression::Lzw => lzw_encode(&chunk),
+                TiffCompression::Deflate => zlib_compress(&chunk, 6),
+                TiffCompression::PackBits => packbits_encode(&chunk, chunk_row_bytes),
+            };
+            offsets.push(out.len() as u32);
+            byte_counts.push(compressed.len() as u32);
+            out.extend(compressed);
+            if out.len() % 2 == 1 {
+                out.push(0);
+            }
+        }
+    }
+
+    let compression = match options.compression {
+        TiffCompression::None => COMPRESSION_NONE,
+        TiffCompression::Lzw => COMPRESSION_LZW,
+        TiffCompression::Deflate => COMPRESSION_DEFLATE,
+        TiffCompression::PackBits => COMPRESSION_PACKBITS,
+    };
+
+    let mut entries = vec![
+        Entry::long(TAG_IMAGE_WIDTH, vec![image.width()]),
+        Entry::long(TAG_IMAGE_LENGTH, vec![image.height()]),
+        Entry::short(TAG_BITS_PER_SAMPLE, vec![8 * sample_bytes as u32; channels]),
+        Entry::short(TAG_COMPRESSION, vec![compression]),
+        Entry::short(TAG_PHOTOMETRIC, vec![if channels < 3 { PHOTOMETRIC_BLACK_IS_ZERO } else { PHOTOMETRIC_RGB }]),
+        Entry::short(TAG_SAMPLES_PER_PIXEL, vec![channels as u32]),
+        Entry { tag: TAG_X_RESOLUTION, kind: TYPE_RATIONAL, values: vec![72, 1] },
+        Entry { tag: TAG_Y_RESOLUTION, kind: TYPE_RATIONAL, values: vec![72, 1] },
+        Entry::short(TAG_PLANAR_CONFIG, vec![1]),
+        // Inches
+        Entry::short(TAG_RESOLUTION_UNIT, vec![2]),
+    ];
+    if options.tile_size.is_some() {
+        entries.push(Entry::long(TAG_TILE_WIDTH, vec![chunk_width as u32]));
+        entries.push(Entry::long(TAG_TILE_LENGTH, vec![chunk_height as u32]));
+        entries.push(Entry::long(TAG_TILE_OFFSETS, offsets));
+        entries.push(Entry::long(TAG_TILE_BYTE_COUNTS, byte_counts));
+    } else {
+        entries.push(Entry::long(TAG_STRIP_OFFSETS, offsets));
+        entries.push(Entry::long(TAG_ROWS_PER_STRIP, vec![chunk_height as u32]));
+        entries.push(Entry::long(TAG_STRIP_BYTE_COUNTS, byte_counts));
+    }
+    if use_predictor {
+        entries.push(Entry::short(TAG_PREDICTOR, vec![2]));
+    }
+    if channels.is_multiple_of(2) {
+        entries.push(Entry::short(TAG_EXTRA_SAMPLES, vec![EXTRA_SAMPLE_UNASSOCIATED_ALPHA]));
+    }
+    if page_count > 1 {
+        // Marks a page of a multi-page document
+        entries.push(Entry::long(TAG_NEW_SUBFILE_TYPE, vec![2]));
+        entries.push(Entry::short(TAG_PAGE_NUMBER, vec![page as u32, page_count as u32]));
+    }
+
+    Ok(write_ifd(out, entries))
+}
+
+pub(crate) fn encode_pages(pages: &[DynamicImageRef<'_>], options: &TiffOptions) -> io::Result<Vec<u8>> {
+    if pages.is_empty() {
+        return Err(invalid_data("TIFF needs at least one page"));
+    }
+
+    let mut out = vec![b'I', b'I', 42, 0, 0, 0, 0, 0];
+    // Position of the offset that should point at the next IFD
+    let mut link = 4;
+    for (page, image) in pages.iter().enumerate() {
+        let ifd_offset = encode_page(&mut out, *image, page, pages.len(), options)?;
+        out[link..link + 4].copy_from_slice(&(ifd_offset as u32).to_le_bytes());
+        let entry_count = u16::from_le_bytes([out[ifd_offset], out[ifd_offset + 1]]) as usize;
+        link = ifd_offset + 2 + entry_count * 12;
+    }
+
+    if out.len() > u32::MAX as usize {
+        return Err(unsupported("TIFF files are limited to 4 GiB"));
+    }
+    Ok(out)
+}
+
+impl DynamicImage {
+    // First page of the file
+    pub fn open_tiff(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode_pages(&fs::read(path)?)?.swap_remove(0))
+    }
+
+    pub fn open_tiff_pages(path: &str) -> Result<Vec<DynamicImage>, ImageError> {
+        Ok(decode_pages(&fs::read(path)?)?)
+    }
+
+    pub fn save_tiff(&self, path: &str) -> Result<(), ImageError> {
+        self.save_tiff_with_options(path, &TiffOptions::default())
+    }
+
+    pub fn save_tiff_with_options(&self, path: &str, options: &TiffOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode_pages(&[self.as_ref()], options)?)?)
+    }
+
+    pub fn save_tiff_pages(path: &str, pages: &[DynamicImage], options: &TiffOptions) -> Result<(), ImageError> {
+        let pages: Vec<DynamicImageRef> = pages.iter().map(DynamicImage::as_ref).collect();
+        Ok(fs::write(path, encode_pages(&pages, options)?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn open_tiff(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_tiff(path)?.into_image())
+    }
+
+    pub fn save_tiff(&self, path: &str) -> Result<(), ImageError> {
+        self.save_tiff_with_options(path, &TiffOptions::default())
+    }
+
+    pub fn save_tiff_with_options(&self, path: &str, options: &TiffOptions) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode_pages(&[self.as_dynamic().as_ref()], options)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Where the pixel data of `tiff` starts
+    fn data_offset(field_count: usize) -> u32 {
+        (8 + 2 + field_count * 12 + 4) as u32
+    }
+
+    // A TIFF with a single IFD of LONG fields followed by `data`, fields with
+    // more than one value are stored after the data
+    fn tiff(big_endian: bool, fields: &[(u16, &[u32])], data: &[u8]) -> Vec<u8> {
+        let short = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let long = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+
+        let mut out = if big_endian { b"MM\0*".to_vec() } else { b"II*\0".to_vec() };
+        out.extend_from_slice(&long(8));
+        out.extend_from_slice(&short(fields.len() as u16));
+        let mut extra = Vec::new();
+        let extra_start = data_offset(fields.len()) as usize + data.len();
+        for &(tag, values) in fields {
+            out.extend_from_slice(&short(tag));
+            out.extend_from_slice(&short(TYPE_LONG));
+            out.extend_from_slice(&long(values.len() as u32));
+            if values.len() == 1 {
+                out.extend_from_slice(&long(values[0]));
+            } else {
+                out.extend_from_slice(&long((extra_start + extra.len()) as u32));
+                extra.extend(values.iter().flat_map(|&value| long(value)));
+            }
+        }
+        out.extend_from_slice(&long(0));
+        out.extend_from_slice(data);
+        out.extend_from_slice(&extra);
+        out
+    }
+
+    // A single strip image with `extra` fields, the strip being all of `data`
+    fn strip(size: (u32, u32), photometric: u32, bits: u32, samples: u32, extra: &[(u16, &[u32])], data: &[u8]) -> Vec<u8> {
+        let values = [size.0, size.1, photometric, samples, bits, data_offset(7 + extra.len()), data.len() as u32];
+        let tags = [
+            TAG_IMAGE_WIDTH,
+            TAG_IMAGE_LENGTH,
+            TAG_PHOTOMETRIC,
+            TAG_SAMPLES_PER_PIXEL,
+            TAG_BITS_PER_SAMPLE,
+            TAG_STRIP_OFFSETS,
+            TAG_STRIP_BYTE_COUNTS,
+        ];
+        let mut fields: Vec<(u16, &[u32])> = tags.iter().zip(&values).map(|(&tag, value)| (tag, std::slice::from_ref(value))).collect();
+        fields.extend_from_slice(extra);
+        tiff(false, &fields, data)
+    }
+
+    #[test]
+    fn lzw_known_answer() {
+        // The example from section 13 of the TIFF 6.0 specification, as 9-bit codes
+        let codes = [LZW_CLEAR, 7, 258, 8, 8, 258, 6, 6, LZW_END];
+        let mut data = vec![0u8; (codes.len() * 9).div_ceil(8)];
+        for (i, &code) in codes.iter().enumerate() {
+            for bit in 0..9 {
+                if code >> (8 - bit) & 1 == 1 {
+                    data[(i * 9 + bit) / 8] |= 0x80 >> ((i * 9 + bit) % 8);
+                }
+            }
+        }
+        assert_eq!(lzw_decode(&data, 9).unwrap(), [7, 7, 7, 8, 8, 7, 7, 6, 6]);
+    }
+
+    #[test]
+    fn packbits_known_answer() {
+        // The example from section 9 of the TIFF 6.0 specification
+        let data = [0xfe, 0xaa, 0x02, 0x80, 0x00, 0x2a, 0xfd, 0xaa, 0x03, 0x80, 0x00, 0x2a, 0x22, 0xf7, 0xaa];
+        let expected = [
+            &[0xaa; 3][..],
+            &[0x80, 0x00, 0x2a],
+            &[0xaa; 4],
+            &[0x80, 0x00, 0x2a, 0x22],
+            &[0xaa; 10],
+        ]
+        .concat();
+        assert_eq!(packbits_decode(&data, 24), expected);
+        assert_eq!(packbits_decode(&packbits_encode(&expected, 24), 24), expected);
+    }
+
+    #[test]
+    fn big_endian_files_read_16_bit_samples_in_their_byte_order() {
+        let fields: [(u16, &[u32]); 6] = [
+            (TAG_IMAGE_WIDTH, &[2]),
+            (TAG_IMAGE_LENGTH, &[1]),
+            (TAG_BITS_PER_SAMPLE, &[16]),
+            (TAG_PHOTOMETRIC, &[PHOTOMETRIC_BLACK_IS_ZERO]),
+            (TAG_STRIP_OFFSETS, &[data_offset(6)]),
+            (TAG_STRIP_BYTE_COUNTS, &[4]),
+        ];
+        let image = decode_pages(&tiff(true, &fields, &[0x12, 0x34, 0xab, 0xcd])).unwrap().remove(0);
+        assert_eq!(image.as_ref().as_samples16(), [0x1234, 0xabcd]);
+    }
+
+    #[test]
+    fn photometric_interpretation_maps_the_samples() {
+        let image = decode_pages(&strip((2, 1), PHOTOMETRIC_WHITE_IS_ZERO, 8, 1, &[], &[0, 200])).unwrap().remove(0);
+        assert_eq!(image.as_bytes(), [255, 55]);
+
+        // 1-bit indices into a red and green color map of 16-bit entries
+        let color_map = [0xffff, 0, 0, 0xffff, 0, 0];
+        let bytes = strip((2, 1), PHOTOMETRIC_PALETTE, 1, 1, &[(TAG_COLOR_MAP, &color_map)], &[0b0100_0000]);
+        assert_eq!(decode_pages(&bytes).unwrap()[0].as_bytes(), [255, 0, 0, 0, 255, 0]);
+
+        let bytes = strip((1, 1), PHOTOMETRIC_SEPARATED, 8, 4, &[], &[0, 255, 255, 0]);
+        assert_eq!(decode_pages(&bytes).unwrap()[0].as_bytes(), [255, 0, 0]);
+    }
+
+    #[test]
+    fn horizontal_predictor_adds_up_each_pixel() {
+        let bytes = strip((3, 1), PHOTOMETRIC_BLACK_IS_ZERO, 8, 1, &[(TAG_PREDICTOR, &[2])], &[10, 5, 5]);
+        assert_eq!(decode_pages(&bytes).unwrap()[0].as_bytes(), [10, 15, 20]);
+
+        // Differences are taken between the same sample of neighbouring pixels
+        let bytes = strip((2, 1), PHOTOMETRIC_RGB, 8, 3, &[(TAG_PREDICTOR, &[2])], &[10, 20, 30, 1, 2, 3]);
+        assert_eq!(decode_pages(&bytes).unwrap()[0].as_bytes(), [10, 20, 30, 11, 22, 33]);
+    }
+
+    #[test]
+    fn associated_alpha_is_unpremultiplied() {
+        let bytes = strip((1, 1), PHOTOMETRIC_RGB, 8, 4, &[(TAG_EXTRA_SAMPLES, &[EXTRA_SAMPLE_ASSOCIATED_ALPHA])], &[64, 32, 0, 128]);
+        assert_eq!(decode_pages(&bytes).unwrap()[0].as_bytes(), [128, 64, 0, 128]);
+    }
+
+    #[test]
+    fn planar_images_have_a_strip_per_sample() {
+        let offset = data_offset(9);
+        let fields: [(u16, &[u32]); 9] = [
+            (TAG_IMAGE_WIDTH, &[2]),
+            (TAG_IMAGE_LENGTH, &[1]),
+            (TAG_BITS_PER_SAMPLE, &[8, 8, 8]),
+            (TAG_PHOTOMETRIC, &[PHOTOMETRIC_RGB]),
+            (TAG_STRIP_OFFSETS, &[offset, offset + 2, offset + 4]),
+            (TAG_SAMPLES_PER_PIXEL, &[3]),
+            (TAG_ROWS_PER_STRIP, &[1]),
+            (TAG_STRIP_BYTE_COUNTS, &[2, 2, 2]),
+            (TAG_PLANAR_CONFIG, &[2]),
+        ];
+        let image = decode_pages(&tiff(false, &fields, &[1, 2, 3, 4, 5, 6])).unwrap().remove(0);
+        assert_eq!(image.as_bytes(), [1, 3, 5, 2, 4, 6]);
+    }
+
+    #[test]
+    fn pages_keep_their_own_size_layout_and_depth() {
+        let gray = DynamicImage::from_raw_parts16(1, 20, 18, (0..360).map(|i| i * 181).collect());
+        let rgb = DynamicImage::from_raw_parts(3, 3, 2, (0..18).collect());
+        let options = TiffOptions { tile_size: Some(16), ..TiffOptions::default() };
+        let bytes = encode_pages(&[gray.as_ref(), rgb.as_ref()], &options).unwrap();
+
+        let pages = decode_pages(&bytes).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].as_ref().as_samples16(), gray.as_ref().as_samples16());
+        assert_eq!((pages[1].channels(), pages[1].as_bytes()), (3, rgb.as_bytes()));
+    }
+
+    #[test]
+    fn huge_header_is_rejected_before_allocating() {
+        let fields: [(u16, &[u32]); 7] = [
+            (TAG_IMAGE_WIDTH, &[1 << 31]),
+            (TAG_IMAGE_LENGTH, &[1 << 31]),
+            (TAG_BITS_PER_SAMPLE, &[8]),
+            (TAG_COMPRESSION, &[COMPRESSION_NONE]),
+            (TAG_PHOTOMETRIC, &[PHOTOMETRIC_BLACK_IS_ZERO]),
+            (TAG_STRIP_OFFSETS, &[data_offset(7)]),
+            (TAG_STRIP_BYTE_COUNTS, &[u32::MAX]),
+        ];
+        let bytes = tiff(false, &fields, &[]);
+        assert_eq!(bytes.len(), 98);
+        assert_eq!(decode_pages(&bytes).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn lzw_stops_at_the_chunk_length() {
+        let data = lzw_encode(&[7; 100_000]);
+        assert_eq!(lzw_decode(&data, 100_000).unwrap(), vec![7; 100_000]);
+        assert!(lzw_decode(&data, 10).unwrap().len() < 1000);
+    }
+
+    #[test]
+    fn header_larger_than_payload_is_rejected() {
+        let bytes = strip((20000, 20000), PHOTOMETRIC_BLACK_IS_ZERO, 8, 1, &[(TAG_COMPRESSION, &[COMPRESSION_PACKBITS])], &[0x81, 0]);
+        assert_eq!(decode_pages(&bytes).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,294 @@
+mod encoder;
+mod lossless;
+mod lossy;
+
+use std::fs;
+use std::io;
+
+use super::{invalid_data, unsupported};
+use crate::{DynamicImage, DynamicImageRef, Image, ImageError, Pixel};
+
+const FLAG_ANIMATION: u8 = 0x02;
+
+const ALPHA_UNCOMPRESSED: u8 = 0;
+const ALPHA_LOSSLESS: u8 = 1;
+
+const FILTER_NONE: u8 = 0;
+const FILTER_HORIZONTAL: u8 = 1;
+const FILTER_VERTICAL: u8 = 2;
+
+struct Chunk<'a> {
+    id: [u8; 4],
+    data: &'a [u8],
+}
+
+fn read_chunks(bytes: &[u8]) -> io::Result<Vec<Chunk<'_>>> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return Err(invalid_data("not a WebP file"));
+    }
+    // Trailing data after the RIFF payload is ignored
+    let riff_size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let end = (riff_size.saturating_add(8)).min(bytes.len());
+
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= end {
+        let id = [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+        let size = u32::from_le_bytes([bytes[pos + 4], bytes[pos + 5], bytes[pos + 6], bytes[pos + 7]]) as usize;
+        let data = bytes.get(pos + 8..pos + 8 + size).ok_or_else(|| invalid_data("truncated WebP chunk"))?;
+        chunks.push(Chunk { id, data });
+        // Chunks are padded to an even size
+        pos += 8 + size + (size & 1);
+    }
+    Ok(chunks)
+}
+
+fn unfilter_alpha(alpha: &mut [u8], width: usize, method: u8) {
+    for y in 0..alpha.len() / width {
+        for x in 0..width {
+            let i = y * width + x;
+            let predictor = match (x, y) {
+                (0, 0) => 0,
+                (_, 0) => alpha[i - 1],
+                (0, _) => alpha[i - width],
+                _ => match method {
+                    FILTER_HORIZONTAL => alpha[i - 1],
+                    FILTER_VERTICAL => alpha[i - width],
+                    _ => {
+                        let gradient = alpha[i - 1] as i32 + alpha[i - width] as i32 - alpha[i - width - 1] as i32;
+                        gradient.clamp(0, 255) as u8
+                    }
+                },
+            };
+            alpha[i] = alpha[i].wrapping_add(predictor);
+        }
+    }
+}
+
+fn decode_alpha(data: &[u8], width: usize, height: usize) -> io::Result<Vec<u8>> {
+    let header = *data.first().ok_or_else(|| invalid_data("empty WebP alpha chunk"))?;
+    let compression = header & 0x03;
+    let filter = (header >> 2) & 0x03;
+
+    let mut alpha = match compression {
+        ALPHA_UNCOMPRESSED => data
+            .get(1..1 + width * height)
+            .ok_or_else(|| invalid_data("truncated WebP alpha chunk"))?
+            .to_vec(),
+        ALPHA_LOSSLESS => lossless::decode_alpha_stream(&data[1..], width, height)?,
+        _ => return Err(invalid_data("invalid WebP alpha compression")),
+    };
+
+    if filter != FILTER_NONE {
+        unfilter_alpha(&mut alpha, width, filter);
+    }
+    Ok(alpha)
+}
+
+// Builds an RGBA image, reduced to RGB when every pixel is opaque
+fn build_image(width: u32, height: u32, rgba: Vec<u8>) -> DynamicImage {
+    if rgba.chunks_exact(4).all(|pixel| pixel[3] == 255) {
+        let data = rgba.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect();
+        return DynamicImage::from_raw_parts(3, width, height, data);
+    }
+    DynamicImage::from_raw_parts(4, width, height, rgba)
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> io::Result<DynamicImage> {
+    let chunks = read_chunks(bytes)?;
+
+    let mut canvas = None;
+    let mut alpha_chunk = None;
+    for chunk in &chunks {
+        match &chunk.id {
+            b"VP8X" => {
+                let data = chunk.data;
+                if data.len() < 10 {
+                    return Err(invalid_data("truncated WebP extended header"));
+                }
+                if data[0] & FLAG_ANIMATION != 0 {
+                    return Err(unsupported("animated WebP images are not supported"));
+                }
+                let width = u32::from_le_bytes([data[4], data[5], data[6], 0]) + 1;
+                let height = u32::from_le_bytes([data[7], data[8], data[9], 0]) + 1;
+                canvas = Some((width, height));
+            }
+            b"ALPH" => alpha_chunk = Some(chunk.data),
+            b"VP8L" => {
+                let (width, height, argb) = lossless::decode(chunk.data)?;
+                if canvas.is_some_and(|canvas| canvas != (width, height)) {
+                    return Err(invalid_data("WebP canvas size does not match the image"));
+                }
+                let rgba = argb.iter().flat_map(|&pixel| pixel.rotate_left(8).to_be_bytes()).collect();
+                return Ok(build_image(width, height, rgba));
+            }
+            b"VP8 " => {
+                let (width, height, rgb) = lossy::decode(chunk.data)?;
+                if canvas.is_some_and(|canvas| canvas != (width, height)) {
+                    return Err(invalid_data("WebP canvas size does not match the image"));
+                }
+                let alpha = match alpha_chunk {
+                    Some(data) => decode_alpha(data, width as usize, height as usize)?,
+                    None => vec![255; width as usize * height as usize],
+                };
+                let rgba = rgb.chunks_exact(3).zip(alpha).flat_map(|(p, a)| [p[0], p[1], p[2], a]).collect();
+                return Ok(build_image(width, height, rgba));
+            }
+            _ => {}
+        }
+    }
+    Err(invalid_data("WebP file contains no image data"))
+}
+
+// Always writes lossless images
+pub(crate) fn encode(image: DynamicImageRef<'_>) -> io::Result<Vec<u8>> {
+    let mut bitstream = encoder::encode(image)?;
+    let size = bitstream.len() as u32;
+    if bitstream.len() % 2 == 1 {
+        bitstream.push(0);
+    }
+
+    let mut out = Vec::with_capacity(bitstream.len() + 20);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(bitstream.len() as u32 + 12).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(b"VP8L");
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&bitstream);
+    Ok(out)
+}
+
+impl DynamicImage {
+    pub fn open_webp(path: &str) -> Result<DynamicImage, ImageError> {
+        Ok(decode(&fs::read(path)?)?)
+    }
+
+    pub fn save_webp(&self, path: &str) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_ref())?)?)
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn open_webp(path: &str) -> Result<Image<P>, ImageError> {
+        Ok(DynamicImage::open_webp(path)?.into_image())
+    }
+
+    pub fn save_webp(&self, path: &str) -> Result<(), ImageError> {
+        Ok(fs::write(path, encode(self.as_dynamic().as_ref())?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for (id, data) in chunks {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat()
+    }
+
+    fn vp8x(flags: u8, width: u32, height: u32) -> Vec<u8> {
+        let mut data = vec![flags, 0, 0, 0];
+        data.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        data
+    }
+
+    #[test]
+    fn encoder_wraps_a_vp8l_chunk_in_riff() {
+        let image = DynamicImage::from_raw_parts(3, 3, 2, vec![9; 18]);
+        let bytes = encode(image.as_ref()).unwrap();
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"WEBPVP8L");
+        assert_eq!(bytes.len() % 2, 0);
+
+        // The VP8L header holds the signature, 14-bit sizes minus one and the alpha hint
+        let size = u32::from_le_bytes(bytes[16..20].try_into().unwrap()) as usize;
+        assert!(size == bytes.len() - 20 || size == bytes.len() - 21);
+        let header = u32::from_le_bytes(bytes[21..25].try_into().unwrap());
+        assert_eq!(bytes[20], lossless::SIGNATURE);
+        assert_eq!((header & 0x3fff, header >> 14 & 0x3fff, header >> 28 & 1), (2, 1, 0));
+    }
+
+    #[test]
+    fn palette_and_predicted_images_decode_exactly() {
+        // 16 colors take the color indexing path, 4096 the predictor path
+        for side in [4usize, 64] {
+            let data: Vec<u8> = (0..side * side).flat_map(|i| [(i * 7) as u8, (i >> 4) as u8, ((i * 3) >> 2) as u8]).collect();
+            let image = DynamicImage::from_raw_parts(3, side as u32, side as u32, data.clone());
+            let decoded = decode(&encode(image.as_ref()).unwrap()).unwrap();
+            assert_eq!((decoded.channels(), decoded.as_bytes()), (3, &data[..]));
+        }
+    }
+
+    #[test]
+    fn transparency_keeps_four_channels() {
+        let data = vec![10, 20, 30, 255, 40, 50, 60, 0, 70, 80, 90, 128];
+        let image = DynamicImage::from_raw_parts(4, 3, 1, data.clone());
+        let bytes = encode(image.as_ref()).unwrap();
+        assert_eq!(bytes[24] >> 4 & 1, 1);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!((decoded.channels(), decoded.as_bytes()), (4, &data[..]));
+
+        // Fully opaque alpha is dropped
+        let opaque = DynamicImage::from_raw_parts(4, 1, 1, vec![1, 2, 3, 255]);
+        assert_eq!(decode(&encode(opaque.as_ref()).unwrap()).unwrap().as_bytes(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn unknown_and_odd_sized_chunks_are_skipped() {
+        let image = DynamicImage::from_raw_parts(1, 2, 2, vec![0, 85, 170, 255]);
+        let bytes = encode(image.as_ref()).unwrap();
+        let bitstream = &bytes[20..20 + u32::from_le_bytes(bytes[16..20].try_into().unwrap()) as usize];
+
+        let mut file = riff(&[(b"ICCP", &[1, 2, 3]), (b"VP8X", &vp8x(0, 2, 2)), (b"VP8L", bitstream)]);
+        file.extend_from_slice(b"trailing");
+        assert_eq!(decode(&file).unwrap().as_bytes(), [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255]);
+    }
+
+    #[test]
+    fn extended_header_is_checked() {
+        let image = DynamicImage::from_raw_parts(3, 2, 2, vec![7; 12]);
+        let bytes = encode(image.as_ref()).unwrap();
+        let bitstream = &bytes[20..];
+
+        let animated = riff(&[(b"VP8X", &vp8x(FLAG_ANIMATION, 2, 2)), (b"VP8L", bitstream)]);
+        assert_eq!(decode(&animated).unwrap_err().kind(), io::ErrorKind::Unsupported);
+
+        let mismatched = riff(&[(b"VP8X", &vp8x(0, 3, 2)), (b"VP8L", bitstream)]);
+        assert_eq!(decode(&mismatched).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        assert_eq!(decode(&riff(&[(b"EXIF", &[0; 4])])).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decode(&bytes[..bytes.len() - 2]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn alpha_filters_predict_from_their_neighbours() {
+        // Uncompressed 3x2 alpha plane
+        let residuals = [10, 5, 1, 20, 1, 1];
+        let plane = |filter: u8| {
+            let data = [&[ALPHA_UNCOMPRESSED | filter << 2][..], &residuals].concat();
+            decode_alpha(&data, 3, 2).unwrap()
+        };
+        assert_eq!(plane(FILTER_NONE), residuals);
+        // The first row and column always predict from the left and from above
+        assert_eq!(plane(FILTER_HORIZONTAL), [10, 15, 16, 30, 31, 32]);
+        assert_eq!(plane(FILTER_VERTICAL), [10, 15, 16, 30, 16, 17]);
+        // Gradient: left + above - above left
+        assert_eq!(plane(3), [10, 15, 16, 30, 36, 38]);
+
+        let mut wrapping = vec![200, 100, 0, 0];
+        unfilter_alpha(&mut wrapping, 2, FILTER_HORIZONTAL);
+        assert_eq!(wrapping, [200, 44, 200, 200]);
+        assert_eq!(decode_alpha(&[ALPHA_UNCOMPRESSED, 1], 2, 1).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
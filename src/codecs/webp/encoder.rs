@@ -0,0 +1,455 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use super::super::huffman::code_lengths;
+use super::super::unsupported;
+use super::super::zlib::{encode_code_lengths, reversed_codes, BitWriter};
+use super::lossless::{
+    cache_hash, per_channel, predict_at, CODE_LENGTH_ORDER, COLOR_INDEXING_TRANSFORM, DISTANCE_MAP,
+    NUM_DISTANCE_CODES, NUM_LENGTH_CODES, NUM_LITERALS, PREDICTOR_TRANSFORM, SIGNATURE, SUBTRACT_GREEN_TRANSFORM,
+};
+use crate::DynamicImageRef;
+
+const MAX_DIMENSION: u32 = 1 << 14;
+const PREDICTOR_BITS: u32 = 4;
+const PREDICTOR_MODES: u32 = 14;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 4096;
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 16;
+// Largest distance the 40 distance prefix codes can express
+const MAX_DISTANCE: usize = (1 << 20) - DISTANCE_MAP.len();
+const MAX_CACHE_BITS: u32 = 10;
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u32),
+    Cache(u16),
+    Copy { length: u16, distance_code: u32 },
+}
+
+fn sub_pixels(a: u32, b: u32) -> u32 {
+    per_channel(a, b, u8::wrapping_sub)
+}
+
+// Splits a length or distance code into (prefix symbol, extra bit count, extra bits)
+fn prefix_encode(value: usize) -> (usize, u32, u32) {
+    let value = value - 1;
+    if value < 4 {
+        return (value, 0, 0);
+    }
+    let highest_bit = usize::BITS - 1 - value.leading_zeros();
+    let second_bit = (value >> (highest_bit - 1)) & 1;
+    let extra_bits = highest_bit - 1;
+    (2 * highest_bit as usize + second_bit, extra_bits, (value & ((1 << extra_bits) - 1)) as u32)
+}
+
+// Short (dx, dy) offsets get their own distance codes, everything else is shifted past them
+fn distance_code(distance: usize, width: usize, short_codes: &HashMap<(isize, isize), u32>) -> u32 {
+    let dy = (distance / width) as isize;
+    let dx = (distance % width) as isize;
+    for (x, y) in [(dx, dy), (dx - width as isize, dy + 1)] {
+        if let Some(&code) = short_codes.get(&(x, y)) {
+            return code;
+        }
+    }
+    (distance + DISTANCE_MAP.len()) as u32
+}
+
+fn backward_references(pixels: &[u32], width: usize) -> Vec<Token> {
+    let short_codes: HashMap<(isize, isize), u32> = DISTANCE_MAP
+        .iter()
+        .enumerate()
+        .map(|(i, &(dx, dy))| ((dx as isize, dy as isize), i as u32 + 1))
+        .collect();
+
+    let hash = |i: usize| {
+        (pixels[i].wrapping_mul(0x9e3779b1) ^ pixels[i + 1].wrapping_mul(0x85ebca6b)).wrapping_mul(0x2545f491) >> (32 - HASH_BITS)
+    };
+    let match_length = |a: usize, b: usize| {
+        let limit = (pixels.len() - a).min(MAX_MATCH);
+        (0..limit).take_while(|&k| pixels[a + k] == pixels[b + k]).count()
+    };
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut chain = vec![usize::MAX; pixels.len()];
+    let insert = |i: usize, head: &mut Vec<usize>, chain: &mut Vec<usize>| {
+        if i + 1 < pixels.len() {
+            let h = hash(i) as usize;
+            chain[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < pixels.len() {
+        let mut best = (0, 0);
+
+        // The previous pixel and the one above are cheap to code, so try them first
+        for distance in [1, width] {
+            if distance <= i {
+                let length = match_length(i, i - distance);
+                if length > best.0 {
+                    best = (length, distance);
+                }
+            }
+        }
+        if i + 1 < pixels.len() {
+            let mut candidate = head[hash(i) as usize];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || i - candidate > MAX_DISTANCE {
+                    break;
+                }
+                let length = match_length(i, candidate);
+                if length > best.0 {
+                    best = (length, i - candidate);
+                }
+                candidate = chain[candidate];
+            }
+        }
+
+        let (length, distance) = best;
+        if length >= MIN_MATCH {
+            tokens.push(Token::Copy { length: length as u16, distance_code: distance_code(distance, width, &short_codes) });
+            for j in i..i + length {
+                insert(j, &mut head, &mut chain);
+            }
+            i += length;
+        } else {
+            tokens.push(Token::Literal(pixels[i]));
+            insert(i, &mut head, &mut chain);
+            i += 1;
+        }
+    }
+    tokens
+}
+
+// Replaces literals that the decoder's color cache would already hold
+fn apply_cache(tokens: &[Token], pixels: &[u32], bits: u32) -> Vec<Token> {
+    if bits == 0 {
+        return tokens.to_vec();
+    }
+    let mut cache = vec![0u32; 1 << bits];
+    let mut pos = 0;
+    tokens
+        .iter()
+        .map(|&token| match token {
+            Token::Literal(pixel) => {
+                pos += 1;
+                let slot = cache_hash(pixel, bits);
+                if cache[slot] == pixel {
+                    Token::Cache(slot as u16)
+                } else {
+                    cache[slot] = pixel;
+                    token
+                }
+            }
+            Token::Copy { length, .. } => {
+                for &pixel in &pixels[pos..pos + length as usize] {
+                    cache[cache_hash(pixel, bits)] = pixel;
+                }
+                pos += length as usize;
+                token
+            }
+            Token::Cache(_) => token,
+        })
+        .collect()
+}
+
+struct Histograms {
+    // Green with lengths and cache indices, red, blue, alpha, distance
+    counts: [Vec<u32>; 5],
+    // Extra bits spent on lengths and distances
+    extra_bits: u64,
+}
+
+fn histograms(tokens: &[Token], cache_bits: u32) -> Histograms {
+    let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+    let mut counts = [
+        vec![0u32; NUM_LITERALS + NUM_LENGTH_CODES + cache_size],
+        vec![0u32; NUM_LITERALS],
+        vec![0u32; NUM_LITERALS],
+        vec![0u32; NUM_LITERALS],
+        vec![0u32; NUM_DISTANCE_CODES],
+    ];
+    let mut extra_bits = 0u64;
+
+    for token in tokens {
+        match *token {
+            Token::Literal(pixel) => {
+                counts[0][((pixel >> 8) & 0xff) as usize] += 1;
+                counts[1][((pixel >> 16) & 0xff) as usize] += 1;
+                counts[2][(pixel & 0xff) as usize] += 1;
+                counts[3][(pixel >> 24) as usize] += 1;
+            }
+            Token::Cache(slot) => counts[0][NUM_LITERALS + NUM_LENGTH_CODES + slot as usize] += 1,
+            Token::Copy { length, distance_code } => {
+                let (length_prefix, length_extra, _) = prefix_encode(length as usize);
+                let (distance_prefix, distance_extra, _) = prefix_encode(distance_code as usize);
+                counts[0][NUM_LITERALS + length_prefix] += 1;
+                counts[4][distance_prefix] += 1;
+                extra_bits += (length_extra + distance_extra) as u64;
+            }
+        }
+    }
+    Histograms { counts, extra_bits }
+}
+
+struct PrefixCode {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+    // A code with one used symbol is written with zero bits per symbol
+    single: bool,
+}
+
+impl PrefixCode {
+    fn new(counts: &[u32], max_bits: u8) -> PrefixCode {
+        let lengths = code_lengths(counts, max_bits);
+        let codes = reversed_codes(&lengths);
+        let single = lengths.iter().filter(|&&len| len > 0).count() <= 1;
+        PrefixCode { lengths, codes, single }
+    }
+
+    fn cost(&self, counts: &[u32]) -> u64 {
+        if self.single {
+            return 0;
+        }
+        counts.iter().zip(&self.lengths).map(|(&count, &len)| count as u64 * len as u64).sum()
+    }
+
+    fn write_symbol(&self, writer: &mut BitWriter, symbol: usize) {
+        if !self.single {
+            writer.write(self.codes[symbol] as u32, self.lengths[symbol] as u32);
+        }
+    }
+
+    fn write_header(&self, writer: &mut BitWriter) {
+        let used: Vec<usize> = (0..self.lengths.len()).filter(|&i| self.lengths[i] > 0).collect();
+
+        if used.len() <= 2 && used.iter().all(|&symbol| symbol < NUM_LITERALS) {
+            // Simple code, an unused alphabet is written as the lone symbol 0
+            let first = used.first().copied().unwrap_or(0);
+            writer.write(1, 1);
+            writer.write(used.len().saturating_sub(1) as u32, 1);
+            if first < 2 {
+                writer.write(0, 1);
+                writer.write(first as u32, 1);
+            } else {
+                writer.write(1, 1);
+                writer.write(first as u32, 8);
+            }
+            if let Some(&second) = used.get(1) {
+                writer.write(second as u32, 8);
+            }
+            return;
+        }
+
+        let encoded = encode_code_lengths(&self.lengths);
+        let mut counts = [0u32; 19];
+        for &(symbol, _) in &encoded {
+            counts[symbol as usize] += 1;
+        }
+        // Code length code lengths are stored in three bits
+        let code_length_code = PrefixCode::new(&counts, 7);
+        let count = CODE_LENGTH_ORDER.iter().rposition(|&symbol| code_length_code.lengths[symbol] > 0).map_or(0, |i| i + 1).max(4);
+
+        writer.write(0, 1);
+        writer.write(count as u32 - 4, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..count] {
+            writer.write(code_length_code.lengths[symbol] as u32, 3);
+        }
+        // Code lengths are given for the whole alphabet
+        writer.write(0, 1);
+
+        for (symbol, extra) in encoded {
+            code_length_code.write_symbol(writer, symbol as usize);
+            match symbol {
+                16 => writer.write(extra as u32, 2),
+                17 => writer.write(extra as u32, 3),
+                18 => writer.write(extra as u32, 7),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Writes an entropy coded image, picking the color cache size that codes smallest
+fn write_entropy_image(writer: &mut BitWriter, pixels: &[u32], width: usize, is_main: bool) {
+    let references = backward_references(pixels, width);
+
+    let mut best: Option<(u64, u32, Vec<Token>, Vec<PrefixCode>)> = None;
+    for cache_bits in 0..=MAX_CACHE_BITS {
+        let tokens = apply_cache(&references, pixels, cache_bits);
+        let histograms = histograms(&tokens, cache_bits);
+        let codes: Vec<PrefixCode> = histograms.counts.iter().map(|counts| PrefixCode::new(counts, 15)).collect();
+        let cost = histograms.extra_bits + codes.iter().zip(&histograms.counts).map(|(code, counts)| code.cost(counts)).sum::<u64>();
+        if best.as_ref().is_none_or(|(best_cost, ..)| cost < *best_cost) {
+            best = Some((cost, cache_bits, tokens, codes));
+        }
+    }
+    let (_, cache_bits, tokens, codes) = best.expect("at least one cache size is tried");
+
+    if cache_bits > 0 {
+        writer.write(1, 1);
+        writer.write(cache_bits, 4);
+    } else {
+        writer.write(0, 1);
+    }
+    if is_main {
+        // A single group of prefix codes for the whole image
+        writer.write(0, 1);
+    }
+    for code in &codes {
+        code.write_header(writer);
+    }
+
+    for token in tokens {
+        match token {
+            Token::Literal(pixel) => {
+                codes[0].write_symbol(writer, ((pixel >> 8) & 0xff) as usize);
+                codes[1].write_symbol(writer, ((pixel >> 16) & 0xff) as usize);
+                codes[2].write_symbol(writer, (pixel & 0xff) as usize);
+                codes[3].write_symbol(writer, (pixel >> 24) as usize);
+            }
+            Token::Cache(slot) => codes[0].write_symbol(writer, NUM_LITERALS + NUM_LENGTH_CODES + slot as usize),
+            Token::Copy { length, distance_code } => {
+                let (prefix, extra_bits, extra) = prefix_encode(length as usize);
+                codes[0].write_symbol(writer, NUM_LITERALS + prefix);
+                writer.write(extra, extra_bits);
+                let (prefix, extra_bits, extra) = prefix_encode(distance_code as usize);
+                codes[4].write_symbol(writer, prefix);
+                writer.write(extra, extra_bits);
+            }
+        }
+    }
+}
+
+// Packs palette indices, several per pixel for small palettes
+fn color_indexing(writer: &mut BitWriter, pixels: &[u32], width: usize, palette: &[u32]) -> (Vec<u32>, usize) {
+    let deltas: Vec<u32> = (0..palette.len()).map(|i| if i == 0 { palette[0] } else { sub_pixels(palette[i], palette[i - 1]) }).collect();
+    writer.write(1, 1);
+    writer.write(COLOR_INDEXING_TRANSFORM, 2);
+    writer.write(palette.len() as u32 - 1, 8);
+    write_entropy_image(writer, &deltas, palette.len(), false);
+
+    let bits = match palette.len() {
+        0..=2 => 3,
+        3..=4 => 2,
+        5..=16 => 1,
+        _ => 0,
+    };
+    let index_bits = 8 >> bits;
+    let packed_width = width.div_ceil(1 << bits);
+    let lookup: HashMap<u32, u32> = palette.iter().enumerate().map(|(i, &color)| (color, i as u32)).collect();
+
+    let mut packed = Vec::with_capacity(packed_width * pixels.len() / width);
+    for row in pixels.chunks_exact(width) {
+        for group in row.chunks(1 << bits) {
+            let value = group.iter().enumerate().fold(0, |acc, (k, pixel)| acc | lookup[pixel] << (k as u32 * index_bits));
+            packed.push(value << 8);
+        }
+    }
+    (packed, packed_width)
+}
+
+fn predictor_residuals(writer: &mut BitWriter, pixels: &[u32], width: usize) -> Vec<u32> {
+    let height = pixels.len() / width;
+    let block = 1usize << PREDICTOR_BITS;
+    let blocks_across = width.div_ceil(block);
+    let blocks_down = height.div_ceil(block);
+
+    // Picks the mode with the smallest residuals in each block
+    let mut modes = Vec::with_capacity(blocks_across * blocks_down);
+    for block_y in 0..blocks_down {
+        for block_x in 0..blocks_across {
+            let mut best = (u64::MAX, 0);
+            for mode in 0..PREDICTOR_MODES {
+                let mut cost = 0u64;
+                for y in block_y * block..((block_y + 1) * block).min(height) {
+                    for x in block_x * block..((block_x + 1) * block).min(width) {
+                        let index = y * width + x;
+                        let residual = sub_pixels(pixels[index], predict_at(pixels, index, width, mode));
+                        cost += residual.to_le_bytes().iter().map(|&byte| (byte as i8).unsigned_abs() as u64).sum::<u64>();
+                    }
+                }
+                if cost < best.0 {
+                    best = (cost, mode);
+                }
+            }
+            modes.push(best.1);
+        }
+    }
+
+    writer.write(1, 1);
+    writer.write(PREDICTOR_TRANSFORM, 2);
+    writer.write(PREDICTOR_BITS - 2, 3);
+    let mode_image: Vec<u32> = modes.iter().map(|&mode| 0xff000000 | mode << 8).collect();
+    write_entropy_image(writer, &mode_image, blocks_across, false);
+
+    (0..pixels.len())
+        .map(|index| {
+            let (x, y) = (index % width, index / width);
+            let mode = modes[(y / block) * blocks_across + x / block];
+            sub_pixels(pixels[index], predict_at(pixels, index, width, mode))
+        })
+        .collect()
+}
+
+// Encodes a full VP8L bitstream
+pub(super) fn encode(image: DynamicImageRef<'_>) -> io::Result<Vec<u8>> {
+    let rgb = image.with_rgb8();
+    let image = rgb.as_ref();
+    let channels = image.channels() as usize;
+    if image.width() == 0 || image.height() == 0 || image.width() > MAX_DIMENSION || image.height() > MAX_DIMENSION {
+        return Err(unsupported("WebP images must be between 1 and 16384 pixels wide and high"));
+    }
+
+    let width = image.width() as usize;
+    let mut pixels: Vec<u32> = image
+        .as_bytes()
+        .chunks_exact(channels)
+        .map(|p| {
+            let alpha = if channels == 4 { p[3] } else { 255 };
+            u32::from_be_bytes([alpha, p[0], p[1], p[2]])
+        })
+        .collect();
+    let has_alpha = pixels.iter().any(|&pixel| pixel >> 24 != 255);
+
+    let mut writer = BitWriter::new();
+    writer.write(SIGNATURE as u32, 8);
+    writer.write(image.width() - 1, 14);
+    writer.write(image.height() - 1, 14);
+    writer.write(has_alpha as u32, 1);
+    writer.write(0, 3);
+
+    // Images with at most 256 colors are coded as palette indices
+    let mut colors = HashSet::new();
+    for &pixel in &pixels {
+        if colors.insert(pixel) && colors.len() > 256 {
+            colors.clear();
+            break;
+        }
+    }
+    let mut palette: Vec<u32> = colors.into_iter().collect();
+
+    let mut coded_width = width;
+    if !palette.is_empty() {
+        palette.sort_unstable();
+        (pixels, coded_width) = color_indexing(&mut writer, &pixels, width, &palette);
+    } else {
+        writer.write(1, 1);
+        writer.write(SUBTRACT_GREEN_TRANSFORM, 2);
+        for pixel in &mut pixels {
+            let green = (*pixel >> 8) & 0xff;
+            *pixel = sub_pixels(*pixel, green << 16 | green);
+        }
+        pixels = predictor_residuals(&mut writer, &pixels, width);
+    }
+    // No more transforms
+    writer.write(0, 1);
+
+    write_entropy_image(&mut writer, &pixels, coded_width, true);
+    Ok(writer.finish())
+}
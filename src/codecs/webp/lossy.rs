@@ -0,0 +1,1114 @@
+mod tables;
+
+use std::io;
+
+use super::super::{invalid_data, unsupported};
+use tables::{AC_TABLE, COEFF_UPDATE_PROBS, DC_TABLE, DEFAULT_COEFF_PROBS, INTRA4_MODE_PROBS};
+
+const START_CODE: [u8; 3] = [0x9d, 0x01, 0x2a];
+
+// Shared by the 16x16, 4x4 and chroma predictors
+const DC_PRED: u8 = 0;
+const TM_PRED: u8 = 1;
+const V_PRED: u8 = 2;
+const H_PRED: u8 = 3;
+// Remaining 4x4 modes
+const RD_PRED: u8 = 4;
+const VR_PRED: u8 = 5;
+const LD_PRED: u8 = 6;
+const VL_PRED: u8 = 7;
+const HD_PRED: u8 = 8;
+const HU_PRED: u8 = 9;
+
+// Coefficient plane types, indexing the token probabilities
+const PLANE_Y_AFTER_Y2: usize = 0;
+const PLANE_Y2: usize = 1;
+const PLANE_CHROMA: usize = 2;
+const PLANE_Y_WITH_DC: usize = 3;
+
+const ZIGZAG: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+const BANDS: [usize; 16] = [0, 1, 2, 3, 6, 4, 5, 6, 6, 6, 6, 6, 6, 6, 6, 7];
+const EXTRA_BITS_PROBS: [&[u8]; 4] = [
+    &[173, 148, 140],
+    &[176, 155, 140, 135],
+    &[180, 157, 141, 134, 130],
+    &[254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129],
+];
+
+type CoeffProbs = [[[[u8; 11]; 3]; 8]; 4];
+
+struct BoolDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    value: u32,
+    range: u32,
+    bit_count: u32,
+}
+
+impl<'a> BoolDecoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        let mut decoder = BoolDecoder { data, pos: 0, value: 0, range: 255, bit_count: 0 };
+        decoder.value = (decoder.next_byte() << 8) | decoder.next_byte();
+        decoder
+    }
+
+    // Reading past the end yields zeros, as libvpx does
+    fn next_byte(&mut self) -> u32 {
+        let byte = self.data.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        byte as u32
+    }
+
+    fn read_bool(&mut self, prob: u8) -> bool {
+        let split = 1 + (((self.range - 1) * prob as u32) >> 8);
+        let big_split = split << 8;
+        let bit = if self.value >= big_split {
+            self.range -= split;
+            self.value -= big_split;
+            true
+        } else {
+            self.range = split;
+            false
+        };
+
+        let shift = (self.range as u8).leading_zeros();
+        self.range <<= shift;
+        self.value <<= shift;
+        self.bit_count += shift;
+        if self.bit_count >= 8 {
+            self.bit_count -= 8;
+            self.value |= self.next_byte() << self.bit_count;
+        }
+        bit
+    }
+
+    fn read_flag(&mut self) -> bool {
+        self.read_bool(128)
+    }
+
+    fn read_literal(&mut self, bits: u32) -> u32 {
+        (0..bits).fold(0, |value, _| (value << 1) | self.read_flag() as u32)
+    }
+
+    fn read_signed(&mut self, bits: u32) -> i32 {
+        let magnitude = self.read_literal(bits) as i32;
+        if self.read_flag() {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    fn read_optional_signed(&mut self, bits: u32) -> i32 {
+        if self.read_flag() {
+            self.read_signed(bits)
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Default)]
+struct Segmentation {
+    enabled: bool,
+    update_map: bool,
+    absolute: bool,
+    quantizer: [i32; 4],
+    filter_level: [i32; 4],
+    probs: [u8; 3],
+}
+
+#[derive(Clone, Copy, Default)]
+struct Quantizer {
+    y: [i32; 2],
+    y2: [i32; 2],
+    uv: [i32; 2],
+}
+
+#[derive(Clone, Copy, Default)]
+struct FilterStrength {
+    limit: i32,
+    interior_limit: i32,
+    hev_threshold: i32,
+}
+
+// Which blocks along an edge had non-zero coefficients, for token contexts
+#[derive(Clone, Copy, Default)]
+struct NonZero {
+    y: [bool; 4],
+    u: [bool; 2],
+    v: [bool; 2],
+    y2: bool,
+}
+
+struct MacroblockFilter {
+    strength: FilterStrength,
+    inner: bool,
+}
+
+struct Plane {
+    data: Vec<u8>,
+    stride: usize,
+}
+
+impl Plane {
+    fn new(width: usize, height: usize) -> Self {
+        Plane { data: vec![0; width * height], stride: width }
+    }
+
+    // Neighbouring samples of the block at (x, y), with the frame edges
+    // filled in as 127 above and 129 to the left
+    fn top(&self, x: usize, y: usize, len: usize) -> Vec<u8> {
+        if y == 0 {
+            return vec![127; len];
+        }
+        self.data[(y - 1) * self.stride + x..][..len].to_vec()
+    }
+
+    fn left(&self, x: usize, y: usize, len: usize) -> Vec<u8> {
+        if x == 0 {
+            return vec![129; len];
+        }
+        (0..len).map(|i| self.data[(y + i) * self.stride + x - 1]).collect()
+    }
+
+    fn top_left(&self, x: usize, y: usize) -> u8 {
+        if y == 0 {
+            127
+        } else if x == 0 {
+            129
+        } else {
+            self.data[(y - 1) * self.stride + x - 1]
+        }
+    }
+
+    // Writes a predicted block plus its residual
+    fn store(&mut self, x: usize, y: usize, size: usize, block: &[u8]) {
+        for (row, values) in block.chunks(size).enumerate() {
+            self.data[(y + row) * self.stride + x..][..size].copy_from_slice(values);
+        }
+    }
+}
+
+fn avg2(a: u8, b: u8) -> u8 {
+    ((a as u16 + b as u16 + 1) >> 1) as u8
+}
+
+fn avg3(a: u8, b: u8, c: u8) -> u8 {
+    ((a as u16 + 2 * b as u16 + c as u16 + 2) >> 2) as u8
+}
+
+// 16x16 luma and 8x8 chroma prediction, DC falls back to whichever edges are
+// inside the frame
+fn predict_block(mode: u8, size: usize, top: &[u8], left: &[u8], top_left: u8, has_top: bool, has_left: bool) -> Vec<u8> {
+    let mut block = vec![0u8; size * size];
+    match mode {
+        DC_PRED => {
+            let shift = size.trailing_zeros();
+            let sum = |edge: &[u8]| edge.iter().map(|&value| value as u32).sum::<u32>();
+            let dc = match (has_top, has_left) {
+                (true, true) => (sum(top) + sum(left) + size as u32) >> (shift + 1),
+                (true, false) => (sum(top) + size as u32 / 2) >> shift,
+                (false, true) => (sum(left) + size as u32 / 2) >> shift,
+                (false, false) => 128,
+            };
+            block.fill(dc as u8);
+        }
+        V_PRED => block.chunks_mut(size).for_each(|row| row.copy_from_slice(&top[..size])),
+        H_PRED => block.chunks_mut(size).zip(left).for_each(|(row, &value)| row.fill(value)),
+        _ => {
+            for (row, &left_value) in block.chunks_mut(size).zip(left) {
+                for (value, &top_value) in row.iter_mut().zip(top) {
+                    *value = (left_value as i32 + top_value as i32 - top_left as i32).clamp(0, 255) as u8;
+                }
+            }
+        }
+    }
+    block
+}
+
+// 4x4 luma prediction, `top` holds the four samples above plus four above-right
+fn predict_subblock(mode: u8, top: &[u8; 8], left: &[u8; 4], top_left: u8) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    let [a, b, c, d, e, f, g, h] = *top;
+    let [i, j, k, l] = *left;
+    let x = top_left;
+    let mut put = |col: usize, row: usize, value: u8| block[row * 4 + col] = value;
+
+    match mode {
+        DC_PRED => {
+            let sum: u32 = top[..4].iter().chain(left).map(|&value| value as u32).sum();
+            (0..16).for_each(|n| put(n % 4, n / 4, ((sum + 4) >> 3) as u8));
+        }
+        TM_PRED => {
+            let value = |col: usize, row: usize| (left[row] as i32 + top[col] as i32 - x as i32).clamp(0, 255) as u8;
+            (0..16).for_each(|n| put(n % 4, n / 4, value(n % 4, n / 4)));
+        }
+        V_PRED => {
+            let values = [avg3(x, a, b), avg3(a, b, c), avg3(b, c, d), avg3(c, d, e)];
+            (0..16).for_each(|n| put(n % 4, n / 4, values[n % 4]));
+        }
+        H_PRED => {
+            let values = [avg3(x, i, j), avg3(i, j, k), avg3(j, k, l), avg3(k, l, l)];
+            (0..16).for_each(|n| put(n % 4, n / 4, values[n / 4]));
+        }
+        RD_PRED => {
+            let edge = [l, k, j, i, x, a, b, c, d];
+            for row in 0..4 {
+                for col in 0..4 {
+                    let n = 4 + col - row;
+                    put(col, row, avg3(edge[n - 1], edge[n], edge[n + 1]));
+                }
+            }
+        }
+        VR_PRED => {
+            put(0, 0, avg2(x, a));
+            put(1, 2, avg2(x, a));
+            put(1, 0, avg2(a, b));
+            put(2, 2, avg2(a, b));
+            put(2, 0, avg2(b, c));
+            put(3, 2, avg2(b, c));
+            put(3, 0, avg2(c, d));
+            put(0, 3, avg3(k, j, i));
+            put(0, 2, avg3(j, i, x));
+            put(0, 1, avg3(i, x, a));
+            put(1, 3, avg3(i, x, a));
+            put(1, 1, avg3(x, a, b));
+            put(2, 3, avg3(x, a, b));
+            put(2, 1, avg3(a, b, c));
+            put(3, 3, avg3(a, b, c));
+            put(3, 1, avg3(b, c, d));
+        }
+        LD_PRED => {
+            for row in 0..4 {
+                for col in 0..4 {
+                    let n = col + row;
+                    put(col, row, avg3(top[n], top[n + 1], top[(n + 2).min(7)]));
+                }
+            }
+        }
+        VL_PRED => {
+            put(0, 0, avg2(a, b));
+            put(1, 0, avg2(b, c));
+            put(0, 2, avg2(b, c));
+            put(2, 0, avg2(c, d));
+            put(1, 2, avg2(c, d));
+            put(3, 0, avg2(d, e));
+            put(2, 2, avg2(d, e));
+            put(0, 1, avg3(a, b, c));
+            put(1, 1, avg3(b, c, d));
+            put(0, 3, avg3(b, c, d));
+            put(2, 1, avg3(c, d, e));
+            put(1, 3, avg3(c, d, e));
+            put(3, 1, avg3(d, e, f));
+            put(2, 3, avg3(d, e, f));
+            put(3, 2, avg3(e, f, g));
+            put(3, 3, avg3(f, g, h));
+        }
+        HD_PRED => {
+            put(0, 0, avg2(i, x));
+            put(2, 1, avg2(i, x));
+            put(0, 1, avg2(j, i));
+            put(2, 2, avg2(j, i));
+            put(0, 2, avg2(k, j));
+            put(2, 3, avg2(k, j));
+            put(0, 3, avg2(l, k));
+            put(3, 0, avg3(a, b, c));
+            put(2, 0, avg3(x, a, b));
+            put(1, 0, avg3(i, x, a));
+            put(3, 1, avg3(i, x, a));
+            put(1, 1, avg3(j, i, x));
+            put(3, 2, avg3(j, i, x));
+            put(1, 2, avg3(k, j, i));
+            put(3, 3, avg3(k, j, i));
+            put(1, 3, avg3(l, k, j));
+        }
+        _ => {
+            put(0, 0, avg2(i, j));
+            put(2, 0, avg2(j, k));
+            put(0, 1, avg2(j, k));
+            put(2, 1, avg2(k, l));
+            put(0, 2, avg2(k, l));
+            put(1, 0, avg3(i, j, k));
+            put(3, 0, avg3(j, k, l));
+            put(1, 1, avg3(j, k, l));
+            put(3, 1, avg3(k, l, l));
+            put(1, 2, avg3(k, l, l));
+            for (col, row) in [(3, 2), (2, 2), (0, 3), (1, 3), (2, 3), (3, 3)] {
+                put(col, row, l);
+            }
+        }
+    }
+    block
+}
+
+// Dequantized coefficients reach about 2^20, so the products need 64 bits
+fn mul1(value: i32) -> i32 {
+    ((value as i64 * 20091) >> 16) as i32 + value
+}
+
+fn mul2(value: i32) -> i32 {
+    ((value as i64 * 35468) >> 16) as i32
+}
+
+// Inverse DCT of one 4x4 block, added onto the prediction
+fn add_inverse_dct(coeffs: &[i32], block: &mut [u8], offset: usize, stride: usize) {
+    let mut tmp = [0i32; 16];
+    for i in 0..4 {
+        let a = coeffs[i] + coeffs[8 + i];
+        let b = coeffs[i] - coeffs[8 + i];
+        let c = mul2(coeffs[4 + i]) - mul1(coeffs[12 + i]);
+        let d = mul1(coeffs[4 + i]) + mul2(coeffs[12 + i]);
+        tmp[4 * i..4 * i + 4].copy_from_slice(&[a + d, b + c, b - c, a - d]);
+    }
+    for row in 0..4 {
+        let dc = tmp[row] + 4;
+        let a = dc + tmp[8 + row];
+        let b = dc - tmp[8 + row];
+        let c = mul2(tmp[4 + row]) - mul1(tmp[12 + row]);
+        let d = mul1(tmp[4 + row]) + mul2(tmp[12 + row]);
+        for (col, residual) in [a + d, b + c, b - c, a - d].into_iter().enumerate() {
+            let value = &mut block[offset + row * stride + col];
+            *value = (*value as i32 + (residual >> 3)).clamp(0, 255) as u8;
+        }
+    }
+}
+
+// Inverse Walsh-Hadamard transform of the second order luma DC block
+fn inverse_wht(input: &[i32; 16]) -> [i32; 16] {
+    let mut tmp = [0i32; 16];
+    for i in 0..4 {
+        let a0 = input[i] + input[12 + i];
+        let a1 = input[4 + i] + input[8 + i];
+        let a2 = input[4 + i] - input[8 + i];
+        let a3 = input[i] - input[12 + i];
+        tmp[i] = a0 + a1;
+        tmp[8 + i] = a0 - a1;
+        tmp[4 + i] = a3 + a2;
+        tmp[12 + i] = a3 - a2;
+    }
+    let mut out = [0i32; 16];
+    for i in 0..4 {
+        let dc = tmp[i * 4] + 3;
+        let a0 = dc + tmp[i * 4 + 3];
+        let a1 = tmp[i * 4 + 1] + tmp[i * 4 + 2];
+        let a2 = tmp[i * 4 + 1] - tmp[i * 4 + 2];
+        let a3 = dc - tmp[i * 4 + 3];
+        out[i * 4] = (a0 + a1) >> 3;
+        out[i * 4 + 1] = (a3 + a2) >> 3;
+        out[i * 4 + 2] = (a0 - a1) >> 3;
+        out[i * 4 + 3] = (a3 - a2) >> 3;
+    }
+    out
+}
+
+fn read_large_value(decoder: &mut BoolDecoder, probs: &[u8; 11]) -> i32 {
+    if !decoder.read_bool(probs[3]) {
+        if !decoder.read_bool(probs[4]) {
+            2
+        } else {
+            3 + decoder.read_bool(probs[5]) as i32
+        }
+    } else if !decoder.read_bool(probs[6]) {
+        if !decoder.read_bool(probs[7]) {
+            5 + decoder.read_bool(159) as i32
+        } else {
+            7 + 2 * decoder.read_bool(165) as i32 + decoder.read_bool(145) as i32
+        }
+    } else {
+        let high = decoder.read_bool(probs[8]) as usize;
+        let category = 2 * high + decoder.read_bool(probs[9 + high]) as usize;
+        let extra = EXTRA_BITS_PROBS[category].iter().fold(0, |value, &prob| 2 * value + decoder.read_bool(prob) as i32);
+        extra + 3 + (8 << category)
+    }
+}
+
+// Reads the tokens of one 4x4 block into `out` in raster order and returns
+// the index after the last non-zero coefficient
+fn read_coefficients(
+    decoder: &mut BoolDecoder,
+    probs: &[[[u8; 11]; 3]; 8],
+    context: usize,
+    dequant: [i32; 2],
+    first: usize,
+    out: &mut [i32],
+) -> usize {
+    let mut n = first;
+    let mut p = &probs[BANDS[n]][context];
+    while n < 16 {
+        if !decoder.read_bool(p[0]) {
+            return n;
+        }
+        while !decoder.read_bool(p[1]) {
+            n += 1;
+            if n == 16 {
+                return 16;
+            }
+            p = &probs[BANDS[n]][0];
+        }
+
+        let (value, next_context) = if !decoder.read_bool(p[2]) { (1, 1) } else { (read_large_value(decoder, p), 2) };
+        let value = if decoder.read_flag() { -value } else { value };
+        out[ZIGZAG[n]] = value * dequant[(n > 0) as usize];
+
+        n += 1;
+        if n < 16 {
+            p = &probs[BANDS[n]][next_context];
+        }
+    }
+    16
+}
+
+struct Header {
+    segmentation: Segmentation,
+    simple_filter: bool,
+    filter_level: i32,
+    sharpness: i32,
+    ref_lf_delta: i32,
+    mode_lf_delta: i32,
+    use_lf_delta: bool,
+    quantizers: [Quantizer; 4],
+    coeff_probs: Box<CoeffProbs>,
+    skip_prob: Option<u8>,
+}
+
+fn read_header(decoder: &mut BoolDecoder) -> Header {
+    // Color space and clamping type, pixel values are always clamped
+    decoder.read_literal(2);
+
+    let mut segmentation = Segmentation { probs: [255; 3], ..Default::default() };
+    segmentation.enabled = decoder.read_flag();
+    if segmentation.enabled {
+        segmentation.update_map = decoder.read_flag();
+        let update_data = decoder.read_flag();
+        if update_data {
+            segmentation.absolute = decoder.read_flag();
+            for quantizer in &mut segmentation.quantizer {
+                *quantizer = decoder.read_optional_signed(7);
+            }
+            for level in &mut segmentation.filter_level {
+                *level = decoder.read_optional_signed(6);
+            }
+        }
+        if segmentation.update_map {
+            for prob in &mut segmentation.probs {
+                *prob = if decoder.read_flag() { decoder.read_literal(8) as u8 } else { 255 };
+            }
+        }
+    }
+
+    let simple_filter = decoder.read_flag();
+    let filter_level = decoder.read_literal(6) as i32;
+    let sharpness = decoder.read_literal(3) as i32;
+
+    // Only the intra frame and intra 4x4 mode deltas matter for key frames
+    let mut ref_lf_delta = 0;
+    let mut mode_lf_delta = 0;
+    let use_lf_delta = decoder.read_flag();
+    if use_lf_delta && decoder.read_flag() {
+        for i in 0..4 {
+            let delta = decoder.read_optional_signed(6);
+            if i == 0 {
+                ref_lf_delta = delta;
+            }
+        }
+        for i in 0..4 {
+            let delta = decoder.read_optional_signed(6);
+            if i == 0 {
+                mode_lf_delta = delta;
+            }
+        }
+    }
+    Header {
+        segmentation,
+        simple_filter,
+        filter_level,
+        sharpness,
+        ref_lf_delta,
+        mode_lf_delta,
+        use_lf_delta,
+        quantizers: [Quantizer::default(); 4],
+        coeff_probs: Box::new(DEFAULT_COEFF_PROBS),
+        skip_prob: None,
+    }
+}
+
+fn read_quantizers(decoder: &mut BoolDecoder, segmentation: &Segmentation) -> [Quantizer; 4] {
+    let base = decoder.read_literal(7) as i32;
+    let y_dc = decoder.read_optional_signed(4);
+    let y2_dc = decoder.read_optional_signed(4);
+    let y2_ac = decoder.read_optional_signed(4);
+    let uv_dc = decoder.read_optional_signed(4);
+    let uv_ac = decoder.read_optional_signed(4);
+
+    let dc = |index: i32, max: i32| DC_TABLE[index.clamp(0, max) as usize] as i32;
+    let ac = |index: i32| AC_TABLE[index.clamp(0, 127) as usize] as i32;
+    std::array::from_fn(|segment| {
+        let q = match (segmentation.enabled, segmentation.absolute) {
+            (false, _) => base,
+            (true, true) => segmentation.quantizer[segment],
+            (true, false) => segmentation.quantizer[segment] + base,
+        };
+        Quantizer {
+            y: [dc(q + y_dc, 127), ac(q)],
+            y2: [dc(q + y2_dc, 127) * 2, (ac(q + y2_ac) * 155 / 100).max(8)],
+            uv: [dc(q + uv_dc, 117), ac(q + uv_ac)],
+        }
+    })
+}
+
+fn filter_strengths(header: &Header) -> [[FilterStrength; 2]; 4] {
+    std::array::from_fn(|segment| {
+        let base = match (header.segmentation.enabled, header.segmentation.absolute) {
+            (false, _) => header.filter_level,
+            (true, true) => header.segmentation.filter_level[segment],
+            (true, false) => header.segmentation.filter_level[segment] + header.filter_level,
+        };
+        std::array::from_fn(|intra4| {
+            let mut level = base;
+            if header.use_lf_delta {
+                level += header.ref_lf_delta;
+                if intra4 == 1 {
+                    level += header.mode_lf_delta;
+                }
+            }
+            let level = level.clamp(0, 63);
+            if level == 0 {
+                return FilterStrength::default();
+            }
+
+            let mut interior_limit = level;
+            if header.sharpness > 0 {
+                interior_limit >>= if header.sharpness > 4 { 2 } else { 1 };
+                interior_limit = interior_limit.min(9 - header.sharpness);
+            }
+            let interior_limit = interior_limit.max(1);
+            FilterStrength {
+                limit: 2 * level + interior_limit,
+                interior_limit,
+                hev_threshold: if level >= 40 {
+                    2
+                } else if level >= 15 {
+                    1
+                } else {
+                    0
+                },
+            }
+        })
+    })
+}
+
+fn read_intra4_mode(decoder: &mut BoolDecoder, probs: &[u8; 9]) -> u8 {
+    if !decoder.read_bool(probs[0]) {
+        DC_PRED
+    } else if !decoder.read_bool(probs[1]) {
+        TM_PRED
+    } else if !decoder.read_bool(probs[2]) {
+        V_PRED
+    } else if !decoder.read_bool(probs[3]) {
+        if !decoder.read_bool(probs[4]) {
+            H_PRED
+        } else if !decoder.read_bool(probs[5]) {
+            RD_PRED
+        } else {
+            VR_PRED
+        }
+    } else if !decoder.read_bool(probs[6]) {
+        LD_PRED
+    } else if !decoder.read_bool(probs[7]) {
+        VL_PRED
+    } else if !decoder.read_bool(probs[8]) {
+        HD_PRED
+    } else {
+        HU_PRED
+    }
+}
+
+fn sclip1(value: i32) -> i32 {
+    value.clamp(-128, 127)
+}
+
+fn sclip2(value: i32) -> i32 {
+    value.clamp(-16, 15)
+}
+
+fn clip(value: i32) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+// Loop filter helpers, `pos` is the first sample after the edge and `step`
+// crosses it
+fn sample(data: &[u8], pos: usize, step: usize, offset: isize) -> i32 {
+    data[(pos as isize + offset * step as isize) as usize] as i32
+}
+
+fn needs_filter(data: &[u8], pos: usize, step: usize, limit: i32) -> bool {
+    let [p1, p0, q0, q1] = [-2, -1, 0, 1].map(|offset| sample(data, pos, step, offset));
+    4 * (p0 - q0).abs() + (p1 - q1).abs() <= 2 * limit + 1
+}
+
+fn needs_normal_filter(data: &[u8], pos: usize, step: usize, limit: i32, interior_limit: i32) -> bool {
+    let [p3, p2, p1, p0, q0, q1, q2, q3] = [-4, -3, -2, -1, 0, 1, 2, 3].map(|offset| sample(data, pos, step, offset));
+    needs_filter(data, pos, step, limit)
+        && [p3 - p2, p2 - p1, p1 - p0, q3 - q2, q2 - q1, q1 - q0].iter().all(|diff| diff.abs() <= interior_limit)
+}
+
+fn high_edge_variance(data: &[u8], pos: usize, step: usize, threshold: i32) -> bool {
+    let [p1, p0, q0, q1] = [-2, -1, 0, 1].map(|offset| sample(data, pos, step, offset));
+    (p1 - p0).abs() > threshold || (q1 - q0).abs() > threshold
+}
+
+// Adjusts the two samples next to the edge
+fn filter2(data: &mut [u8], pos: usize, step: usize) {
+    let [p1, p0, q0, q1] = [-2, -1, 0, 1].map(|offset| sample(data, pos, step, offset));
+    let a = 3 * (q0 - p0) + sclip1(p1 - q1);
+    let a1 = sclip2((a + 4) >> 3);
+    let a2 = sclip2((a + 3) >> 3);
+    data[pos - step] = clip(p0 + a2);
+    data[pos] = clip(q0 - a1);
+}
+
+// Inner edges adjust two samples on either side
+fn filter4(data: &mut [u8], pos: usize, step: usize) {
+    let [p1, p0, q0, q1] = [-2, -1, 0, 1].map(|offset| sample(data, pos, step, offset));
+    let a = 3 * (q0 - p0);
+    let a1 = sclip2((a + 4) >> 3);
+    let a2 = sclip2((a + 3) >> 3);
+    let a3 = (a1 + 1) >> 1;
+    data[pos - 2 * step] = clip(p1 + a3);
+    data[pos - step] = clip(p0 + a2);
+    data[pos] = clip(q0 - a1);
+    data[pos + step] = clip(q1 - a3);
+}
+
+// Macroblock edges adjust three samples on either side
+fn filter6(data: &mut [u8], pos: usize, step: usize) {
+    let [p2, p1, p0, q0, q1, q2] = [-3, -2, -1, 0, 1, 2].map(|offset| sample(data, pos, step, offset));
+    let a = sclip1(3 * (q0 - p0) + sclip1(p1 - q1));
+    let a1 = (27 * a + 63) >> 7;
+    let a2 = (18 * a + 63) >> 7;
+    let a3 = (9 * a + 63) >> 7;
+    data[pos - 3 * step] = clip(p2 + a3);
+    data[pos - 2 * step] = clip(p1 + a2);
+    data[pos - step] = clip(p0 + a1);
+    data[pos] = clip(q0 - a1);
+    data[pos + step] = clip(q1 - a2);
+    data[pos + 2 * step] = clip(q2 - a3);
+}
+
+// Filters `len` positions along an edge, `step` crosses the edge and `along`
+// moves to the next position
+fn filter_simple_edge(data: &mut [u8], pos: usize, step: usize, along: usize, len: usize, limit: i32) {
+    for i in 0..len {
+        let pos = pos + i * along;
+        if needs_filter(data, pos, step, limit) {
+            filter2(data, pos, step);
+        }
+    }
+}
+
+fn filter_normal_edge(data: &mut [u8], pos: usize, step: usize, along: usize, len: usize, strength: FilterStrength, inner: bool) {
+    for i in 0..len {
+        let pos = pos + i * along;
+        if !needs_normal_filter(data, pos, step, strength.limit, strength.interior_limit) {
+            continue;
+        }
+        if high_edge_variance(data, pos, step, strength.hev_threshold) {
+            filter2(data, pos, step);
+        } else if inner {
+            filter4(data, pos, step);
+        } else {
+            filter6(data, pos, step);
+        }
+    }
+}
+
+fn filter_plane(plane: &mut Plane, x: usize, y: usize, size: usize, filter: &MacroblockFilter, simple: bool) {
+    let stride = plane.stride;
+    let strength = filter.strength;
+    let origin = y * stride + x;
+    let edge = |data: &mut [u8], pos, step, along, limit, inner| {
+        if simple {
+            filter_simple_edge(data, pos, step, along, size, limit);
+        } else {
+            filter_normal_edge(data, pos, step, along, size, FilterStrength { limit, ..strength }, inner);
+        }
+    };
+
+    if x > 0 {
+        edge(&mut plane.data, origin, 1, stride, strength.limit + 4, false);
+    }
+    if filter.inner {
+        for offset in (4..size).step_by(4) {
+            edge(&mut plane.data, origin + offset, 1, stride, strength.limit, true);
+        }
+    }
+    if y > 0 {
+        edge(&mut plane.data, origin, stride, 1, strength.limit + 4, false);
+    }
+    if filter.inner {
+        for offset in (4..size).step_by(4) {
+            edge(&mut plane.data, origin + offset * stride, stride, 1, strength.limit, true);
+        }
+    }
+}
+
+struct Frame<'a> {
+    header: Header,
+    strengths: [[FilterStrength; 2]; 4],
+    modes: BoolDecoder<'a>,
+    partitions: Vec<BoolDecoder<'a>>,
+    y: Plane,
+    u: Plane,
+    v: Plane,
+    mb_width: usize,
+    intra_top: Vec<[u8; 4]>,
+    non_zero_top: Vec<NonZero>,
+    filters: Vec<MacroblockFilter>,
+}
+
+impl Frame<'_> {
+    fn decode_macroblock(&mut self, mb_x: usize, mb_y: usize, intra_left: &mut [u8; 4], non_zero_left: &mut NonZero) {
+        let modes = &mut self.modes;
+        let segmentation = &self.header.segmentation;
+        let segment = if segmentation.update_map {
+            if !modes.read_bool(segmentation.probs[0]) {
+                modes.read_bool(segmentation.probs[1]) as usize
+            } else {
+                2 + modes.read_bool(segmentation.probs[2]) as usize
+            }
+        } else {
+            0
+        };
+        let skip = self.header.skip_prob.is_some_and(|prob| modes.read_bool(prob));
+
+        let intra4 = !modes.read_bool(145);
+        let mut luma_modes = [DC_PRED; 16];
+        let intra_top = &mut self.intra_top[mb_x];
+        if !intra4 {
+            let mode = if modes.read_bool(156) {
+                if modes.read_bool(128) {
+                    TM_PRED
+                } else {
+                    H_PRED
+                }
+            } else if modes.read_bool(163) {
+                V_PRED
+            } else {
+                DC_PRED
+            };
+            luma_modes[0] = mode;
+            *intra_top = [mode; 4];
+            *intra_left = [mode; 4];
+        } else {
+            for row in 0..4 {
+                for col in 0..4 {
+                    let probs = &INTRA4_MODE_PROBS[intra_top[col] as usize][intra_left[row] as usize];
+                    let mode = read_intra4_mode(modes, probs);
+                    luma_modes[row * 4 + col] = mode;
+                    intra_top[col] = mode;
+                    intra_left[row] = mode;
+                }
+            }
+        }
+        let chroma_mode = if !modes.read_bool(142) {
+            DC_PRED
+        } else if !modes.read_bool(114) {
+            V_PRED
+        } else if modes.read_bool(183) {
+            TM_PRED
+        } else {
+            H_PRED
+        };
+
+        // 16 luma blocks followed by 4 U and 4 V blocks
+        let mut coeffs = [0i32; 384];
+        let non_zero_top = &mut self.non_zero_top[mb_x];
+        if skip {
+            // Intra 4x4 macroblocks have no Y2 block and leave its context alone
+            *non_zero_top = NonZero { y2: intra4 && non_zero_top.y2, ..Default::default() };
+            *non_zero_left = NonZero { y2: intra4 && non_zero_left.y2, ..Default::default() };
+        } else {
+            let index = mb_y % self.partitions.len();
+            let partition = &mut self.partitions[index];
+            read_residuals(
+                partition,
+                &self.header.coeff_probs,
+                &self.header.quantizers[segment],
+                intra4,
+                non_zero_top,
+                non_zero_left,
+                &mut coeffs,
+            );
+        }
+
+        let has_coeffs = coeffs.iter().any(|&coeff| coeff != 0);
+        self.filters.push(MacroblockFilter {
+            strength: self.strengths[segment][intra4 as usize],
+            inner: intra4 || has_coeffs,
+        });
+
+        self.reconstruct_luma(mb_x, mb_y, intra4, &luma_modes, &coeffs[..256]);
+        let (x, y) = (mb_x * 8, mb_y * 8);
+        for (plane, coeffs) in [(&mut self.u, &coeffs[256..320]), (&mut self.v, &coeffs[320..])] {
+            let top = plane.top(x, y, 8);
+            let left = plane.left(x, y, 8);
+            let mut block = predict_block(chroma_mode, 8, &top, &left, plane.top_left(x, y), y > 0, x > 0);
+            for (n, coeffs) in coeffs.chunks(16).enumerate() {
+                if coeffs.iter().any(|&coeff| coeff != 0) {
+                    add_inverse_dct(coeffs, &mut block, (n / 2) * 32 + (n % 2) * 4, 8);
+                }
+            }
+            plane.store(x, y, 8, &block);
+        }
+    }
+
+    fn reconstruct_luma(&mut self, mb_x: usize, mb_y: usize, intra4: bool, modes: &[u8; 16], coeffs: &[i32]) {
+        let plane = &mut self.y;
+        let (x, y) = (mb_x * 16, mb_y * 16);
+        if !intra4 {
+            let top = plane.top(x, y, 16);
+            let left = plane.left(x, y, 16);
+            let mut block = predict_block(modes[0], 16, &top, &left, plane.top_left(x, y), y > 0, x > 0);
+            for (n, coeffs) in coeffs.chunks(16).enumerate() {
+                if coeffs.iter().any(|&coeff| coeff != 0) {
+                    add_inverse_dct(coeffs, &mut block, (n / 4) * 64 + (n % 4) * 4, 16);
+                }
+            }
+            plane.store(x, y, 16, &block);
+            return;
+        }
+
+        // Samples above and to the right of the macroblock, reused by every
+        // subblock in the rightmost column
+        let top_right: [u8; 4] = if y == 0 {
+            [127; 4]
+        } else if mb_x + 1 < self.mb_width {
+            plane.top(x + 16, y, 4).try_into().unwrap()
+        } else {
+            [plane.data[(y - 1) * plane.stride + x + 15]; 4]
+        };
+
+        for n in 0..16 {
+            let (sx, sy) = (x + (n % 4) * 4, y + (n / 4) * 4);
+            let mut top = [0u8; 8];
+            top[..4].copy_from_slice(&plane.top(sx, sy, 4));
+            if n % 4 == 3 {
+                top[4..].copy_from_slice(&top_right);
+            } else {
+                top[4..].copy_from_slice(&plane.top(sx + 4, sy, 4));
+            }
+            let left: [u8; 4] = plane.left(sx, sy, 4).try_into().unwrap();
+
+            let mut block = predict_subblock(modes[n], &top, &left, plane.top_left(sx, sy));
+            let coeffs = &coeffs[n * 16..][..16];
+            if coeffs.iter().any(|&coeff| coeff != 0) {
+                add_inverse_dct(coeffs, &mut block, 0, 4);
+            }
+            plane.store(sx, sy, 4, &block);
+        }
+    }
+}
+
+fn read_residuals(
+    decoder: &mut BoolDecoder,
+    probs: &CoeffProbs,
+    quantizer: &Quantizer,
+    intra4: bool,
+    top: &mut NonZero,
+    left: &mut NonZero,
+    coeffs: &mut [i32; 384],
+) {
+    let (first, luma_plane) = if intra4 {
+        (0, PLANE_Y_WITH_DC)
+    } else {
+        let mut dc = [0i32; 16];
+        let context = top.y2 as usize + left.y2 as usize;
+        let count = read_coefficients(decoder, &probs[PLANE_Y2], context, quantizer.y2, 0, &mut dc);
+        top.y2 = count > 0;
+        left.y2 = count > 0;
+        let dc = if count > 1 { inverse_wht(&dc) } else { [(dc[0] + 3) >> 3; 16] };
+        for (n, value) in dc.into_iter().enumerate() {
+            coeffs[n * 16] = value;
+        }
+        (1, PLANE_Y_AFTER_Y2)
+    };
+
+    for row in 0..4 {
+        for col in 0..4 {
+            let context = top.y[col] as usize + left.y[row] as usize;
+            let block = &mut coeffs[(row * 4 + col) * 16..][..16];
+            let count = read_coefficients(decoder, &probs[luma_plane], context, quantizer.y, first, block);
+            top.y[col] = count > first;
+            left.y[row] = count > first;
+        }
+    }
+
+    for (plane, offset) in [(0, 256), (1, 320)] {
+        for row in 0..2 {
+            for col in 0..2 {
+                let (top_flag, left_flag) = if plane == 0 { (&mut top.u[col], &mut left.u[row]) } else { (&mut top.v[col], &mut left.v[row]) };
+                let context = *top_flag as usize + *left_flag as usize;
+                let block = &mut coeffs[offset + (row * 2 + col) * 16..][..16];
+                let count = read_coefficients(decoder, &probs[PLANE_CHROMA], context, quantizer.uv, 0, block);
+                *top_flag = count > 0;
+                *left_flag = count > 0;
+            }
+        }
+    }
+}
+
+// Converts with the BT.601 fixed point coefficients used by libwebp, chroma
+// is upsampled by interpolating the four nearest samples
+fn yuv_to_rgb(y_plane: &Plane, u_plane: &Plane, v_plane: &Plane, width: usize, height: usize) -> Vec<u8> {
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    let upsample = |plane: &Plane, x: usize, y: usize| -> i32 {
+        let cx = x / 2;
+        let cy = y / 2;
+        let nx = if x % 2 == 1 { (cx + 1).min(chroma_width - 1) } else { cx.saturating_sub(1) };
+        let ny = if y % 2 == 1 { (cy + 1).min(chroma_height - 1) } else { cy.saturating_sub(1) };
+        let at = |x: usize, y: usize| plane.data[y * plane.stride + x] as i32;
+        (9 * at(cx, cy) + 3 * at(nx, cy) + 3 * at(cx, ny) + at(nx, ny) + 8) >> 4
+    };
+    let clip8 = |value: i32| (value >> 6).clamp(0, 255) as u8;
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let luma = (y_plane.data[y * y_plane.stride + x] as i32 * 19077) >> 8;
+            let u = upsample(u_plane, x, y);
+            let v = upsample(v_plane, x, y);
+            rgb.push(clip8(luma + ((v * 26149) >> 8) - 14234));
+            rgb.push(clip8(luma - ((u * 6419) >> 8) - ((v * 13320) >> 8) + 8708));
+            rgb.push(clip8(luma + ((u * 33050) >> 8) - 17685));
+        }
+    }
+    rgb
+}
+
+// Decodes a VP8 key frame into RGB samples
+pub(super) fn decode(data: &[u8]) -> io::Result<(u32, u32, Vec<u8>)> {
+    if data.len() < 10 {
+        return Err(invalid_data("truncated VP8 frame header"));
+    }
+    let tag = data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16;
+    if tag & 1 != 0 {
+        return Err(unsupported("VP8 inter frames are not supported"));
+    }
+    if data[3..6] != START_CODE {
+        return Err(invalid_data("invalid VP8 start code"));
+    }
+    let width = (u16::from_le_bytes([data[6], data[7]]) & 0x3fff) as usize;
+    let height = (u16::from_le_bytes([data[8], data[9]]) & 0x3fff) as usize;
+    if width == 0 || height == 0 {
+        return Err(invalid_data("invalid VP8 dimensions"));
+    }
+
+    let first_size = (tag >> 5) as usize;
+    let rest = &data[10..];
+    if first_size > rest.len() {
+        return Err(invalid_data("truncated VP8 frame"));
+    }
+    let mut modes = BoolDecoder::new(&rest[..first_size]);
+    let mut header = read_header(&mut modes);
+
+    let partition_count = 1 << modes.read_literal(2);
+    let sizes_len = 3 * (partition_count - 1);
+    let mut partition_data = rest.get(first_size + sizes_len..).ok_or_else(|| invalid_data("truncated VP8 frame"))?;
+    let mut partitions = Vec::with_capacity(partition_count);
+    for i in 0..partition_count {
+        let size = if i + 1 < partition_count {
+            let entry = &rest[first_size + 3 * i..];
+            entry[0] as usize | (entry[1] as usize) << 8 | (entry[2] as usize) << 16
+        } else {
+            partition_data.len()
+        };
+        if size > partition_data.len() {
+            return Err(invalid_data("truncated VP8 partitions"));
+        }
+        let (partition, remaining) = partition_data.split_at(size);
+        partitions.push(BoolDecoder::new(partition));
+        partition_data = remaining;
+    }
+
+    header.quantizers = read_quantizers(&mut modes, &header.segmentation);
+    // Whether to keep the probability updates, irrelevant for a single frame
+    modes.read_flag();
+    for (i, bands) in header.coeff_probs.iter_mut().enumerate() {
+        for (j, contexts) in bands.iter_mut().enumerate() {
+            for (k, probs) in contexts.iter_mut().enumerate() {
+                for (l, prob) in probs.iter_mut().enumerate() {
+                    if modes.read_bool(COEFF_UPDATE_PROBS[i][j][k][l]) {
+                        *prob = modes.read_literal(8) as u8;
+                    }
+                }
+            }
+        }
+    }
+    if modes.read_flag() {
+        header.skip_prob = Some(modes.read_literal(8) as u8);
+    }
+
+    let mb_width = width.div_ceil(16);
+    let mb_height = height.div_ceil(16);
+    let mut frame = Frame {
+        strengths: filter_strengths(&header),
+        header,
+        modes,
+        partitions,
+        y: Plane::new(mb_width * 16, mb_height * 16),
+        u: Plane::new(mb_width * 8, mb_height * 8),
+        v: Plane::new(mb_width * 8, mb_height * 8),
+        mb_width,
+        intra_top: vec![[DC_PRED; 4]; mb_width],
+        non_zero_top: vec![NonZero::default(); mb_width],
+        filters: Vec::with_capacity(mb_width * mb_height),
+    };
+
+    for mb_y in 0..mb_height {
+        let mut intra_left = [DC_PRED; 4];
+        let mut non_zero_left = NonZero::default();
+        for mb_x in 0..mb_width {
+            frame.decode_macroblock(mb_x, mb_y, &mut intra_left, &mut non_zero_left);
+        }
+    }
+
+    // The loop filter runs over the whole frame once every macroblock is
+    // reconstructed, since intra prediction uses the unfiltered samples
+    if frame.header.filter_level > 0 {
+        let simple = frame.header.simple_filter;
+        for (i, filter) in frame.filters.iter().enumerate() {
+            if filter.strength.limit == 0 {
+                continue;
+            }
+            let (mb_x, mb_y) = (i % mb_width, i / mb_width);
+            filter_plane(&mut frame.y, mb_x * 16, mb_y * 16, 16, filter, simple);
+            if !simple {
+                filter_plane(&mut frame.u, mb_x * 8, mb_y * 8, 8, filter, false);
+                filter_plane(&mut frame.v, mb_x * 8, mb_y * 8, 8, filter, false);
+            }
+        }
+    }
+
+    let rgb = yuv_to_rgb(&frame.y, &frame.u, &frame.v, width, height);
+    Ok((width as u32, height as u32, rgb))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_coefficients_do_not_overflow() {
+        // A category 6 token with every extra bit set, times the largest dequantizer
+        let largest = (67 + 2047) * 440;
+        for sign in [1, -1] {
+            let dc = inverse_wht(&[sign * largest; 16]);
+            let mut coeffs = [sign * largest; 16];
+            coeffs[0] = dc[0];
+            let mut block = [128u8; 16];
+            add_inverse_dct(&coeffs, &mut block, 0, 4);
+        }
+    }
+}
@@ -0,0 +1,823 @@
+use std::io;
+
+use super::huffman;
+use super::invalid_data;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+const BLOCK_TOKENS: usize = 1 << 16;
+
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    // 5552 is the largest run that can't overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+pub(crate) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    static TABLE: std::sync::OnceLock<[u32; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        table
+    });
+
+    let mut c = !crc;
+    for &byte in data {
+        c = table[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u64,
+    bit_count: u32,
+    // Zero bits appended after the end of the input, so peeking near the end works
+    padding: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0, bit_buf: 0, bit_count: 0, padding: 0 }
+    }
+
+    fn refill(&mut self) {
+        while self.bit_count <= 56 {
+            if self.pos < self.data.len() {
+                self.bit_buf |= (self.data[self.pos] as u64) << self.bit_count;
+                self.pos += 1;
+            } else {
+                self.padding += 8;
+            }
+            self.bit_count += 8;
+        }
+    }
+
+    pub(crate) fn peek(&mut self, n: u32) -> u32 {
+        if self.bit_count < n {
+            self.refill();
+        }
+        (self.bit_buf & ((1u64 << n) - 1)) as u32
+    }
+
+    pub(crate) fn consume(&mut self, n: u32) -> io::Result<()> {
+        if n + self.padding > self.bit_count {
+            return Err(invalid_data("unexpected end of compressed data"));
+        }
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(())
+    }
+
+    pub(crate) fn bits(&mut self, n: u32) -> io::Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+        let value = self.peek(n);
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) -> io::Result<()> {
+        self.consume(self.bit_count % 8)
+    }
+
+    // Only valid once aligned to a byte boundary
+    fn read_bytes(&mut self, mut count: usize, out: &mut Vec<u8>) -> io::Result<()> {
+        while count > 0 && self.bit_count >= 8 + self.padding {
+            out.push(self.bit_buf as u8);
+            self.consume(8)?;
+            count -= 1;
+        }
+        if count == 0 {
+            return Ok(());
+        }
+
+        // Bit buffer only holds padding now
+        if self.pos + count > self.data.len() {
+            return Err(invalid_data("unexpected end of deflate stream"));
+        }
+        out.extend_from_slice(&self.data[self.pos..self.pos + count]);
+        self.pos += count;
+        self.bit_buf = 0;
+        self.bit_count = 0;
+        self.padding = 0;
+        Ok(())
+    }
+}
+
+struct Huffman {
+    // Indexed by the next `max_len` bits of input, entries are `symbol << 4 | length`
+    table: Vec<u16>,
+    max_len: u32,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let max_len = lengths.iter().copied().max().unwrap_or(0) as u32;
+        let codes = canonical_codes(lengths)
+            .ok_or_else(|| invalid_data("over-subscribed huffman code"))?;
+
+        let mut table = vec![0u16; 1 << max_len];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            let reversed = reverse_bits(codes[symbol], len as u32) as usize;
+            let entry = ((symbol as u16) << 4) | len as u16;
+            for index in (reversed..table.len()).step_by(1 << len) {
+                table[index] = entry;
+            }
+        }
+
+        Ok(Huffman { table, max_len })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        let entry = self.table[reader.peek(self.max_len) as usize];
+        if entry == 0 {
+            return Err(invalid_data("invalid huffman code"));
+        }
+        reader.consume((entry & 15) as u32)?;
+        Ok(entry >> 4)
+    }
+}
+
+pub(crate) fn reverse_bits(code: u16, len: u32) -> u16 {
+    code.reverse_bits() >> (16 - len)
+}
+
+// Returns None if the lengths describe an over-subscribed code
+pub(crate) fn canonical_codes(lengths: &[u8]) -> Option<Vec<u16>> {
+    let mut counts = [0u32; 16];
+    for &len in lengths {
+        counts[len as usize] += 1;
+    }
+    counts[0] = 0;
+
+    let mut next_code = [0u32; 16];
+    let mut code = 0;
+    for len in 1..16 {
+        code = (code + counts[len - 1]) << 1;
+        if code + counts[len] > 1 << len {
+            return None;
+        }
+        next_code[len] = code;
+    }
+
+    let mut codes = vec![0u16; lengths.len()];
+    for (symbol, &len) in lengths.iter().enumerate() {
+        if len != 0 {
+            codes[symbol] = next_code[len as usize] as u16;
+            next_code[len as usize] += 1;
+        }
+    }
+    Some(codes)
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut literal = vec![8u8; 288];
+    literal[144..256].fill(9);
+    literal[256..280].fill(7);
+    (literal, vec![5u8; 30])
+}
+
+// Stops soon after `limit` bytes, callers never need more and a small stream
+// can otherwise expand to gigabytes
+pub(crate) fn inflate(data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::with_capacity(data.len().saturating_mul(4).min(limit));
+
+    loop {
+        let is_final = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte()?;
+                let len = reader.bits(16)?;
+                let nlen = reader.bits(16)?;
+                if len != !nlen & 0xffff {
+                    return Err(invalid_data("corrupt stored block length"));
+                }
+                reader.read_bytes(len as usize, &mut out)?;
+            }
+            1 => {
+                let (literal, distance) = fixed_lengths();
+                inflate_block(&mut reader, &mut out, &Huffman::new(&literal)?, &Huffman::new(&distance)?, limit)?;
+            }
+            2 => {
+                let (literal, distance) = read_dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literal, &distance, limit)?;
+            }
+            _ => return Err(invalid_data("invalid deflate block type")),
+        }
+
+        if is_final || out.len() > limit {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_length_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_huffman = Huffman::new(&code_length_lengths)?;
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_huffman.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or_else(|| invalid_data("repeat with no previous length"))?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() != literal_count + distance_count {
+        return Err(invalid_data("code lengths overflow table"));
+    }
+    if lengths[256] == 0 {
+        return Err(invalid_data("missing end of block code"));
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literal: &Huffman,
+    distance: &Huffman,
+    limit: usize,
+) -> io::Result<()> {
+    while out.len() <= limit {
+        let symbol = literal.decode(reader)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(invalid_data("invalid length symbol"));
+        }
+        let len = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+        let dist_symbol = distance.decode(reader)? as usize;
+        if dist_symbol >= DIST_BASE.len() {
+            return Err(invalid_data("invalid distance symbol"));
+        }
+        let dist = DIST_BASE[dist_symbol] as usize + reader.bits(DIST_EXTRA[dist_symbol] as u32)? as usize;
+        if dist > out.len() {
+            return Err(invalid_data("distance too far back"));
+        }
+
+        let start = out.len() - dist;
+        if dist >= len {
+            out.extend_from_within(start..start + len);
+        } else {
+            for i in 0..len {
+                out.push(out[start + i]);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Output past `limit` is cut off unchecked, the checksum covers the whole stream
+pub(crate) fn zlib_decompress(data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(invalid_data("zlib stream too short"));
+    }
+
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err(invalid_data("invalid zlib header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(invalid_data("zlib preset dictionaries are not supported"));
+    }
+
+    let mut out = inflate(&data[2..], limit)?;
+    if out.len() > limit {
+        out.truncate(limit);
+        return Ok(out);
+    }
+
+    let trailer = &data[data.len() - 4..];
+    let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    if adler32(&out) != expected {
+        return Err(invalid_data("zlib checksum mismatch"));
+    }
+
+    Ok(out)
+}
+
+pub(crate) struct BitWriter {
+    out: Vec<u8>,
+    bit_buf: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn new() -> Self {
+        BitWriter { out: Vec::new(), bit_buf: 0, bit_count: 0 }
+    }
+
+    pub(crate) fn write(&mut self, bits: u32, n: u32) {
+        self.bit_buf |= (bits as u64) << self.bit_count;
+        self.bit_count += n;
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.bit_count > 0 {
+            self.write(0, 8 - self.bit_count);
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.align_to_byte();
+        self.out
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match(u16, u16),
+}
+
+fn length_symbol(len: usize) -> usize {
+    match LENGTH_BASE.binary_search(&(len as u16)) {
+        Ok(index) => index,
+        Err(index) => index - 1,
+    }
+}
+
+fn distance_symbol(dist: usize) -> usize {
+    match DIST_BASE.binary_search(&(dist as u16)) {
+        Ok(index) => index,
+        Err(index) => index - 1,
+    }
+}
+
+// Greedy LZ77 with one step of lazy matching, `max_chain` bounds the hash chain walk
+fn lz77(data: &[u8], max_chain: usize) -> Vec<Token> {
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let hash = |pos: usize| -> usize {
+        let value = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+        (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    };
+
+    let insert = |pos: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(pos);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let find_match = |pos: usize, head: &Vec<usize>, prev: &Vec<usize>| -> (usize, usize) {
+        if pos + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let max_len = MAX_MATCH.min(data.len() - pos);
+        let (mut best_len, mut best_dist) = (0, 0);
+
+        let mut candidate = head[hash(pos)];
+        let mut chain = max_chain;
+        while candidate != usize::MAX && chain > 0 && pos - candidate < WINDOW_SIZE {
+            if data[candidate + best_len.min(max_len - 1)] == data[pos + best_len.min(max_len - 1)] {
+                let len = data[candidate..candidate + max_len]
+                    .iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+
+            let next = prev[candidate % WINDOW_SIZE];
+            if next == usize::MAX || next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+
+        if best_len >= MIN_MATCH { (best_len, best_dist) } else { (0, 0) }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (len, dist) = find_match(pos, &head, &prev);
+        insert(pos, &mut head, &mut prev);
+
+        if len == 0 {
+            tokens.push(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+
+        // Prefer a longer match starting at the next byte
+        let (next_len, _) = find_match(pos + 1, &head, &prev);
+        if next_len > len {
+            tokens.push(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+
+        tokens.push(Token::Match(len as u16, dist as u16));
+        for i in 1..len {
+            insert(pos + i, &mut head, &mut prev);
+        }
+        pos += len;
+    }
+
+    tokens
+}
+
+pub(crate) fn reversed_codes(lengths: &[u8]) -> Vec<u16> {
+    let codes = canonical_codes(lengths).expect("generated huffman lengths are valid");
+    codes
+        .iter()
+        .zip(lengths)
+        .map(|(&code, &len)| if len == 0 { 0 } else { reverse_bits(code, len as u32) })
+        .collect()
+}
+
+// Run-length encodes code lengths into (symbol, extra bits value) pairs
+pub(crate) fn encode_code_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = 1;
+        while i + run < lengths.len() && lengths[i + run] == value {
+            run += 1;
+        }
+        i += run;
+
+        if value == 0 {
+            while run >= 11 {
+                let count = run.min(138);
+                encoded.push((18, (count - 11) as u8));
+                run -= count;
+            }
+            if run >= 3 {
+                encoded.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            encoded.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let count = run.min(6);
+                encoded.push((16, (count - 3) as u8));
+                run -= count;
+            }
+        }
+
+        for _ in 0..run {
+            encoded.push((value, 0));
+        }
+    }
+
+    encoded
+}
+
+fn token_cost(tokens: &[Token], literal: &[u8], distance: &[u8]) -> usize {
+    tokens
+        .iter()
+        .map(|token| match *token {
+            Token::Literal(byte) => literal[byte as usize] as usize,
+            Token::Match(len, dist) => {
+                let len_symbol = length_symbol(len as usize);
+                let dist_symbol = distance_symbol(dist as usize);
+                literal[257 + len_symbol] as usize
+                    + LENGTH_EXTRA[len_symbol] as usize
+                    + distance[dist_symbol] as usize
+                    + DIST_EXTRA[dist_symbol] as usize
+            }
+        })
+        .sum::<usize>()
+        + literal[256] as usize
+}
+
+fn write_tokens(writer: &mut BitWriter, tokens: &[Token], literal: &[u8], distance: &[u8]) {
+    let literal_codes = reversed_codes(literal);
+    let distance_codes = reversed_codes(distance);
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => {
+                writer.write(literal_codes[byte as usize] as u32, literal[byte as usize] as u32);
+            }
+            Token::Match(len, dist) => {
+                let len_symbol = length_symbol(len as usize);
+                writer.write(literal_codes[257 + len_symbol] as u32, literal[257 + len_symbol] as u32);
+                writer.write(
+                    (len - LENGTH_BASE[len_symbol]) as u32,
+                    LENGTH_EXTRA[len_symbol] as u32,
+                );
+
+                let dist_symbol = distance_symbol(dist as usize);
+                writer.write(distance_codes[dist_symbol] as u32, distance[dist_symbol] as u32);
+                writer.write(
+                    (dist - DIST_BASE[dist_symbol]) as u32,
+                    DIST_EXTRA[dist_symbol] as u32,
+                );
+            }
+        }
+    }
+    writer.write(literal_codes[256] as u32, literal[256] as u32);
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], raw: &[u8], is_final: bool) {
+    let mut literal_freqs = vec![0u32; 286];
+    let mut distance_freqs = vec![0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literal_freqs[byte as usize] += 1,
+            Token::Match(len, dist) => {
+                literal_freqs[257 + length_symbol(len as usize)] += 1;
+                distance_freqs[distance_symbol(dist as usize)] += 1;
+            }
+        }
+    }
+    literal_freqs[256] = 1;
+
+    let literal = huffman::code_lengths(&literal_freqs, 15);
+    let mut distance = huffman::code_lengths(&distance_freqs, 15);
+    // Keep the distance tree complete for strict decoders
+    if distance.iter().filter(|&&len| len > 0).count() < 2 {
+        let first = distance.iter().position(|&len| len > 0).unwrap_or(0);
+        distance[first] = 1;
+        distance[if first == 0 { 1 } else { 0 }] = 1;
+    }
+
+    let literal_count = 257.max(literal.iter().rposition(|&len| len > 0).unwrap_or(0) + 1);
+    let distance_count = 1.max(distance.iter().rposition(|&len| len > 0).unwrap_or(0) + 1);
+
+    let mut all_lengths = literal[..literal_count].to_vec();
+    all_lengths.extend_from_slice(&distance[..distance_count]);
+    let code_lengths = encode_code_lengths(&all_lengths);
+
+    let mut code_length_freqs = vec![0u32; 19];
+    for &(symbol, _) in &code_lengths {
+        code_length_freqs[symbol as usize] += 1;
+    }
+    let code_length_lengths = huffman::code_lengths(&code_length_freqs, 7);
+    let code_length_count = 4.max(
+        CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&symbol| code_length_lengths[symbol] > 0)
+            .unwrap_or(0)
+            + 1,
+    );
+
+    let header_cost = 14
+        + code_length_count * 3
+        + code_lengths
+            .iter()
+            .map(|&(symbol, _)| {
+                code_length_lengths[symbol as usize] as usize
+                    + match symbol {
+                        16 => 2,
+                        17 => 3,
+                        18 => 7,
+                        _ => 0,
+                    }
+            })
+            .sum::<usize>();
+    let dynamic_cost = header_cost + token_cost(tokens, &literal, &distance);
+
+    let (fixed_literal, fixed_distance) = fixed_lengths();
+    let fixed_cost = token_cost(tokens, &fixed_literal, &fixed_distance);
+
+    // Stored blocks need a 5 byte header per 65535 bytes
+    let stored_cost = (raw.len() + 5 * raw.len().div_ceil(65535).max(1)) * 8;
+
+    if stored_cost <= dynamic_cost && stored_cost <= fixed_cost {
+        write_stored(writer, raw, is_final);
+        return;
+    }
+
+    writer.write(is_final as u32, 1);
+    if fixed_cost <= dynamic_cost {
+        writer.write(1, 2);
+        write_tokens(writer, tokens, &fixed_literal, &fixed_distance);
+        return;
+    }
+
+    writer.write(2, 2);
+    writer.write((literal_count - 257) as u32, 5);
+    writer.write((distance_count - 1) as u32, 5);
+    writer.write((code_length_count - 4) as u32, 4);
+    for &symbol in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        writer.write(code_length_lengths[symbol] as u32, 3);
+    }
+
+    let code_length_codes = reversed_codes(&code_length_lengths);
+    for &(symbol, extra) in &code_lengths {
+        writer.write(code_length_codes[symbol as usize] as u32, code_length_lengths[symbol as usize] as u32);
+        match symbol {
+            16 => writer.write(extra as u32, 2),
+            17 => writer.write(extra as u32, 3),
+            18 => writer.write(extra as u32, 7),
+            _ => {}
+        }
+    }
+
+    write_tokens(writer, tokens, &literal, &distance);
+}
+
+fn write_stored(writer: &mut BitWriter, raw: &[u8], is_final: bool) {
+    let chunks: Vec<&[u8]> = if raw.is_empty() { vec![raw] } else { raw.chunks(65535).collect() };
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        writer.write((is_final && i == chunks.len() - 1) as u32, 1);
+        writer.write(0, 2);
+        writer.align_to_byte();
+        writer.write(chunk.len() as u32, 16);
+        writer.write(!chunk.len() as u32 & 0xffff, 16);
+        writer.out.extend_from_slice(chunk);
+    }
+}
+
+// `level` ranges from 0 (stored) to 9 (slowest, smallest)
+pub(crate) fn deflate(data: &[u8], level: u8) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+
+    if level == 0 {
+        write_stored(&mut writer, data, true);
+        return writer.finish();
+    }
+
+    let max_chain = match level.min(9) {
+        1 => 4,
+        2 => 8,
+        3 => 16,
+        4 => 32,
+        5 => 64,
+        6 => 128,
+        7 => 256,
+        8 => 1024,
+        _ => 4096,
+    };
+    let tokens = lz77(data, max_chain);
+
+    if tokens.is_empty() {
+        write_block(&mut writer, &[], data, true);
+        return writer.finish();
+    }
+
+    let mut raw_start = 0;
+    let block_count = tokens.len().div_ceil(BLOCK_TOKENS);
+    for (i, block) in tokens.chunks(BLOCK_TOKENS).enumerate() {
+        let raw_len: usize = block
+            .iter()
+            .map(|token| match *token {
+                Token::Literal(_) => 1,
+                Token::Match(len, _) => len as usize,
+            })
+            .sum();
+        write_block(&mut writer, block, &data[raw_start..raw_start + raw_len], i == block_count - 1);
+        raw_start += raw_len;
+    }
+
+    writer.finish()
+}
+
+pub(crate) fn zlib_compress(data: &[u8], level: u8) -> Vec<u8> {
+    let flevel = match level {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let cmf: u16 = 0x78;
+    let mut flg: u16 = flevel << 6;
+    flg += 31 - (cmf * 256 + flg) % 31;
+
+    let mut out = vec![cmf as u8, flg as u8];
+    out.extend(deflate(data, level));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // Streams written by the reference zlib, one per block type
+    #[test]
+    fn inflates_reference_streams() {
+        assert_eq!(zlib_decompress(&hex("7801010500faff68656c6c6f062c0215"), usize::MAX).unwrap(), b"hello");
+        assert_eq!(zlib_decompress(&hex("78dacb48cdc9c90700062c0215"), usize::MAX).unwrap(), b"hello");
+
+        let dynamic = hex(concat!(
+            "78daed8bc11100400c016b45f4dfc2892e32735e765890922631c082c6e90b",
+            "cce002d89397be71dc7837c9c15d",
+        ));
+        let expected: Vec<u8> = (0..200).flat_map(|i| vec![b'a' + (i % 5) as u8; i % 4 + 1]).collect();
+        assert_eq!(zlib_decompress(&dynamic, usize::MAX).unwrap(), expected);
+    }
+
+    #[test]
+    fn round_trips_every_level() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i * i % 251) as u8 ^ (i / 300) as u8).collect();
+        for level in 0..=9 {
+            assert_eq!(zlib_decompress(&zlib_compress(&data, level), usize::MAX).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn output_is_cut_off_at_the_limit() {
+        let data = zlib_compress(&[1; 1 << 20], 9);
+        assert_eq!(zlib_decompress(&data, 1 << 20).unwrap().len(), 1 << 20);
+        assert_eq!(zlib_decompress(&data, 100).unwrap(), vec![1; 100]);
+    }
+
+    #[test]
+    fn checksum_is_verified_up_to_the_limit() {
+        let mut data = zlib_compress(b"checksummed", 6);
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(zlib_decompress(&data, 11).is_err());
+    }
+}
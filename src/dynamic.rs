@@ -0,0 +1,436 @@
+use std::any::Any;
+use std::marker::PhantomData;
+
+use super::{Image, Pixel, Primitive};
+use super::{Luma16, Luma32F, Luma8, LumaA16, LumaA32F, LumaA8, Rgb16, Rgb32F, Rgb8, Rgba16, Rgba32F, Rgba8};
+
+// An image whose pixel type is only known at runtime, as produced by the decoders
+#[derive(Debug, Clone)]
+pub enum DynamicImage {
+    Luma8(Image<Luma8>),
+    LumaA8(Image<LumaA8>),
+    Rgb8(Image<Rgb8>),
+    Rgba8(Image<Rgba8>),
+    Luma16(Image<Luma16>),
+    LumaA16(Image<LumaA16>),
+    Rgb16(Image<Rgb16>),
+    Rgba16(Image<Rgba16>),
+    Luma32F(Image<Luma32F>),
+    LumaA32F(Image<LumaA32F>),
+    Rgb32F(Image<Rgb32F>),
+    Rgba32F(Image<Rgba32F>),
+}
+
+// A borrowed `DynamicImage`, which is what the encoders take so that typed
+// images can be saved without copying their pixels
+#[derive(Debug, Clone, Copy)]
+pub enum DynamicImageRef<'a> {
+    Luma8(&'a Image<Luma8>),
+    LumaA8(&'a Image<LumaA8>),
+    Rgb8(&'a Image<Rgb8>),
+    Rgba8(&'a Image<Rgba8>),
+    Luma16(&'a Image<Luma16>),
+    LumaA16(&'a Image<LumaA16>),
+    Rgb16(&'a Image<Rgb16>),
+    Rgba16(&'a Image<Rgba16>),
+    Luma32F(&'a Image<Luma32F>),
+    LumaA32F(&'a Image<LumaA32F>),
+    Rgb32F(&'a Image<Rgb32F>),
+    Rgba32F(&'a Image<Rgba32F>),
+}
+
+// An image borrowed as it was, or converted into a new one
+pub(crate) enum Converted<'a> {
+    Borrowed(DynamicImageRef<'a>),
+    Owned(DynamicImage),
+}
+
+impl Converted<'_> {
+    pub(crate) fn as_ref(&self) -> DynamicImageRef<'_> {
+        match self {
+            Converted::Borrowed(image) => *image,
+            Converted::Owned(image) => image.as_ref(),
+        }
+    }
+
+    pub(crate) fn into_owned(self) -> DynamicImage {
+        match self {
+            Converted::Borrowed(image) => image.to_dynamic(),
+            Converted::Owned(image) => image,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleDepth {
+    U8,
+    U16,
+    F32,
+}
+
+// Runs the same expression on whichever typed image is inside
+macro_rules! dynamic_map {
+    ($kind:ident: $dynamic:expr, $image:ident => $body:expr) => {
+        match $dynamic {
+            $kind::Luma8($image) => $body,
+            $kind::LumaA8($image) => $body,
+            $kind::Rgb8($image) => $body,
+            $kind::Rgba8($image) => $body,
+            $kind::Luma16($image) => $body,
+            $kind::LumaA16($image) => $body,
+            $kind::Rgb16($image) => $body,
+            $kind::Rgba16($image) => $body,
+            $kind::Luma32F($image) => $body,
+            $kind::LumaA32F($image) => $body,
+            $kind::Rgb32F($image) => $body,
+            $kind::Rgba32F($image) => $body,
+        }
+    };
+}
+
+fn typed<P: Pixel>(width: u32, height: u32, data: Vec<P::Subpixel>) -> Image<P> {
+    debug_assert_eq!(data.len(), width as usize * height as usize * P::CHANNEL_COUNT as usize);
+    Image { width, height, data, pixel: PhantomData }
+}
+
+impl DynamicImage {
+    // Wraps interleaved samples, `data` must hold width * height * channels samples
+    pub(crate) fn from_raw_parts(channels: u8, width: u32, height: u32, data: Vec<u8>) -> DynamicImage {
+        match channels {
+            1 => DynamicImage::Luma8(typed(width, height, data)),
+            2 => DynamicImage::LumaA8(typed(width, height, data)),
+            3 => DynamicImage::Rgb8(typed(width, height, data)),
+            4 => DynamicImage::Rgba8(typed(width, height, data)),
+            _ => unreachable!("images have 1 to 4 channels"),
+        }
+    }
+
+    pub(crate) fn from_raw_parts16(channels: u8, width: u32, height: u32, data: Vec<u16>) -> DynamicImage {
+        match channels {
+            1 => DynamicImage::Luma16(typed(width, height, data)),
+            2 => DynamicImage::LumaA16(typed(width, height, data)),
+            3 => DynamicImage::Rgb16(typed(width, height, data)),
+            4 => DynamicImage::Rgba16(typed(width, height, data)),
+            _ => unreachable!("images have 1 to 4 channels"),
+        }
+    }
+
+    pub(crate) fn from_raw_parts32f(channels: u8, width: u32, height: u32, data: Vec<f32>) -> DynamicImage {
+        match channels {
+            1 => DynamicImage::Luma32F(typed(width, height, data)),
+            2 => DynamicImage::LumaA32F(typed(width, height, data)),
+            3 => DynamicImage::Rgb32F(typed(width, height, data)),
+            4 => DynamicImage::Rgba32F(typed(width, height, data)),
+            _ => unreachable!("images have 1 to 4 channels"),
+        }
+    }
+
+    pub fn as_ref(&self) -> DynamicImageRef<'_> {
+        dynamic_map!(DynamicImage: self, image => image.into())
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.as_ref().as_bytes()
+    }
+
+    // Converts to the given depth, keeping the channel layout
+    pub fn to_depth(&self, depth: SampleDepth) -> DynamicImage {
+        self.as_ref().to_depth(depth)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.as_ref().width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.as_ref().height()
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.as_ref().channels()
+    }
+
+    pub fn depth(&self) -> SampleDepth {
+        self.as_ref().depth()
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.as_ref().has_alpha()
+    }
+
+    // Converts to any pixel type, copying the pixels even when they already match
+    pub fn to_image<P: Pixel>(&self) -> Image<P> {
+        self.as_ref().to_image()
+    }
+
+    // Converts to any pixel type, handing the image over as-is when it already matches
+    pub fn into_image<P: Pixel>(self) -> Image<P> {
+        dynamic_map!(DynamicImage: self, image => {
+            let mut image = Some(image);
+            if let Some(same) = (&mut image as &mut dyn Any).downcast_mut::<Option<Image<P>>>() {
+                return same.take().unwrap();
+            }
+            image.unwrap().convert()
+        })
+    }
+
+    pub fn to_luma8(&self) -> Image<Luma8> {
+        self.to_image()
+    }
+
+    pub fn to_luma_alpha8(&self) -> Image<LumaA8> {
+        self.to_image()
+    }
+
+    pub fn to_rgb8(&self) -> Image<Rgb8> {
+        self.to_image()
+    }
+
+    pub fn to_rgba8(&self) -> Image<Rgba8> {
+        self.to_image()
+    }
+
+    pub fn to_luma16(&self) -> Image<Luma16> {
+        self.to_image()
+    }
+
+    pub fn to_rgb16(&self) -> Image<Rgb16> {
+        self.to_image()
+    }
+
+    pub fn to_rgba16(&self) -> Image<Rgba16> {
+        self.to_image()
+    }
+
+    pub fn to_rgb32f(&self) -> Image<Rgb32F> {
+        self.to_image()
+    }
+
+    pub fn to_rgba32f(&self) -> Image<Rgba32F> {
+        self.to_image()
+    }
+}
+
+impl<'a> DynamicImageRef<'a> {
+    // The samples of an 8-bit image, encoders convert other depths first
+    pub(crate) fn as_bytes(self) -> &'a [u8] {
+        match self {
+            DynamicImageRef::Luma8(image) => &image.data,
+            DynamicImageRef::LumaA8(image) => &image.data,
+            DynamicImageRef::Rgb8(image) => &image.data,
+            DynamicImageRef::Rgba8(image) => &image.data,
+            _ => unreachable!("only 8-bit images expose their bytes"),
+        }
+    }
+
+    pub(crate) fn as_samples16(self) -> &'a [u16] {
+        match self {
+            DynamicImageRef::Luma16(image) => &image.data,
+            DynamicImageRef::LumaA16(image) => &image.data,
+            DynamicImageRef::Rgb16(image) => &image.data,
+            DynamicImageRef::Rgba16(image) => &image.data,
+            _ => unreachable!("only 16-bit images expose 16-bit samples"),
+        }
+    }
+
+    pub fn to_depth(self, depth: SampleDepth) -> DynamicImage {
+        fn convert<T: Primitive, U: Primitive>(data: &[T]) -> Vec<U> {
+            data.iter().map(|&sample| sample.convert()).collect()
+        }
+
+        let (channels, width, height) = (self.channels(), self.width(), self.height());
+        match depth {
+            SampleDepth::U8 => DynamicImage::from_raw_parts(channels, width, height, dynamic_map!(DynamicImageRef: self, image => convert(&image.data))),
+            SampleDepth::U16 => DynamicImage::from_raw_parts16(channels, width, height, dynamic_map!(DynamicImageRef: self, image => convert(&image.data))),
+            SampleDepth::F32 => DynamicImage::from_raw_parts32f(channels, width, height, dynamic_map!(DynamicImageRef: self, image => convert(&image.data))),
+        }
+    }
+
+    // Borrows the image when it already has the requested depth
+    pub(crate) fn with_depth(self, depth: SampleDepth) -> Converted<'a> {
+        if self.depth() == depth {
+            Converted::Borrowed(self)
+        } else {
+            Converted::Owned(self.to_depth(depth))
+        }
+    }
+
+    // For encoders that only store 8-bit color, grayscale is expanded to RGB
+    pub(crate) fn with_rgb8(self) -> Converted<'a> {
+        match self {
+            DynamicImageRef::Rgb8(_) | DynamicImageRef::Rgba8(_) => Converted::Borrowed(self),
+            _ if self.has_alpha() => Converted::Owned(self.to_image::<Rgba8>().into()),
+            _ => Converted::Owned(self.to_image::<Rgb8>().into()),
+        }
+    }
+
+    pub fn width(self) -> u32 {
+        dynamic_map!(DynamicImageRef: self, image => image.width)
+    }
+
+    pub fn height(self) -> u32 {
+        dynamic_map!(DynamicImageRef: self, image => image.height)
+    }
+
+    pub fn channels(self) -> u8 {
+        dynamic_map!(DynamicImageRef: self, image => image.channels())
+    }
+
+    pub fn depth(self) -> SampleDepth {
+        match self {
+            DynamicImageRef::Luma8(_) | DynamicImageRef::LumaA8(_) | DynamicImageRef::Rgb8(_) | DynamicImageRef::Rgba8(_) => SampleDepth::U8,
+            DynamicImageRef::Luma16(_) | DynamicImageRef::LumaA16(_) | DynamicImageRef::Rgb16(_) | DynamicImageRef::Rgba16(_) => SampleDepth::U16,
+            _ => SampleDepth::F32,
+        }
+    }
+
+    pub fn has_alpha(self) -> bool {
+        self.channels().is_multiple_of(2)
+    }
+
+    pub fn to_image<P: Pixel>(self) -> Image<P> {
+        dynamic_map!(DynamicImageRef: self, image => image.convert())
+    }
+
+    pub fn to_dynamic(self) -> DynamicImage {
+        dynamic_map!(DynamicImageRef: self, image => image.clone().into())
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    // Copies the image into the matching `DynamicImage` variant
+    pub fn to_dynamic(&self) -> DynamicImage {
+        self.as_dynamic().into_owned()
+    }
+
+    // Borrows images of the built-in pixel types, others are converted like in `into_dynamic`
+    pub(crate) fn as_dynamic(&self) -> Converted<'_> {
+        let any = self as &dyn Any;
+
+        macro_rules! try_variant {
+            ($($pixel:ident),*) => {
+                $(
+                    if let Some(image) = any.downcast_ref::<Image<$pixel>>() {
+                        return Converted::Borrowed(DynamicImageRef::$pixel(image));
+                    }
+                )*
+            };
+        }
+        try_variant!(Luma8, LumaA8, Rgb8, Rgba8, Luma16, LumaA16, Rgb16, Rgba16, Luma32F, LumaA32F, Rgb32F, Rgba32F);
+
+        Converted::Owned(DynamicImage::Rgba32F(self.convert()))
+    }
+
+    // Pixel types defined outside the crate become RGBA floats
+    pub fn into_dynamic(self) -> DynamicImage {
+        let mut image = Some(self);
+        let any = &mut image as &mut dyn Any;
+
+        macro_rules! try_variant {
+            ($($pixel:ident),*) => {
+                $(
+                    if let Some(image) = any.downcast_mut::<Option<Image<$pixel>>>() {
+                        return DynamicImage::$pixel(image.take().unwrap());
+                    }
+                )*
+            };
+        }
+        try_variant!(Luma8, LumaA8, Rgb8, Rgba8, Luma16, LumaA16, Rgb16, Rgba16, Luma32F, LumaA32F, Rgb32F, Rgba32F);
+
+        DynamicImage::Rgba32F(image.unwrap().convert())
+    }
+}
+
+macro_rules! impl_from_image {
+    ($($pixel:ident),*) => {
+        $(
+            impl From<Image<$pixel>> for DynamicImage {
+                fn from(image: Image<$pixel>) -> Self {
+                    DynamicImage::$pixel(image)
+                }
+            }
+
+            impl<'a> From<&'a Image<$pixel>> for DynamicImageRef<'a> {
+                fn from(image: &'a Image<$pixel>) -> Self {
+                    DynamicImageRef::$pixel(image)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_image!(Luma8, LumaA8, Rgb8, Rgba8, Luma16, LumaA16, Rgb16, Rgba16, Luma32F, LumaA32F, Rgb32F, Rgba32F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rgba;
+
+    #[test]
+    fn matching_pixel_types_are_handed_over_without_copying() {
+        let image = Image::<Rgb16>::from_raw(1, 1, vec![1, 2, 3]).unwrap();
+        let samples = image.as_bytes().as_ptr();
+        assert!(matches!(image.as_dynamic(), Converted::Borrowed(DynamicImageRef::Rgb16(borrowed)) if borrowed.as_bytes().as_ptr() == samples));
+
+        let dynamic = image.into_dynamic();
+        assert!(matches!(dynamic, DynamicImage::Rgb16(_)));
+        let image: Image<Rgb16> = dynamic.into_image();
+        assert_eq!(image.as_bytes().as_ptr(), samples);
+
+        // Anything else is a copy
+        assert_ne!(image.to_dynamic().to_image::<Rgb16>().as_bytes().as_ptr(), samples);
+    }
+
+    #[test]
+    fn gray_expands_to_color_and_averages_back() {
+        let gray = DynamicImage::from(Image::<LumaA8>::from_raw(2, 1, vec![10, 255, 200, 0]).unwrap());
+        assert_eq!(gray.to_rgba8().as_bytes(), [10, 10, 10, 255, 200, 200, 200, 0]);
+        assert_eq!(gray.to_rgb8().as_bytes(), [10, 10, 10, 200, 200, 200]);
+
+        let color = DynamicImage::from(Image::<Rgb8>::from_raw(1, 1, vec![30, 60, 91]).unwrap());
+        assert_eq!(color.to_luma8().as_bytes(), [60]);
+        assert_eq!(color.to_luma_alpha8().as_bytes(), [60, 255]);
+        assert_eq!(color.to_rgba8().as_bytes(), [30, 60, 91, 255]);
+    }
+
+    #[test]
+    fn depths_rescale_to_the_full_range() {
+        let image = DynamicImage::from_raw_parts(2, 2, 1, vec![0, 128, 255, 51]);
+        let deep = image.to_depth(SampleDepth::U16);
+        assert_eq!((deep.depth(), deep.channels()), (SampleDepth::U16, 2));
+        assert_eq!(deep.as_ref().as_samples16(), [0, 0x8080, 0xffff, 0x3333]);
+        assert_eq!(deep.to_depth(SampleDepth::U8).as_bytes(), image.as_bytes());
+
+        let float = image.to_depth(SampleDepth::F32).to_image::<LumaA32F>();
+        assert_eq!(float.as_bytes(), [0.0, 128.0 / 255.0, 1.0, 0.2]);
+        assert_eq!(DynamicImage::from(float).to_depth(SampleDepth::U8).as_bytes(), image.as_bytes());
+    }
+
+    #[test]
+    fn encoders_get_converted_copies_only_when_needed() {
+        let rgba = DynamicImage::from(Image::<Rgba8>::new(1, 1));
+        assert!(matches!(rgba.as_ref().with_rgb8(), Converted::Borrowed(_)));
+        assert!(matches!(rgba.as_ref().with_depth(SampleDepth::U8), Converted::Borrowed(_)));
+
+        let gray = DynamicImage::from(Image::<LumaA16>::from_raw(1, 1, vec![0xffff, 0x8080]).unwrap());
+        let rgb = gray.as_ref().with_rgb8();
+        assert_eq!(rgb.as_ref().as_bytes(), [255, 255, 255, 128]);
+        assert!(matches!(gray.as_ref().with_depth(SampleDepth::U16), Converted::Borrowed(_)));
+    }
+
+    #[test]
+    fn typed_file_helpers_convert_on_the_way_in() {
+        let path = std::env::temp_dir().join(format!("imagely-typed-{}.png", std::process::id()));
+        let path = path.to_str().unwrap();
+        let image = Image::<Rgba<u16>>::from_raw(2, 1, vec![0xffff, 0, 0, 0xffff, 0x1234, 0x1234, 0x1234, 0x8000]).unwrap();
+        image.save_png(path).unwrap();
+        let same = Image::<Rgba16>::open_png(path).unwrap();
+        let gray = Image::<LumaA8>::open_png(path).unwrap();
+        let opened = DynamicImage::open_png(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(same.as_bytes(), image.as_bytes());
+        assert_eq!(gray.as_bytes(), [85, 255, 18, 128]);
+        assert!(matches!(opened, DynamicImage::Rgba16(_)));
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ImageError {
+    // A pixel buffer whose length doesn't match width * height * channels
+    DimensionMismatch { expected: usize, actual: usize },
+    UnsupportedChannels(u8),
+    // A rectangle (x, y, width, height) that doesn't fit inside the image
+    OutOfBounds { rect: (u32, u32, u32, u32), width: u32, height: u32 },
+    InvalidParameter(String),
+    // Malformed or truncated file contents
+    Decoding(String),
+    // Well-formed input using a feature or format the crate can't handle
+    Unsupported(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::DimensionMismatch { expected, actual } => {
+                write!(f, "expected {expected} bytes of pixel data, got {actual}")
+            }
+            ImageError::UnsupportedChannels(channels) => write!(f, "unsupported number of channels: {channels}"),
+            ImageError::OutOfBounds { rect: (x, y, w, h), width, height } => {
+                write!(f, "rectangle {w}x{h} at ({x}, {y}) does not fit in a {width}x{height} image")
+            }
+            ImageError::InvalidParameter(message) => write!(f, "invalid parameter: {message}"),
+            ImageError::Decoding(message) => write!(f, "decoding error: {message}"),
+            ImageError::Unsupported(message) => write!(f, "unsupported: {message}"),
+            ImageError::Io(error) => write!(f, "I/O error: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImageError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// The codecs report problems as `io::Error`s tagged with InvalidData or
+// Unsupported, everything else comes from the file system
+impl From<io::Error> for ImageError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::InvalidData => ImageError::Decoding(error.to_string()),
+            io::ErrorKind::Unsupported => ImageError::Unsupported(error.to_string()),
+            _ => ImageError::Io(error),
+        }
+    }
+}
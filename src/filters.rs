@@ -1,149 +1,1337 @@
 use std::f64::consts::E;
-use std::f64::consts::PI;
+use std::thread;
 
-use super::Image;
+use super::{Image, ImageError, ImageViewMut, Pixel, Primitive};
 
-fn gaussian(x: isize, y: isize, sigma: f64) -> f64 {
-    let exponent = -((x.pow(2) as f64 + y.pow(2) as f64) / (2.0 * sigma.powf(2.0)));
-    (1.0 / (2.0 * PI * sigma.powf(2.0))) * E.powf(exponent)
+fn gaussian(x: isize, sigma: f64) -> f64 {
+    E.powf(-(x.pow(2) as f64) / (2.0 * sigma.powf(2.0)))
+}
+
+fn check_sigma(sigma: f64) -> Result<(), ImageError> {
+    if !(sigma > 0.0 && sigma.is_finite()) {
+        return Err(ImageError::InvalidParameter(format!("sigma must be positive, got {sigma}")));
+    }
+    Ok(())
+}
+
+// Weights laid out row by row, centered on the pixel being computed (on the
+// right or lower middle cell for even sizes). Kernels are applied as they are
+// written, without flipping them first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+    width: usize,
+    height: usize,
+    weights: Vec<f64>,
+    // The horizontal and vertical kernels whose product gives `weights`, if known
+    factors: Option<(Vec<f64>, Vec<f64>)>,
 }
 
-struct Kernel;
 impl Kernel {
-    fn gaussian(size: usize, sigma: f64) -> Vec<f64> {
+    pub fn new(width: usize, height: usize, weights: Vec<f64>) -> Kernel {
+        Kernel::try_new(width, height, weights).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn try_new(width: usize, height: usize, weights: Vec<f64>) -> Result<Kernel, ImageError> {
+        if width == 0 || height == 0 {
+            return Err(ImageError::InvalidParameter(format!("a {width}x{height} kernel is empty")));
+        }
+        if weights.len() != width * height {
+            return Err(ImageError::InvalidParameter(format!(
+                "a {width}x{height} kernel needs {} weights, got {}",
+                width * height,
+                weights.len()
+            )));
+        }
+        Ok(Kernel { width, height, weights, factors: None })
+    }
+
+    // A kernel that is the product of a row and a column, convolving with it
+    // takes two passes of one dimension each instead of a full 2D one
+    pub fn separable(horizontal: Vec<f64>, vertical: Vec<f64>) -> Kernel {
+        Kernel::try_separable(horizontal, vertical).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn try_separable(horizontal: Vec<f64>, vertical: Vec<f64>) -> Result<Kernel, ImageError> {
+        let weights = vertical.iter().flat_map(|&y| horizontal.iter().map(move |&x| x * y)).collect();
+        let kernel = Kernel::try_new(horizontal.len(), vertical.len(), weights)?;
+        Ok(Kernel { factors: Some((horizontal, vertical)), ..kernel })
+    }
+
+    // Covers three sigmas each side, past that the weights are negligible
+    pub fn gaussian(sigma: f64) -> Kernel {
+        Kernel::try_gaussian(sigma).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn try_gaussian(sigma: f64) -> Result<Kernel, ImageError> {
+        check_sigma(sigma)?;
+        Kernel::try_gaussian_with_size(2 * (3.0 * sigma).ceil() as usize + 1, sigma)
+    }
+
+    pub fn gaussian_with_size(size: usize, sigma: f64) -> Kernel {
+        Kernel::try_gaussian_with_size(size, sigma).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn try_gaussian_with_size(size: usize, sigma: f64) -> Result<Kernel, ImageError> {
+        if size.is_multiple_of(2) {
+            return Err(ImageError::InvalidParameter(format!("kernel size must be odd, got {size}")));
+        }
+        check_sigma(sigma)?;
+
         let amplitude = (size as isize - 1) / 2;
+        let mut weights: Vec<f64> = (-amplitude..(amplitude + 1)).map(|x| gaussian(x, sigma)).collect();
 
-        let mut kernel = Vec::with_capacity(size * size);
+        // Normalize kernel
+        let weight_sum: f64 = weights.iter().sum();
+        for weight in weights.iter_mut() {
+            *weight /= weight_sum;
+        }
+
+        Kernel::try_separable(weights.clone(), weights)
+    }
+
+    pub fn sharpen() -> Kernel {
+        Kernel::new(3, 3, vec![0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0])
+    }
+
+    pub fn emboss() -> Kernel {
+        Kernel::new(3, 3, vec![-2.0, -1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0, 2.0])
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    pub fn is_separable(&self) -> bool {
+        self.factors.is_some()
+    }
+}
 
-        for x in -amplitude..(amplitude + 1) {
-            for y in -amplitude..(amplitude + 1) {
-                kernel.push(gaussian(x, y, sigma));
+// What the kernel sees past the edges of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BorderMode<P: Pixel> {
+    // Repeats the edge pixels
+    Clamp,
+    // Reflects the image around its edge pixels, without repeating them
+    Mirror,
+    // Continues from the opposite edge
+    Wrap,
+    Constant(P),
+    // Leaves out the weights that fall outside and scales up the rest, so the
+    // result keeps the brightness it would have inside the image
+    Skip,
+}
+
+// `BorderMode` with the constant premultiplied like the samples it is mixed with
+#[derive(Clone, Copy)]
+enum Border {
+    Clamp,
+    Mirror,
+    Wrap,
+    Constant([f64; 4]),
+    Skip,
+}
+
+impl Border {
+    fn new<P: Pixel>(mode: BorderMode<P>) -> Border {
+        match mode {
+            BorderMode::Clamp => Border::Clamp,
+            BorderMode::Mirror => Border::Mirror,
+            BorderMode::Wrap => Border::Wrap,
+            BorderMode::Constant(pixel) => {
+                let mut constant = [0.0; 4];
+                for (value, sample) in constant.iter_mut().zip(premultiply(pixel)) {
+                    *value = sample;
+                }
+                Border::Constant(constant)
             }
+            BorderMode::Skip => Border::Skip,
         }
+    }
 
-        // Normalize kernel
-        let kernel_sum: f64 = kernel.iter().sum();
-        for i in 0..kernel.len() {
-            kernel[i] = kernel[i] / kernel_sum;
+    // The sample a kernel tap at `position` reads, `None` when it falls outside
+    fn resolve(self, position: isize, len: usize) -> Option<usize> {
+        if 0 <= position && position < len as isize {
+            return Some(position as usize);
+        }
+        let last = len as isize - 1;
+        match self {
+            Border::Clamp => Some(position.clamp(0, last) as usize),
+            Border::Mirror if len == 1 => Some(0),
+            Border::Mirror => {
+                let position = position.rem_euclid(2 * last);
+                Some(if position > last { 2 * last - position } else { position } as usize)
+            }
+            Border::Wrap => Some(position.rem_euclid(len as isize) as usize),
+            Border::Constant(_) | Border::Skip => None,
         }
+    }
+}
 
-        kernel
+// Colors multiplied by alpha, all normalized, followed by alpha itself
+fn premultiply<P: Pixel>(pixel: P) -> impl Iterator<Item = f64> {
+    let channels = pixel.channels();
+    let alpha = if P::HAS_ALPHA { channels[P::COLOR_CHANNELS].to_normalized() } else { 1.0 };
+    let colors: [f64; 4] = std::array::from_fn(|c| channels.get(c).map_or(0.0, |sample| sample.to_normalized() * alpha));
+
+    colors.into_iter().take(P::COLOR_CHANNELS).chain(P::HAS_ALPHA.then_some(alpha))
+}
+
+// Splits `output` into bands of whole rows and fills each band on its own thread.
+// `fill_row` gets the row index and the row to write.
+fn fill_rows_parallel(output: &mut [f64], row_len: usize, fill_row: impl Fn(usize, &mut [f64]) + Sync) {
+    if output.is_empty() {
+        return;
     }
+    let rows = output.len() / row_len;
+    let threads = thread::available_parallelism().map_or(1, |count| count.get()).min(rows);
+    let band_rows = rows.div_ceil(threads);
+
+    thread::scope(|scope| {
+        for (band, band_data) in output.chunks_mut(band_rows * row_len).enumerate() {
+            let fill_row = &fill_row;
+            scope.spawn(move || {
+                for (i, row) in band_data.chunks_exact_mut(row_len).enumerate() {
+                    fill_row(band * band_rows + i, row);
+                }
+            });
+        }
+    });
 }
 
-impl Image {
-    // proof of concept
-    pub fn mean_blur(&mut self) {
-        let mut output_data: Vec<u8> = Vec::with_capacity(self.data.len());
+// One pass of a `kernel_width` x `kernel_height` kernel over `channels`
+// interleaved samples per pixel
+fn convolve_pass(
+    source: &[f64],
+    (width, height, channels): (usize, usize, usize),
+    weights: &[f64],
+    (kernel_width, kernel_height): (usize, usize),
+    border: Border,
+) -> Vec<f64> {
+    let row_len = width * channels;
+    let (anchor_x, anchor_y) = ((kernel_width / 2) as isize, (kernel_height / 2) as isize);
+    let weight_total: f64 = weights.iter().sum();
+    let constant = match border {
+        Border::Constant(constant) => Some(constant),
+        _ => None,
+    };
 
-        let width = self.width as usize;
+    let mut output = vec![0.0; source.len()];
+    fill_rows_parallel(&mut output, row_len, |y, row| {
+        let inside_y = y as isize >= anchor_y && y + kernel_height - anchor_y as usize <= height;
+        for (x, output) in row.chunks_exact_mut(channels).enumerate() {
+            // Away from the edges every tap lands inside, no border handling needed
+            if inside_y && x as isize >= anchor_x && x + kernel_width - anchor_x as usize <= width {
+                let top_left = (y - anchor_y as usize) * row_len + (x - anchor_x as usize) * channels;
+                let mut sums = [0.0; 4];
+                for (kernel_y, kernel_row) in weights.chunks_exact(kernel_width).enumerate() {
+                    let mut index = top_left + kernel_y * row_len;
+                    for &weight in kernel_row {
+                        for (c, sum) in sums.iter_mut().enumerate().take(channels) {
+                            *sum += source[index + c] * weight;
+                        }
+                        index += channels;
+                    }
+                }
+                output.copy_from_slice(&sums[..channels]);
+                continue;
+            }
 
-        for i in 0..(self.data.len() / self.channels as usize) {
-            let mut reds: Vec<u8> = Vec::new();
-            let mut greens: Vec<u8> = Vec::new();
-            let mut blues: Vec<u8> = Vec::new();
+            let mut inside_total = 0.0;
+            for (kernel_y, kernel_row) in weights.chunks_exact(kernel_width).enumerate() {
+                let source_y = border.resolve(y as isize + kernel_y as isize - anchor_y, height);
+                for (kernel_x, &weight) in kernel_row.iter().enumerate() {
+                    let source_x = border.resolve(x as isize + kernel_x as isize - anchor_x, width);
+                    let samples = match (source_x, source_y, &constant) {
+                        (Some(source_x), Some(source_y), _) => &source[source_y * row_len + source_x * channels..],
+                        (_, _, Some(constant)) => &constant[..],
+                        _ => continue,
+                    };
 
-            if (i as isize - 1) / width as isize == (i / width) as isize && i as isize - 1 >= 0 {
-                let left = i as usize - 1;
-                reds.push(self.data[left * self.channels as usize]);
-                greens.push(self.data[left * self.channels as usize + 1]);
-                blues.push(self.data[left * self.channels as usize + 2]);
+                    inside_total += weight;
+                    for (value, &sample) in output.iter_mut().zip(samples) {
+                        *value += sample * weight;
+                    }
+                }
             }
 
-            if (i + 1) / width == i / width && i + 1 < width * self.height as usize {
-                let right = i + 1;
-                reds.push(self.data[right * self.channels as usize]);
-                greens.push(self.data[right * self.channels as usize + 1]);
-                blues.push(self.data[right * self.channels as usize + 2]);
+            // Kernels summing to zero, like edge detectors, have nothing to scale up to
+            if let Border::Skip = border
+                && inside_total.abs() > f64::EPSILON
+                && weight_total.abs() > f64::EPSILON
+            {
+                for value in output {
+                    *value *= weight_total / inside_total;
+                }
             }
+        }
+    });
+
+    output
+}
 
-            if i as isize - width as isize >= 0 {
-                let top = i - width;
-                reds.push(self.data[top * self.channels as usize]);
-                greens.push(self.data[top * self.channels as usize + 1]);
-                blues.push(self.data[top * self.channels as usize + 2]);
+// Averages the samples within `radius` of each pixel, clipped to the image.
+// A summed-area table gives each sum from four lookups whatever the radius.
+fn box_pass(source: &[f64], (width, height, channels): (usize, usize, usize), radius: usize) -> Vec<f64> {
+    let table_row_len = (width + 1) * channels;
+    let mut table = vec![0.0; table_row_len * (height + 1)];
+    for y in 0..height {
+        let mut row_sums = [0.0; 4];
+        for x in 0..width {
+            for (c, row_sum) in row_sums.iter_mut().enumerate().take(channels) {
+                *row_sum += source[(y * width + x) * channels + c];
+                let above = table[y * table_row_len + (x + 1) * channels + c];
+                table[(y + 1) * table_row_len + (x + 1) * channels + c] = above + *row_sum;
             }
+        }
+    }
 
-            if i + width < width * self.height as usize {
-                let bottom = i + width;
-                reds.push(self.data[bottom * self.channels as usize]);
-                greens.push(self.data[bottom * self.channels as usize + 1]);
-                blues.push(self.data[bottom * self.channels as usize + 2]);
+    let mut output = vec![0.0; source.len()];
+    fill_rows_parallel(&mut output, width * channels, |y, row| {
+        let (top, bottom) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        for (x, output) in row.chunks_exact_mut(channels).enumerate() {
+            let (left, right) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let count = ((right - left) * (bottom - top)) as f64;
+            for (c, value) in output.iter_mut().enumerate() {
+                let corner = |x: usize, y: usize| table[y * table_row_len + x * channels + c];
+                *value = (corner(right, bottom) - corner(right, top) - corner(left, bottom) + corner(left, top)) / count;
             }
+        }
+    });
 
-            let red: u8 = (reds.iter().map(|&x| x as usize).sum::<usize>() / reds.len() as usize)
-                .try_into()
-                .unwrap();
-            let green: u8 = (greens.iter().map(|&x| x as usize).sum::<usize>()
-                / greens.len() as usize)
-                .try_into()
-                .unwrap();
-            let blue: u8 = (blues.iter().map(|&x| x as usize).sum::<usize>()
-                / blues.len() as usize)
-                .try_into()
+    output
+}
+
+// Widths of `count` boxes whose repeated averaging approximates a gaussian
+// with the given sigma, from "Fast Almost-Gaussian Filtering" by Kovesi
+fn gaussian_box_widths(sigma: f64, count: usize) -> Vec<f64> {
+    let n = count as f64;
+    let ideal = (12.0 * sigma * sigma / n + 1.0).sqrt();
+    let mut lower = ideal.floor();
+    if lower % 2.0 == 0.0 {
+        lower -= 1.0;
+    }
+    let upper = lower + 2.0;
+
+    // How many of the boxes take the lower width
+    let lower_count = ((12.0 * sigma * sigma - n * lower * lower - 4.0 * n * lower - 3.0 * n) / (-4.0 * lower - 4.0)).round();
+    (0..count).map(|i| if (i as f64) < lower_count { lower } else { upper }).collect()
+}
+
+// Up to this many levels a histogram per column is cheap enough to keep
+const HISTOGRAM_LEVELS: usize = 256;
+
+// Wider histograms are searched through the totals of blocks of this many
+// levels, and of blocks of those blocks while there are more than this many
+const LEVEL_BLOCK: usize = 256;
+
+// Maps the samples of one channel to histogram levels and back. 8 and 16-bit
+// samples are their own level, floats are numbered by their place among the
+// distinct samples of the channel, so ranking levels ranks the samples exactly.
+enum Levels<T> {
+    Integer(usize),
+    Sorted(Vec<T>),
+}
+
+impl<T: Primitive> Levels<T> {
+    fn new(samples: &[T]) -> Levels<T> {
+        match size_of::<T>() {
+            1 => Levels::Integer(1 << 8),
+            2 => Levels::Integer(1 << 16),
+            _ => {
+                // `total_cmp` orders NaN too, after infinity
+                let mut values = samples.to_vec();
+                values.sort_unstable_by(|a, b| a.to_f64().total_cmp(&b.to_f64()));
+                values.dedup_by(|a, b| a.to_f64().total_cmp(&b.to_f64()).is_eq());
+                Levels::Sorted(values)
+            }
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            Levels::Integer(count) => *count,
+            Levels::Sorted(values) => values.len(),
+        }
+    }
+
+    fn level(&self, sample: T) -> u32 {
+        match self {
+            Levels::Integer(_) => sample.to_f64() as u32,
+            Levels::Sorted(values) => values.binary_search_by(|value| value.to_f64().total_cmp(&sample.to_f64())).unwrap() as u32,
+        }
+    }
+
+    fn sample(&self, level: u32) -> T {
+        match self {
+            Levels::Integer(_) => T::from_f64(level as f64),
+            Levels::Sorted(values) => values[level as usize],
+        }
+    }
+}
+
+// Position of the value at `percentile` among `count` sorted values
+fn rank_index(count: usize, percentile: f64) -> usize {
+    ((count - 1) as f64 * percentile / 100.0).round() as usize
+}
+
+// Replaces each sample of one channel with the value at `percentile` among
+// the samples within `radius` of it, clipped to the image
+fn rank_channel<T: Primitive>(samples: &[T], (width, height): (usize, usize), radius: usize, percentile: f64) -> Vec<T> {
+    let levels = Levels::new(samples);
+    let sample_levels: Vec<u32> = samples.iter().map(|&sample| levels.level(sample)).collect();
+
+    let ranked = if levels.count() <= HISTOGRAM_LEVELS {
+        rank_channel_histogram(&sample_levels, levels.count(), (width, height), radius, percentile)
+    } else {
+        rank_channel_sliding(&sample_levels, levels.count(), (width, height), radius, percentile)
+    };
+    ranked.into_iter().map(|level| levels.sample(level)).collect()
+}
+
+// "Median Filtering in Constant Time" by Perreault and Hébert: a histogram per
+// column covers the rows of the window and slides down one row at a time, the
+// window histogram is the sum of the column histograms and slides along the row.
+// Each step adds and removes whole histograms, so the radius doesn't matter.
+fn rank_channel_histogram(
+    sample_levels: &[u32],
+    level_count: usize,
+    (width, height): (usize, usize),
+    radius: usize,
+    percentile: f64,
+) -> Vec<u32> {
+    let mut columns = vec![0u32; width * level_count];
+    let update_row = |columns: &mut [u32], y: usize, add: bool| {
+        for (x, &level) in sample_levels[y * width..(y + 1) * width].iter().enumerate() {
+            let count = &mut columns[x * level_count + level as usize];
+            if add { *count += 1 } else { *count -= 1 }
+        }
+    };
+
+    let mut output = Vec::with_capacity(sample_levels.len());
+    let mut window = vec![0u32; level_count];
+    for y in 0..height {
+        if y == 0 {
+            for row in 0..(radius + 1).min(height) {
+                update_row(&mut columns, row, true);
+            }
+        } else {
+            if y > radius {
+                update_row(&mut columns, y - radius - 1, false);
+            }
+            if y + radius < height {
+                update_row(&mut columns, y + radius, true);
+            }
+        }
+        let rows = (y + radius + 1).min(height) - y.saturating_sub(radius);
+
+        window.fill(0);
+        let column = |x: usize| &columns[x * level_count..(x + 1) * level_count];
+        for x in 0..(radius + 1).min(width) {
+            window.iter_mut().zip(column(x)).for_each(|(count, added)| *count += added);
+        }
+
+        for x in 0..width {
+            if x > 0 {
+                if x + radius < width {
+                    window.iter_mut().zip(column(x + radius)).for_each(|(count, added)| *count += added);
+                }
+                if x > radius {
+                    window.iter_mut().zip(column(x - radius - 1)).for_each(|(count, removed)| *count -= removed);
+                }
+            }
+            let count = ((x + radius + 1).min(width) - x.saturating_sub(radius)) * rows;
+
+            let target = rank_index(count, percentile) as u32;
+            let mut seen = 0;
+            let level = window
+                .iter()
+                .position(|&level_count| {
+                    seen += level_count;
+                    seen > target
+                })
                 .unwrap();
+            output.push(level as u32);
+        }
+    }
+
+    output
+}
+
+// Huang's algorithm, for levels too many to keep a histogram per column of:
+// one histogram covers the window and slides along each row, adding and
+// removing a column of samples per step. The time grows with the radius
+// rather than its square. The histogram is tiered, each tier counting blocks
+// of `LEVEL_BLOCK` entries of the one below, so finding a rank takes a few
+// short scans however many levels there are.
+fn rank_channel_sliding(
+    sample_levels: &[u32],
+    level_count: usize,
+    (width, height): (usize, usize),
+    radius: usize,
+    percentile: f64,
+) -> Vec<u32> {
+    let mut tiers = vec![vec![0u32; level_count]];
+    while tiers[tiers.len() - 1].len() > LEVEL_BLOCK {
+        let blocks = tiers[tiers.len() - 1].len().div_ceil(LEVEL_BLOCK);
+        tiers.push(vec![0; blocks]);
+    }
+
+    let mut output = Vec::with_capacity(sample_levels.len());
+    for y in 0..height {
+        let rows = y.saturating_sub(radius)..(y + radius + 1).min(height);
+        let update_column = |tiers: &mut [Vec<u32>], x: usize, add: bool| {
+            for row in rows.clone() {
+                let mut index = sample_levels[row * width + x] as usize;
+                for tier in tiers.iter_mut() {
+                    if add { tier[index] += 1 } else { tier[index] -= 1 }
+                    index /= LEVEL_BLOCK;
+                }
+            }
+        };
+
+        for x in 0..(radius + 1).min(width) {
+            update_column(&mut tiers, x, true);
+        }
+
+        for x in 0..width {
+            if x > 0 {
+                if x + radius < width {
+                    update_column(&mut tiers, x + radius, true);
+                }
+                if x > radius {
+                    update_column(&mut tiers, x - radius - 1, false);
+                }
+            }
+            let count = ((x + radius + 1).min(width) - x.saturating_sub(radius)) * rows.len();
+
+            // From the top tier down, skip whole blocks and walk into the one holding the target
+            let target = rank_index(count, percentile) as u32;
+            let mut seen = 0;
+            let mut level = 0;
+            for tier in tiers.iter().rev() {
+                let start = level * LEVEL_BLOCK;
+                level = start
+                    + tier[start..(start + LEVEL_BLOCK).min(tier.len())]
+                        .iter()
+                        .position(|&level_count| {
+                            if seen + level_count > target {
+                                return true;
+                            }
+                            seen += level_count;
+                            false
+                        })
+                        .unwrap();
+            }
+            output.push(level as u32);
+        }
+
+        // Taking the last columns back out costs the samples they hold, clearing
+        // the tiers would cost every level
+        for x in width.saturating_sub(radius + 1)..width {
+            update_column(&mut tiers, x, false);
+        }
+    }
+
+    output
+}
+
+// Averages the pixels within three spatial sigmas, each weighted by its
+// distance and by how far its samples are from those of the center pixel
+fn bilateral_pass(samples: &[f64], (width, height, channels): (usize, usize, usize), radius: usize, spatial_sigma: f64, range_sigma: f64) -> Vec<f64> {
+    let diameter = 2 * radius + 1;
+    let spatial_weights: Vec<f64> = (0..diameter * diameter)
+        .map(|i| {
+            let (dx, dy) = ((i % diameter) as f64 - radius as f64, (i / diameter) as f64 - radius as f64);
+            E.powf(-(dx * dx + dy * dy) / (2.0 * spatial_sigma.powf(2.0)))
+        })
+        .collect();
+
+    let mut output = vec![0.0; samples.len()];
+    fill_rows_parallel(&mut output, width * channels, |y, row| {
+        for (x, output) in row.chunks_exact_mut(channels).enumerate() {
+            let center = &samples[(y * width + x) * channels..][..channels];
+            let mut sums = [0.0; 4];
+            let mut weight_sum = 0.0;
+
+            for source_y in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                for source_x in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                    let neighbour = &samples[(source_y * width + source_x) * channels..][..channels];
+                    let distance: f64 = neighbour.iter().zip(center).map(|(a, b)| (a - b).powi(2)).sum();
+                    let spatial = spatial_weights[(source_y + radius - y) * diameter + source_x + radius - x];
+                    let weight = spatial * E.powf(-distance / (2.0 * range_sigma.powf(2.0)));
+
+                    weight_sum += weight;
+                    for (sum, &sample) in sums.iter_mut().zip(neighbour) {
+                        *sum += sample * weight;
+                    }
+                }
+            }
+
+            for (value, sum) in output.iter_mut().zip(sums) {
+                *value = sum / weight_sum;
+            }
+        }
+    });
+
+    output
+}
+
+// Cells left empty around the bilateral grid, so its blur never runs off the edge
+const GRID_PADDING: usize = 2;
+
+// A cell holds a sum per channel and a count, so a grid with more cells than
+// the image has pixels outweighs its samples. Finer grids are coarsened to fit,
+// though small images may always use `MIN_GRID_CELLS`, about 40 MB for RGBA.
+const GRID_CELLS_PER_PIXEL: usize = 1;
+const MIN_GRID_CELLS: usize = 1 << 20;
+
+// "Real-time Edge-Aware Image Processing with the Bilateral Grid" by Chen, Paris
+// and Durand. Pixels are summed into a coarse grid over position and intensity,
+// one cell per sigma, the grid is blurred, and each pixel reads its result back
+// from where it fell. Only the intensity, Rec. 709 luma for color images, tells
+// pixels apart, so edges between colors of equal brightness get blurred.
+fn bilateral_grid_pass(
+    samples: &[f64],
+    (width, height, channels): (usize, usize, usize),
+    color_channels: usize,
+    mut spatial_sigma: f64,
+    mut range_sigma: f64,
+) -> Vec<f64> {
+    let intensities: Vec<f64> = samples
+        .chunks_exact(channels)
+        .map(|pixel| match color_channels {
+            3 => 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2],
+            _ => pixel[0],
+        })
+        .collect();
+    let lowest = intensities.iter().copied().fold(f64::INFINITY, f64::min);
+    let highest = intensities.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+    // Cells along an axis covering `span` with one cell per sigma, plus padding
+    let axis_size = |span: f64, sigma: f64| (span / sigma) as usize + 1 + 2 * GRID_PADDING;
+    let spatial_cells = |sigma: f64| axis_size((width - 1) as f64, sigma).saturating_mul(axis_size((height - 1) as f64, sigma));
+
+    // Sigmas too small for the budget are raised: the spatial one until at least
+    // two intensities fit, then the range one to take whatever is left
+    let budget = (width * height).saturating_mul(GRID_CELLS_PER_PIXEL).max(MIN_GRID_CELLS);
+    while spatial_cells(spatial_sigma).saturating_mul(2 + 2 * GRID_PADDING) > budget {
+        spatial_sigma *= 1.25;
+    }
+    let range_cells = budget / spatial_cells(spatial_sigma) - 1 - 2 * GRID_PADDING;
+    range_sigma = range_sigma.max((highest - lowest) / range_cells as f64);
+
+    // Grid coordinates of a pixel, before padding
+    let position = |x: usize, y: usize| {
+        [x as f64 / spatial_sigma, y as f64 / spatial_sigma, (intensities[y * width + x] - lowest) / range_sigma]
+    };
+    let size = [
+        axis_size((width - 1) as f64, spatial_sigma),
+        axis_size((height - 1) as f64, spatial_sigma),
+        axis_size(highest - lowest, range_sigma),
+    ];
+
+    // Each cell holds the summed samples followed by how many pixels went in
+    let cell_len = channels + 1;
+    let strides = [cell_len, cell_len * size[0], cell_len * size[0] * size[1]];
+    let mut grid = vec![0.0; strides[2] * size[2]];
+    for y in 0..height {
+        for x in 0..width {
+            let index: usize = position(x, y).iter().zip(strides).map(|(&coordinate, stride)| (coordinate.round() as usize + GRID_PADDING) * stride).sum();
+            let cell = &mut grid[index..index + cell_len];
+            for (value, &sample) in cell.iter_mut().zip(&samples[(y * width + x) * channels..][..channels]) {
+                *value += sample;
+            }
+            cell[channels] += 1.0;
+        }
+    }
+
+    // A [1 4 6 4 1] kernel along each axis, close to a gaussian of one cell,
+    // each pass reading one buffer and writing the other
+    let mut blurred = vec![0.0; grid.len()];
+    for (axis, &stride) in strides.iter().enumerate() {
+        for (index, value) in blurred.iter_mut().enumerate() {
+            let coordinate = index / stride % size[axis];
+            if coordinate < GRID_PADDING || coordinate + GRID_PADDING >= size[axis] {
+                *value = grid[index];
+                continue;
+            }
+            *value = [1.0, 4.0, 6.0, 4.0, 1.0]
+                .iter()
+                .enumerate()
+                .map(|(offset, weight)| weight * grid[index + offset * stride - GRID_PADDING * stride])
+                .sum::<f64>()
+                / 16.0;
+        }
+        std::mem::swap(&mut grid, &mut blurred);
+    }
+
+    // Reads each pixel back with trilinear interpolation between the 8 cells around it
+    let mut output = Vec::with_capacity(samples.len());
+    for y in 0..height {
+        for x in 0..width {
+            let position = position(x, y).map(|coordinate| coordinate + GRID_PADDING as f64);
+            let mut cell = [0.0; 5];
+            for corner in 0..8 {
+                let mut index = 0;
+                let mut weight = 1.0;
+                for (axis, (&coordinate, stride)) in position.iter().zip(strides).enumerate() {
+                    let fraction = coordinate.fract();
+                    if corner >> axis & 1 == 1 {
+                        index += (coordinate as usize + 1) * stride;
+                        weight *= fraction;
+                    } else {
+                        index += coordinate as usize * stride;
+                        weight *= 1.0 - fraction;
+                    }
+                }
+                for (value, &sum) in cell.iter_mut().zip(&grid[index..index + cell_len]) {
+                    *value += sum * weight;
+                }
+            }
+
+            // The pixel itself went into the grid, so some weight always reaches it
+            output.extend(cell[..channels].iter().map(|value| value / cell[channels]));
+        }
+    }
+
+    output
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn mean_blur(&mut self) {
+        self.as_view_mut().mean_blur();
+    }
+
+    pub fn box_blur(&mut self, radius: u32) {
+        self.as_view_mut().box_blur(radius);
+    }
 
-            output_data.push(red);
-            output_data.push(green);
-            output_data.push(blue);
-            if self.channels == 4 {
-                output_data.push(self.data[i * self.channels as usize + 3]);
-            }
-        }
-
-        self.data = output_data;
-    }
-
-    pub fn gaussian_blur(&mut self, kernel_size: usize, sigma: f64) {
-        // let kernel_size = 33;
-        assert!(kernel_size % 2 == 1);
-
-        let kernel = Kernel::gaussian(kernel_size, sigma);
-        let kernel_amplitude = (kernel_size as isize - 1) / 2;
-
-        let mut output_data: Vec<u8> = Vec::with_capacity(self.data.len());
-
-        for y in 0..self.height as usize {
-            for x in 0..self.width as usize {
-                let mut rgb_sums = (0.0, 0.0, 0.0);
-                let pixel_index = self.pos_to_index(x, y);
-
-                // For each cell in the kernel
-                for kernel_y in -kernel_amplitude..(kernel_amplitude + 1) {
-                    for kernel_x in -kernel_amplitude..(kernel_amplitude + 1) {
-                        // Ensure cell is within image bounds
-                        let image_cell_x = x as isize + kernel_x;
-                        let image_cell_y = y as isize + kernel_y;
-                        if 0 <= image_cell_x
-                            && image_cell_x < self.width as isize
-                            && 0 <= image_cell_y
-                            && image_cell_y < self.height as isize
-                        {
-                            let kernel_index = ((kernel_y + kernel_amplitude)
-                                * kernel_size as isize
-                                + (kernel_y + kernel_amplitude))
-                                as usize;
-                            let image_cell_index =
-                                self.pos_to_index(image_cell_x as usize, image_cell_y as usize);
-                            rgb_sums.0 += self.data[image_cell_index] as f64 * kernel[kernel_index];
-                            rgb_sums.1 +=
-                                self.data[image_cell_index + 1] as f64 * kernel[kernel_index];
-                            rgb_sums.2 +=
-                                self.data[image_cell_index + 2] as f64 * kernel[kernel_index];
+    pub fn convolve(&mut self, kernel: &Kernel, border: BorderMode<P>) {
+        self.as_view_mut().convolve(kernel, border);
+    }
+
+    // The kernel size follows from sigma
+    pub fn gaussian_blur(&mut self, sigma: f64) {
+        self.as_view_mut().gaussian_blur(sigma);
+    }
+
+    pub fn try_gaussian_blur(&mut self, sigma: f64) -> Result<(), ImageError> {
+        self.as_view_mut().try_gaussian_blur(sigma)
+    }
+
+    pub fn gaussian_blur_with_size(&mut self, kernel_size: usize, sigma: f64) {
+        self.as_view_mut().gaussian_blur_with_size(kernel_size, sigma);
+    }
+
+    pub fn try_gaussian_blur_with_size(&mut self, kernel_size: usize, sigma: f64) -> Result<(), ImageError> {
+        self.as_view_mut().try_gaussian_blur_with_size(kernel_size, sigma)
+    }
+
+    pub fn fast_gaussian_blur(&mut self, sigma: f64) {
+        self.as_view_mut().fast_gaussian_blur(sigma);
+    }
+
+    pub fn try_fast_gaussian_blur(&mut self, sigma: f64) -> Result<(), ImageError> {
+        self.as_view_mut().try_fast_gaussian_blur(sigma)
+    }
+
+    pub fn median_filter(&mut self, radius: u32) {
+        self.as_view_mut().median_filter(radius);
+    }
+
+    pub fn min_filter(&mut self, radius: u32) {
+        self.as_view_mut().min_filter(radius);
+    }
+
+    pub fn max_filter(&mut self, radius: u32) {
+        self.as_view_mut().max_filter(radius);
+    }
+
+    pub fn percentile_filter(&mut self, radius: u32, percentile: f64) {
+        self.as_view_mut().percentile_filter(radius, percentile);
+    }
+
+    pub fn try_percentile_filter(&mut self, radius: u32, percentile: f64) -> Result<(), ImageError> {
+        self.as_view_mut().try_percentile_filter(radius, percentile)
+    }
+
+    pub fn bilateral_filter(&mut self, spatial_sigma: f64, range_sigma: f64) {
+        self.as_view_mut().bilateral_filter(spatial_sigma, range_sigma);
+    }
+
+    pub fn try_bilateral_filter(&mut self, spatial_sigma: f64, range_sigma: f64) -> Result<(), ImageError> {
+        self.as_view_mut().try_bilateral_filter(spatial_sigma, range_sigma)
+    }
+
+    pub fn fast_bilateral_filter(&mut self, spatial_sigma: f64, range_sigma: f64) {
+        self.as_view_mut().fast_bilateral_filter(spatial_sigma, range_sigma);
+    }
+
+    pub fn try_fast_bilateral_filter(&mut self, spatial_sigma: f64, range_sigma: f64) -> Result<(), ImageError> {
+        self.as_view_mut().try_fast_bilateral_filter(spatial_sigma, range_sigma)
+    }
+}
+
+// The filters treat the edges of a view as image edges, pixels around it are left alone
+impl<P: Pixel> ImageViewMut<'_, P> {
+    // Averages the four pixels that share an edge with each pixel, leaving the
+    // pixel itself out. Pixels on the edges average the neighbours they have.
+    pub fn mean_blur(&mut self) {
+        // A lone pixel has no neighbours to average
+        if self.width().max(self.height()) <= 1 {
+            return;
+        }
+        let cross = Kernel::new(3, 3, vec![0.0, 0.25, 0.0, 0.25, 0.0, 0.25, 0.0, 0.25, 0.0]);
+        self.convolve(&cross, BorderMode::Skip);
+    }
+
+    // Averages the (2 * radius + 1) squared pixels around each pixel, in the same time for any radius.
+    // Every radius is valid, so unlike the gaussian blurs it has no `try_` variant.
+    pub fn box_blur(&mut self, radius: u32) {
+        if radius == 0 {
+            return;
+        }
+        let dimensions = (self.width() as usize, self.height() as usize, P::CHANNEL_COUNT as usize);
+        let samples = box_pass(&self.premultiplied_samples(), dimensions, radius as usize);
+        self.write_premultiplied_samples(&samples);
+    }
+
+    // Normalized samples with the colors premultiplied by alpha, so transparent
+    // pixels don't bleed their color into their neighbours
+    fn premultiplied_samples(&self) -> Vec<f64> {
+        let mut samples: Vec<f64> = Vec::with_capacity(self.width() as usize * self.height() as usize * P::CHANNEL_COUNT as usize);
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                samples.extend(premultiply(self.get_pixel(x, y)));
+            }
+        }
+        samples
+    }
+
+    fn write_premultiplied_samples(&mut self, samples: &[f64]) {
+        let mut output_data: Vec<P::Subpixel> = Vec::with_capacity(samples.len());
+        for pixel in samples.chunks_exact(P::CHANNEL_COUNT as usize) {
+            let alpha = if P::HAS_ALPHA { pixel[P::COLOR_CHANNELS].clamp(0.0, 1.0) } else { 1.0 };
+            for &value in &pixel[..P::COLOR_CHANNELS] {
+                // Fully transparent pixels have no color left to recover
+                let color = if alpha > 0.0 { value / alpha } else { 0.0 };
+                output_data.push(P::Subpixel::from_normalized(color));
+            }
+            if P::HAS_ALPHA {
+                output_data.push(P::Subpixel::from_normalized(alpha));
+            }
+        }
+
+        self.write_pixels(&output_data);
+    }
+
+    // Alpha is convolved along with the premultiplied colors
+    pub fn convolve(&mut self, kernel: &Kernel, border: BorderMode<P>) {
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        if width == 0 || height == 0 {
+            return;
+        }
+        let dimensions = (width, height, P::CHANNEL_COUNT as usize);
+        let border = Border::new(border);
+
+        let samples = self.premultiplied_samples();
+        let samples = match &kernel.factors {
+            Some((horizontal, vertical)) => {
+                let samples = convolve_pass(&samples, dimensions, horizontal, (horizontal.len(), 1), border);
+
+                // Rows past the edge went through the horizontal pass too
+                let border = match border {
+                    Border::Constant(constant) => Border::Constant(constant.map(|value| value * horizontal.iter().sum::<f64>())),
+                    border => border,
+                };
+                convolve_pass(&samples, dimensions, vertical, (1, vertical.len()), border)
+            }
+            None => convolve_pass(&samples, dimensions, &kernel.weights, (kernel.width, kernel.height), border),
+        };
+
+        self.write_premultiplied_samples(&samples);
+    }
+
+    pub fn gaussian_blur(&mut self, sigma: f64) {
+        self.try_gaussian_blur(sigma).unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    pub fn try_gaussian_blur(&mut self, sigma: f64) -> Result<(), ImageError> {
+        check_sigma(sigma)?;
+
+        // Weights past the far edge of the view would never be used, so they're left out
+        let longest_side = self.width().max(self.height()) as f64;
+        let amplitude = (3.0 * sigma).ceil().min(longest_side) as usize;
+        self.convolve(&Kernel::try_gaussian_with_size(2 * amplitude + 1, sigma)?, BorderMode::Skip);
+        Ok(())
+    }
+
+    pub fn gaussian_blur_with_size(&mut self, kernel_size: usize, sigma: f64) {
+        self.try_gaussian_blur_with_size(kernel_size, sigma).unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    pub fn try_gaussian_blur_with_size(&mut self, kernel_size: usize, sigma: f64) -> Result<(), ImageError> {
+        self.convolve(&Kernel::try_gaussian_with_size(kernel_size, sigma)?, BorderMode::Skip);
+        Ok(())
+    }
+
+    pub fn fast_gaussian_blur(&mut self, sigma: f64) {
+        self.try_fast_gaussian_blur(sigma).unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    // Three box blurs in a row, close to a gaussian and as fast for any sigma.
+    // Suited to large sigmas, where the gaussian kernel gets expensive.
+    pub fn try_fast_gaussian_blur(&mut self, sigma: f64) -> Result<(), ImageError> {
+        check_sigma(sigma)?;
+        let dimensions = (self.width() as usize, self.height() as usize, P::CHANNEL_COUNT as usize);
+
+        // Boxes twice as wide as the view already average all of it
+        let longest_side = self.width().max(self.height()) as f64;
+        let mut samples = self.premultiplied_samples();
+        for width in gaussian_box_widths(sigma, 3) {
+            let radius = ((width - 1.0) / 2.0).min(longest_side) as usize;
+            samples = box_pass(&samples, dimensions, radius);
+        }
+
+        self.write_premultiplied_samples(&samples);
+        Ok(())
+    }
+
+    // Removes salt-and-pepper noise without smearing it around like the blurs do.
+    // Only 8-bit images, and float channels with at most 256 distinct values,
+    // take the same time for any radius. 16-bit and other float images take
+    // a time growing with the radius, see `try_percentile_filter`.
+    // Median, min and max can't fail, only `try_percentile_filter` checks its percentile.
+    pub fn median_filter(&mut self, radius: u32) {
+        self.percentile_filter(radius, 50.0);
+    }
+
+    pub fn min_filter(&mut self, radius: u32) {
+        self.percentile_filter(radius, 0.0);
+    }
+
+    pub fn max_filter(&mut self, radius: u32) {
+        self.percentile_filter(radius, 100.0);
+    }
+
+    pub fn percentile_filter(&mut self, radius: u32, percentile: f64) {
+        self.try_percentile_filter(radius, percentile).unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    // Each channel, alpha included, takes the value at `percentile` among the
+    // (2 * radius + 1) squared pixels around it, always one of their samples.
+    // 8-bit images, and float channels with at most 256 distinct values, take
+    // the same time for any radius. 16-bit and other float images take a time
+    // growing with the radius, not its square, and floats need a sort of each
+    // channel first. NaN ranks above infinity.
+    pub fn try_percentile_filter(&mut self, radius: u32, percentile: f64) -> Result<(), ImageError> {
+        if !(0.0..=100.0).contains(&percentile) {
+            return Err(ImageError::InvalidParameter(format!("percentile must be between 0 and 100, got {percentile}")));
+        }
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        if radius == 0 || width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        let mut channels: Vec<Vec<P::Subpixel>> = vec![Vec::with_capacity(width * height); P::CHANNEL_COUNT as usize];
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                for (channel, &sample) in channels.iter_mut().zip(self.get_pixel(x, y).channels()) {
+                    channel.push(sample);
+                }
+            }
+        }
+
+        let channels: Vec<Vec<P::Subpixel>> = channels
+            .iter()
+            .map(|channel| rank_channel(channel, (width, height), radius as usize, percentile))
+            .collect();
+
+        let output_data: Vec<P::Subpixel> = (0..width * height).flat_map(|i| channels.iter().map(move |channel| channel[i])).collect();
+        self.write_pixels(&output_data);
+        Ok(())
+    }
+
+    pub fn bilateral_filter(&mut self, spatial_sigma: f64, range_sigma: f64) {
+        self.try_bilateral_filter(spatial_sigma, range_sigma).unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    // Smooths like a gaussian blur of `spatial_sigma` pixels, but leaves pixels
+    // that differ by much more than `range_sigma` out of each other's average,
+    // so edges stay sharp. Samples are normalized, `range_sigma` of 0.1 is a
+    // tenth of full intensity.
+    pub fn try_bilateral_filter(&mut self, spatial_sigma: f64, range_sigma: f64) -> Result<(), ImageError> {
+        check_sigma(spatial_sigma)?;
+        check_sigma(range_sigma)?;
+        let dimensions = (self.width() as usize, self.height() as usize, P::CHANNEL_COUNT as usize);
+
+        let longest_side = self.width().max(self.height()) as f64;
+        let radius = (3.0 * spatial_sigma).ceil().min(longest_side) as usize;
+        let samples = bilateral_pass(&self.premultiplied_samples(), dimensions, radius, spatial_sigma, range_sigma);
+        self.write_premultiplied_samples(&samples);
+        Ok(())
+    }
+
+    pub fn fast_bilateral_filter(&mut self, spatial_sigma: f64, range_sigma: f64) {
+        self.try_fast_bilateral_filter(spatial_sigma, range_sigma).unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    // Approximates `bilateral_filter` with a bilateral grid, in about the same
+    // time for any spatial sigma. Pixels are told apart by brightness alone.
+    // The grid gets about a cell per pixel at most, sigmas too small for that
+    // are raised until it fits, smoothing more than asked for.
+    pub fn try_fast_bilateral_filter(&mut self, spatial_sigma: f64, range_sigma: f64) -> Result<(), ImageError> {
+        check_sigma(spatial_sigma)?;
+        check_sigma(range_sigma)?;
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        let dimensions = (width, height, P::CHANNEL_COUNT as usize);
+        let samples = bilateral_grid_pass(&self.premultiplied_samples(), dimensions, P::COLOR_CHANNELS, spatial_sigma, range_sigma);
+        self.write_premultiplied_samples(&samples);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BorderMode, Image, ImageError, Kernel, Luma, Luma16, Luma32F, Luma8, Primitive, Rgba8};
+
+    fn gradient(width: u32, height: u32) -> Image<Luma8> {
+        let data = (0..width * height).map(|i| (i % width * 255 / width) as u8).collect();
+        Image::from_raw(width, height, data).unwrap()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-5, "{actual} vs {expected}");
+        }
+    }
+
+    #[test]
+    fn gaussian_blur_spreads_a_point_into_its_kernel() {
+        let mut data = vec![0.0; 81];
+        data[40] = 1.0;
+        let mut image: Image<Luma32F> = Image::from_raw(9, 9, data).unwrap();
+        image.gaussian_blur_with_size(5, 1.0);
+
+        let kernel = Kernel::gaussian_with_size(5, 1.0);
+        let output = image.into_raw();
+        for y in 0..5 {
+            let row: Vec<f32> = kernel.weights()[y * 5..][..5].iter().map(|&weight| weight as f32).collect();
+            assert_close(&output[(y + 2) * 9 + 2..][..5], &row);
+        }
+        assert_eq!(output[..2], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn separable_gaussian_matches_the_full_kernel() {
+        let data: Vec<f32> = noise(15 * 11).map(|value| value as f32 / u32::MAX as f32).collect();
+        let mut separable: Image<Luma32F> = Image::from_raw(15, 11, data.clone()).unwrap();
+        separable.gaussian_blur(1.5);
+
+        let gaussian = Kernel::gaussian(1.5);
+        let full = Kernel::new(gaussian.width(), gaussian.height(), gaussian.weights().to_vec());
+        assert!(gaussian.is_separable() && !full.is_separable());
+        let mut image: Image<Luma32F> = Image::from_raw(15, 11, data).unwrap();
+        image.convolve(&full, BorderMode::Skip);
+        assert_close(&separable.into_raw(), &image.into_raw());
+    }
+
+    #[test]
+    fn gaussian_blur_keeps_flat_images_flat_up_to_the_edges() {
+        let mut image: Image<Luma8> = Image::from_raw(7, 5, vec![100; 35]).unwrap();
+        image.gaussian_blur(2.0);
+        assert!(image.as_bytes().iter().all(|&value| value == 100));
+
+        // Sigmas far past the image size average the whole of it
+        let mut image: Image<Luma8> = Image::from_raw(3, 1, vec![0, 30, 60]).unwrap();
+        image.gaussian_blur(1000.0);
+        assert_eq!(image.into_raw(), [30, 30, 30]);
+    }
+
+    #[test]
+    fn transparent_pixels_lend_no_color_to_the_blur() {
+        let data = vec![255, 0, 0, 255, 0, 255, 0, 0, 255, 0, 0, 255];
+        let mut image: Image<Rgba8> = Image::from_raw(3, 1, data).unwrap();
+        image.gaussian_blur(1.0);
+        for pixel in image.into_raw().chunks_exact(4) {
+            assert_eq!(pixel[..3], [255, 0, 0]);
+            assert!(pixel[3] > 0);
+        }
+
+        // Alpha itself is blurred, a fully transparent image stays so
+        let mut image: Image<Rgba8> = Image::from_raw(2, 1, vec![9, 9, 9, 0, 9, 9, 9, 0]).unwrap();
+        image.gaussian_blur(1.0);
+        assert_eq!(image.into_raw(), [0; 8]);
+    }
+
+    #[test]
+    fn gaussian_blurs_check_their_parameters() {
+        let mut image: Image<Luma8> = Image::new(4, 4);
+        for sigma in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(image.try_gaussian_blur(sigma), Err(ImageError::InvalidParameter(_))));
+            assert!(matches!(image.try_fast_gaussian_blur(sigma), Err(ImageError::InvalidParameter(_))));
+        }
+        assert!(matches!(image.try_gaussian_blur_with_size(4, 1.0), Err(ImageError::InvalidParameter(_))));
+        assert!(image.try_gaussian_blur_with_size(3, 1.0).is_ok());
+    }
+
+    fn convolve_row(samples: &[f32], kernel: &Kernel, border: BorderMode<Luma<f32>>) -> Vec<f32> {
+        let mut image: Image<Luma32F> = Image::from_raw(samples.len() as u32, 1, samples.to_vec()).unwrap();
+        image.convolve(kernel, border);
+        image.into_raw()
+    }
+
+    #[test]
+    fn each_border_mode_fills_in_its_own_samples() {
+        // Applied as written, each pixel takes its left neighbour plus twice its right one
+        let kernel = Kernel::new(3, 1, vec![1.0, 0.0, 2.0]);
+        let row = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(convolve_row(&row, &kernel, BorderMode::Clamp), [5.0, 7.0, 10.0, 11.0]);
+        assert_eq!(convolve_row(&row, &kernel, BorderMode::Mirror), [6.0, 7.0, 10.0, 9.0]);
+        assert_eq!(convolve_row(&row, &kernel, BorderMode::Wrap), [8.0, 7.0, 10.0, 5.0]);
+        assert_eq!(convolve_row(&row, &kernel, BorderMode::Constant(Luma([10.0]))), [14.0, 7.0, 10.0, 23.0]);
+        // Scaled by the total weight over the weight that landed inside
+        assert_eq!(convolve_row(&row, &kernel, BorderMode::Skip), [6.0, 7.0, 10.0, 9.0]);
+
+        // A single pixel mirrors onto itself
+        assert_eq!(convolve_row(&[2.0], &kernel, BorderMode::Mirror), [6.0]);
+
+        // Columns are handled like rows
+        let mut column: Image<Luma32F> = Image::from_raw(1, 4, row.to_vec()).unwrap();
+        column.convolve(&Kernel::new(1, 3, vec![1.0, 0.0, 2.0]), BorderMode::Wrap);
+        assert_eq!(column.into_raw(), [8.0, 7.0, 10.0, 5.0]);
+    }
+
+    #[test]
+    fn non_square_kernels_center_on_the_lower_right_middle() {
+        let (width, height) = (5, 4);
+        let data: Vec<f32> = (0..width * height).map(|i| i as f32).collect();
+        let weights: Vec<f64> = (1..=6).map(|weight| weight as f64).collect();
+        let mut image: Image<Luma32F> = Image::from_raw(width as u32, height as u32, data.clone()).unwrap();
+        image.convolve(&Kernel::new(2, 3, weights.clone()), BorderMode::Wrap);
+
+        let mut expected = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0.0;
+                for (i, weight) in weights.iter().enumerate() {
+                    let source_x = (x + width + i % 2 - 1) % width;
+                    let source_y = (y + height + i / 2 - 1) % height;
+                    sum += data[source_y * width + source_x] as f64 * weight;
+                }
+                expected.push(sum as f32);
+            }
+        }
+        assert_close(&image.into_raw(), &expected);
+    }
+
+    #[test]
+    fn separable_kernels_match_their_product_for_every_border() {
+        let data: Vec<f32> = noise(9 * 7).map(|value| value as f32 / u32::MAX as f32).collect();
+        let separable = Kernel::separable(vec![1.0, -2.0, 0.5], vec![0.25, 1.0]);
+        let full = Kernel::new(3, 2, separable.weights().to_vec());
+        assert_eq!(full.weights(), [0.25, -0.5, 0.125, 1.0, -2.0, 0.5]);
+
+        let borders = [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Constant(Luma([0.7])), BorderMode::Skip];
+        for border in borders {
+            let mut image: Image<Luma32F> = Image::from_raw(9, 7, data.clone()).unwrap();
+            image.convolve(&separable, border);
+            let mut expected: Image<Luma32F> = Image::from_raw(9, 7, data.clone()).unwrap();
+            expected.convolve(&full, border);
+            assert_close(&image.into_raw(), &expected.into_raw());
+        }
+    }
+
+    #[test]
+    fn built_in_kernels_keep_flat_areas() {
+        for kernel in [Kernel::sharpen(), Kernel::emboss(), Kernel::gaussian(0.8)] {
+            let mut image: Image<Luma8> = Image::from_raw(5, 5, vec![90; 25]).unwrap();
+            image.convolve(&kernel, BorderMode::Clamp);
+            assert!(image.as_bytes().iter().all(|&value| value == 90));
+        }
+    }
+
+    #[test]
+    fn kernels_need_weights_for_every_cell() {
+        assert!(matches!(Kernel::try_new(0, 3, vec![]), Err(ImageError::InvalidParameter(_))));
+        assert!(matches!(Kernel::try_new(2, 2, vec![1.0; 3]), Err(ImageError::InvalidParameter(_))));
+        assert!(matches!(Kernel::try_separable(vec![], vec![1.0]), Err(ImageError::InvalidParameter(_))));
+        assert!(matches!(Kernel::try_gaussian_with_size(4, 1.0), Err(ImageError::InvalidParameter(_))));
+        assert!(matches!(Kernel::try_gaussian(0.0), Err(ImageError::InvalidParameter(_))));
+
+        let kernel = Kernel::try_new(3, 1, vec![1.0, 2.0, 3.0]).unwrap();
+        assert_eq!((kernel.width(), kernel.height(), kernel.is_separable()), (3, 1, false));
+    }
+
+    #[test]
+    fn mean_blur_averages_the_four_neighbours() {
+        let mut image: Image<Luma32F> = Image::from_raw(3, 3, (1..=9).map(|i| i as f32).collect()).unwrap();
+        image.mean_blur();
+        // Corners have two neighbours, edges three and the center four
+        let expected = [3.0, 3.0, 4.0, 13.0 / 3.0, 5.0, 17.0 / 3.0, 6.0, 7.0, 7.0];
+        assert_close(&image.into_raw(), &expected);
+
+        let mut single: Image<Luma8> = Image::from_raw(1, 1, vec![77]).unwrap();
+        single.mean_blur();
+        assert_eq!(single.into_raw(), [77]);
+    }
+
+    #[test]
+    fn box_blur_matches_averaging_each_window() {
+        let (width, height) = (13, 9);
+        let data: Vec<f32> = noise(width * height).map(|value| value as f32 / u32::MAX as f32).collect();
+        for radius in [1, 2, 4, 20] {
+            let mut image: Image<Luma32F> = Image::from_raw(width as u32, height as u32, data.clone()).unwrap();
+            image.box_blur(radius as u32);
+
+            let mut expected = Vec::new();
+            for y in 0..height {
+                for x in 0..width {
+                    let mut window = Vec::new();
+                    for row in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                        for column in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                            window.push(data[row * width + column] as f64);
                         }
                     }
+                    expected.push((window.iter().sum::<f64>() / window.len() as f64) as f32);
                 }
+            }
+            assert_close(&image.into_raw(), &expected);
+        }
+
+        let mut image: Image<Luma8> = Image::from_raw(2, 1, vec![10, 20]).unwrap();
+        image.box_blur(0);
+        assert_eq!(image.into_raw(), [10, 20]);
+    }
 
-                output_data.push(rgb_sums.0 as u8);
-                output_data.push(rgb_sums.1 as u8);
-                output_data.push(rgb_sums.2 as u8);
+    #[test]
+    fn fast_gaussian_blur_approximates_the_gaussian() {
+        let mut data = vec![0.0; 41 * 41];
+        data[20 * 41 + 20] = 1.0;
+        let mut fast: Image<Luma32F> = Image::from_raw(41, 41, data.clone()).unwrap();
+        fast.fast_gaussian_blur(4.0);
+        let mut exact: Image<Luma32F> = Image::from_raw(41, 41, data).unwrap();
+        exact.gaussian_blur(4.0);
+
+        // Away from the edges nothing is lost, and the shape stays close to the gaussian
+        let (fast, exact) = (fast.into_raw(), exact.into_raw());
+        assert!((fast.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+        let peak = exact[20 * 41 + 20];
+        for (fast, exact) in fast.iter().zip(&exact) {
+            assert!((fast - exact).abs() < peak * 0.1, "{fast} vs {exact}");
+        }
 
-                if self.channels == 4 {
-                    output_data.push(self.data[pixel_index + 3]);
+        let mut flat: Image<Luma8> = Image::from_raw(6, 4, vec![140; 24]).unwrap();
+        flat.fast_gaussian_blur(30.0);
+        assert!(flat.as_bytes().iter().all(|&value| value == 140));
+    }
+
+    #[test]
+    fn fine_bilateral_grids_are_coarsened_to_fit() {
+        // Left as asked, these sigmas would take billions of cells
+        let original = gradient(300, 200);
+        let mut image = original.clone();
+        image.fast_bilateral_filter(0.1, 1e-4);
+
+        // A coarser grid still smooths the ramp into about the same ramp
+        for y in 0..200 {
+            for x in 30..270 {
+                let (value, expected) = (image.get_pixel(x, y).0[0], original.get_pixel(x, y).0[0]);
+                assert!(value.abs_diff(expected) <= 3, "{value} vs {expected} at {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn bilateral_grid_keeps_flat_images() {
+        let mut image: Image<Luma8> = Image::from_raw(64, 64, vec![100; 64 * 64]).unwrap();
+        image.fast_bilateral_filter(4.0, 20.0);
+        assert!(image.as_bytes().iter().all(|&value| value == 100));
+    }
+
+    // Sorts every window, clipped to the image like the filters
+    fn naive_rank<T: Primitive>(samples: &[T], (width, height): (usize, usize), radius: usize, percentile: f64) -> Vec<T> {
+        let mut output = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let mut window = Vec::new();
+                for row in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                    for column in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                        window.push(samples[row * width + column]);
+                    }
                 }
+                window.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                output.push(window[super::rank_index(window.len(), percentile)]);
+            }
+        }
+        output
+    }
+
+    fn noise(count: usize) -> impl Iterator<Item = u32> {
+        let mut state = 0x2545_f491u32;
+        (0..count).map(move |_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        })
+    }
+
+    #[test]
+    fn rank_filters_match_sorting_each_window() {
+        let (width, height) = (23, 17);
+        for (radius, percentile) in [(1, 50.0), (2, 0.0), (3, 100.0), (5, 25.0), (30, 50.0)] {
+            let data: Vec<u8> = noise(width * height).map(|value| value as u8).collect();
+            let mut image: Image<Luma8> = Image::from_raw(width as u32, height as u32, data.clone()).unwrap();
+            image.percentile_filter(radius as u32, percentile);
+            assert_eq!(image.into_raw(), naive_rank(&data, (width, height), radius, percentile));
+
+            let data: Vec<u16> = noise(width * height).map(|value| value as u16).collect();
+            let mut image: Image<Luma16> = Image::from_raw(width as u32, height as u32, data.clone()).unwrap();
+            image.percentile_filter(radius as u32, percentile);
+            assert_eq!(image.into_raw(), naive_rank(&data, (width, height), radius, percentile));
+        }
+    }
+
+    #[test]
+    fn float_rank_filters_return_exact_samples() {
+        let (width, height) = (23, 17);
+        let data: Vec<f32> = noise(width * height).map(|value| value as f32 / u32::MAX as f32 * 4.0 - 1.0).collect();
+        // A channel with few distinct values goes through the per-column histograms
+        let steps: Vec<f32> = noise(width * height).map(|value| (value % 7) as f32 * 0.1).collect();
+        for data in [data, steps] {
+            for (radius, percentile) in [(1, 50.0), (4, 0.0), (2, 100.0), (6, 75.0), (20, 50.0)] {
+                let mut image: Image<Luma32F> = Image::from_raw(width as u32, height as u32, data.clone()).unwrap();
+                image.percentile_filter(radius as u32, percentile);
+                assert_eq!(image.into_raw(), naive_rank(&data, (width, height), radius, percentile));
             }
         }
 
-        self.data = output_data;
+        // More distinct values than fit two tiers of blocks
+        let (width, height) = (300, 250);
+        let data: Vec<f32> = noise(width * height).map(|value| value as f32).collect();
+        let mut image: Image<Luma32F> = Image::from_raw(width as u32, height as u32, data.clone()).unwrap();
+        image.median_filter(2);
+        assert_eq!(image.into_raw(), naive_rank(&data, (width, height), 2, 50.0));
+
+        let mut extremes: Image<Luma32F> = Image::from_raw(3, 1, vec![f32::INFINITY, 2.5, f32::NEG_INFINITY]).unwrap();
+        extremes.max_filter(1);
+        assert_eq!(extremes.into_raw(), [f32::INFINITY, f32::INFINITY, 2.5]);
+
+        let mut flat: Image<Luma32F> = Image::from_raw(4, 4, vec![0.3; 16]).unwrap();
+        flat.median_filter(1);
+        assert!(flat.into_raw().iter().all(|&value| value == 0.3));
     }
 }
@@ -1,4 +1,4 @@
-use super::Image;
+use super::{Image, ImageViewMut, Pixel};
 
 
 struct Circle {
@@ -32,13 +32,12 @@ impl Circle {
 
     // Function name could use some work
     fn bounding_pos_to_pos(&self, pos: (usize, usize)) -> (isize, isize) {
-        let abs_pos = Image::sum_pos(pos, self.center_pos);
-        Image::subtract_pos(abs_pos, (self.radius, self.radius))
+        let abs_pos = (pos.0 + self.center_pos.0, pos.1 + self.center_pos.1);
+        (abs_pos.0 as isize - self.radius as isize, abs_pos.1 as isize - self.radius as isize)
     }
 
-    pub fn draw_outline(&self, image: &mut Image, color: (u8, u8, u8, u8)) {
+    pub fn draw_outline<P: Pixel>(&self, image: &mut ImageViewMut<P>, color: P) {
         let width = self.radius * 2 + 1;
-        let color = vec![color.0, color.1, color.2, color.3];
 
         // Find circle pos corresponding to each circle bounding box pixel
         for i in 0..width {
@@ -47,34 +46,33 @@ impl Circle {
             let left_pos = self.bounding_pos_to_pos((0, i));
             let right_pos = self.bounding_pos_to_pos((width - 1, i));
 
-            image.replace_pixel_if_viable(self.circle_pos(top_pos), &color);
-            image.replace_pixel_if_viable(self.circle_pos(bottom_pos), &color);
-            image.replace_pixel_if_viable(self.circle_pos(left_pos), &color);
-            image.replace_pixel_if_viable(self.circle_pos(right_pos), &color);
+            image.replace_pixel_if_viable(self.circle_pos(top_pos), color);
+            image.replace_pixel_if_viable(self.circle_pos(bottom_pos), color);
+            image.replace_pixel_if_viable(self.circle_pos(left_pos), color);
+            image.replace_pixel_if_viable(self.circle_pos(right_pos), color);
         }
     }
 }
 
-impl Image {
-    fn replace_pixel_at(&mut self, pos: (u32, u32), value: &Vec<u8>) {
-        let index = self.pos_to_index(pos.0 as usize, pos.1 as usize);
-
-        self.data[index] = value[0];
-        self.data[index + 1] = value[1];
-        self.data[index + 2] = value[2];
+impl<P: Pixel> Image<P> {
+    pub fn replace_pixel_if_viable(&mut self, pos: (isize, isize), color: P) {
+        self.as_view_mut().replace_pixel_if_viable(pos, color);
+    }
 
-        if self.channels == 4 {
-            self.data[index + 3] = value[3];
-        }
+    pub fn draw_circle_outline(&mut self, center_pos: (usize, usize), radius: usize, color: P) {
+        self.as_view_mut().draw_circle_outline(center_pos, radius, color);
     }
+}
 
-    pub fn replace_pixel_if_viable(&mut self, pos: (isize, isize), color: &Vec<u8>) {
+// Positions are relative to the view, anything outside it is clipped
+impl<P: Pixel> ImageViewMut<'_, P> {
+    pub fn replace_pixel_if_viable(&mut self, pos: (isize, isize), color: P) {
         if self.is_pos_in_image(pos) {
-            self.replace_pixel_at((pos.0 as u32, pos.1 as u32), color);
+            self.put_pixel(pos.0 as u32, pos.1 as u32, color);
         }
     }
 
-    pub fn draw_circle_outline(&mut self, center_pos: (usize, usize), radius: usize, color: (u8, u8, u8, u8)) {
+    pub fn draw_circle_outline(&mut self, center_pos: (usize, usize), radius: usize, color: P) {
         Circle::build(center_pos, radius).draw_outline(self, color);
     }
 }
@@ -0,0 +1,294 @@
+use std::iter::FusedIterator;
+use std::slice::{ChunksExact, ChunksExactMut};
+
+use super::{Image, Pixel};
+
+// Borrows each pixel in row-major order
+pub struct PixelIterator<'a, P: Pixel> {
+    chunks: ChunksExact<'a, P::Subpixel>,
+}
+
+impl<'a, P: Pixel> PixelIterator<'a, P> {
+    pub fn new(image: &'a Image<P>) -> Self {
+        PixelIterator::from_samples(&image.data)
+    }
+
+    fn from_samples(samples: &'a [P::Subpixel]) -> Self {
+        PixelIterator { chunks: samples.chunks_exact(P::CHANNEL_COUNT as usize) }
+    }
+}
+
+impl<'a, P: Pixel> Iterator for PixelIterator<'a, P> {
+    type Item = &'a P;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.next().map(P::from_slice_ref)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.chunks.nth(n).map(P::from_slice_ref)
+    }
+}
+
+impl<P: Pixel> DoubleEndedIterator for PixelIterator<'_, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.chunks.next_back().map(P::from_slice_ref)
+    }
+}
+
+impl<P: Pixel> ExactSizeIterator for PixelIterator<'_, P> {}
+impl<P: Pixel> FusedIterator for PixelIterator<'_, P> {}
+
+pub struct PixelIteratorMut<'a, P: Pixel> {
+    chunks: ChunksExactMut<'a, P::Subpixel>,
+}
+
+impl<'a, P: Pixel> PixelIteratorMut<'a, P> {
+    fn from_samples(samples: &'a mut [P::Subpixel]) -> Self {
+        PixelIteratorMut { chunks: samples.chunks_exact_mut(P::CHANNEL_COUNT as usize) }
+    }
+}
+
+impl<'a, P: Pixel> Iterator for PixelIteratorMut<'a, P> {
+    type Item = &'a mut P;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.next().map(P::from_slice_mut)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.chunks.nth(n).map(P::from_slice_mut)
+    }
+}
+
+impl<P: Pixel> DoubleEndedIterator for PixelIteratorMut<'_, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.chunks.next_back().map(P::from_slice_mut)
+    }
+}
+
+impl<P: Pixel> ExactSizeIterator for PixelIteratorMut<'_, P> {}
+impl<P: Pixel> FusedIterator for PixelIteratorMut<'_, P> {}
+
+// Pixels together with their (x, y) position
+pub struct EnumeratePixels<'a, P: Pixel> {
+    pixels: PixelIterator<'a, P>,
+    width: u32,
+    x: u32,
+    y: u32,
+}
+
+impl<'a, P: Pixel> Iterator for EnumeratePixels<'a, P> {
+    type Item = (u32, u32, &'a P);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pixel = self.pixels.next()?;
+        let item = (self.x, self.y, pixel);
+
+        self.x += 1;
+        if self.x == self.width {
+            self.x = 0;
+            self.y += 1;
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pixels.size_hint()
+    }
+}
+
+impl<P: Pixel> ExactSizeIterator for EnumeratePixels<'_, P> {}
+impl<P: Pixel> FusedIterator for EnumeratePixels<'_, P> {}
+
+pub struct EnumeratePixelsMut<'a, P: Pixel> {
+    pixels: PixelIteratorMut<'a, P>,
+    width: u32,
+    x: u32,
+    y: u32,
+}
+
+impl<'a, P: Pixel> Iterator for EnumeratePixelsMut<'a, P> {
+    type Item = (u32, u32, &'a mut P);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pixel = self.pixels.next()?;
+        let item = (self.x, self.y, pixel);
+
+        self.x += 1;
+        if self.x == self.width {
+            self.x = 0;
+            self.y += 1;
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pixels.size_hint()
+    }
+}
+
+impl<P: Pixel> ExactSizeIterator for EnumeratePixelsMut<'_, P> {}
+impl<P: Pixel> FusedIterator for EnumeratePixelsMut<'_, P> {}
+
+// Each row as its own pixel iterator, top to bottom
+pub struct Rows<'a, P: Pixel> {
+    chunks: ChunksExact<'a, P::Subpixel>,
+}
+
+impl<'a, P: Pixel> Iterator for Rows<'a, P> {
+    type Item = PixelIterator<'a, P>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.next().map(PixelIterator::from_samples)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<P: Pixel> DoubleEndedIterator for Rows<'_, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.chunks.next_back().map(PixelIterator::from_samples)
+    }
+}
+
+impl<P: Pixel> ExactSizeIterator for Rows<'_, P> {}
+impl<P: Pixel> FusedIterator for Rows<'_, P> {}
+
+pub struct RowsMut<'a, P: Pixel> {
+    chunks: ChunksExactMut<'a, P::Subpixel>,
+}
+
+impl<'a, P: Pixel> Iterator for RowsMut<'a, P> {
+    type Item = PixelIteratorMut<'a, P>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.next().map(PixelIteratorMut::from_samples)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+impl<P: Pixel> DoubleEndedIterator for RowsMut<'_, P> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.chunks.next_back().map(PixelIteratorMut::from_samples)
+    }
+}
+
+impl<P: Pixel> ExactSizeIterator for RowsMut<'_, P> {}
+impl<P: Pixel> FusedIterator for RowsMut<'_, P> {}
+
+impl<P: Pixel> Image<P> {
+    pub fn pixels(&self) -> PixelIterator<'_, P> {
+        PixelIterator::new(self)
+    }
+
+    pub fn pixels_mut(&mut self) -> PixelIteratorMut<'_, P> {
+        PixelIteratorMut::from_samples(&mut self.data)
+    }
+
+    pub fn enumerate_pixels(&self) -> EnumeratePixels<'_, P> {
+        EnumeratePixels { pixels: self.pixels(), width: self.width, x: 0, y: 0 }
+    }
+
+    pub fn enumerate_pixels_mut(&mut self) -> EnumeratePixelsMut<'_, P> {
+        let width = self.width;
+        EnumeratePixelsMut { pixels: self.pixels_mut(), width, x: 0, y: 0 }
+    }
+
+    // A zero-width image has no samples, so it yields no rows either
+    pub fn rows(&self) -> Rows<'_, P> {
+        Rows { chunks: self.data.chunks_exact(self.row_len().max(1)) }
+    }
+
+    pub fn rows_mut(&mut self) -> RowsMut<'_, P> {
+        let row_len = self.row_len().max(1);
+        RowsMut { chunks: self.data.chunks_exact_mut(row_len) }
+    }
+
+    fn row_len(&self) -> usize {
+        self.width as usize * P::CHANNEL_COUNT as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Image, Rgb, Rgb8};
+
+    fn ramp() -> Image<Rgb8> {
+        Image::from_raw(3, 2, (0..18).collect()).unwrap()
+    }
+
+    #[test]
+    fn pixels_cover_every_pixel_in_both_directions() {
+        let image = ramp();
+        let pixels: Vec<Rgb8> = image.pixels().copied().collect();
+        assert_eq!(pixels.len(), 6);
+        assert_eq!((pixels[0], pixels[5]), (Rgb([0, 1, 2]), Rgb([15, 16, 17])));
+
+        let mut iter = image.pixels();
+        assert_eq!(iter.len(), 6);
+        assert_eq!(iter.nth(2), Some(&Rgb([6, 7, 8])));
+        assert_eq!(iter.next_back(), Some(&Rgb([15, 16, 17])));
+        assert_eq!(iter.len(), 2);
+        assert_eq!(image.pixels().rev().nth(1), Some(&Rgb([12, 13, 14])));
+    }
+
+    #[test]
+    fn pixels_borrow_the_image_samples() {
+        let image = ramp();
+        let first = image.pixels().next().unwrap();
+        assert_eq!(first as *const Rgb8 as *const u8, image.as_bytes().as_ptr());
+    }
+
+    #[test]
+    fn enumerate_pixels_wraps_at_the_width() {
+        let mut image = ramp();
+        let positions: Vec<(u32, u32, u8)> = image.enumerate_pixels().map(|(x, y, pixel)| (x, y, pixel.0[0])).collect();
+        assert_eq!(positions, [(0, 0, 0), (1, 0, 3), (2, 0, 6), (0, 1, 9), (1, 1, 12), (2, 1, 15)]);
+
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            pixel.0 = [x as u8, y as u8, 0];
+        }
+        assert_eq!(image.get_pixel(2, 1), &Rgb([2, 1, 0]));
+    }
+
+    #[test]
+    fn rows_yield_one_iterator_per_line() {
+        let mut image = ramp();
+        let firsts: Vec<u8> = image.rows().map(|mut row| row.next().unwrap().0[0]).collect();
+        assert_eq!(firsts, [0, 9]);
+        assert_eq!(image.rows().next_back().unwrap().len(), 3);
+
+        for (y, row) in image.rows_mut().enumerate() {
+            for pixel in row {
+                pixel.0[1] = y as u8;
+            }
+        }
+        for pixel in image.pixels_mut().rev().take(1) {
+            pixel.0[2] = 99;
+        }
+        assert_eq!(image.as_bytes()[..6], [0, 0, 2, 3, 0, 5]);
+        assert_eq!(image.as_bytes()[15..], [15, 1, 99]);
+    }
+
+    #[test]
+    fn empty_images_yield_nothing() {
+        let wide: Image<Rgb8> = Image::new(4, 0);
+        assert_eq!((wide.pixels().len(), wide.rows().len()), (0, 0));
+        let narrow: Image<Rgb8> = Image::new(0, 4);
+        assert_eq!((narrow.pixels().len(), narrow.rows().len(), narrow.enumerate_pixels().len()), (0, 0, 0));
+    }
+}
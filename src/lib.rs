@@ -1,91 +1,100 @@
 mod manipulation;
 mod geometry;
 mod filters;
+mod codecs;
+mod dynamic;
+mod error;
+mod pixel;
+mod iter;
+mod view;
 
-use core::panic;
-use std::{fs, ops::Add};
+pub use codecs::{
+    register_codec, ChannelLayout, ChromaSubsampling, Codec, ColorSpace, Frame, GifOptions, ImagelyHeader,
+    ImagelyOptions, JpegOptions, NetpbmFormat, NetpbmOptions, PngFilter, PngOptions, TiffCompression, TiffOptions,
+};
+pub use dynamic::{DynamicImage, DynamicImageRef, SampleDepth};
+pub use error::ImageError;
+pub use filters::{BorderMode, Kernel};
+pub use iter::{EnumeratePixels, EnumeratePixelsMut, PixelIterator, PixelIteratorMut, Rows, RowsMut};
+pub use manipulation::GrayscaleMethod;
+pub use pixel::{
+    Luma, Luma16, Luma32F, Luma8, LumaA, LumaA16, LumaA32F, LumaA8, Pixel, Primitive, Rgb, Rgb16, Rgb32F, Rgb8, Rgba,
+    Rgba16, Rgba32F, Rgba8,
+};
+pub use view::{ImageView, ImageViewMut, SampleLayout};
 
-#[derive(Debug)]
-pub struct PixelRGBA {
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
-    pub a: u8,
-}
+use std::{fs, marker::PhantomData, mem, ops::{Add, Range}};
 
-impl PixelRGBA {
-    pub fn build(data: Vec<u8>) -> PixelRGBA {
-        PixelRGBA {
-            r: data[0],
-            g: data[1],
-            b: data[2],
-            a: data[3],
-        }
-    }
+#[derive(Debug, Clone)]
+pub struct Image<P: Pixel> {
+    pub width: u32,
+    pub height: u32,
+    data: Vec<P::Subpixel>,
+    pixel: PhantomData<P>,
 }
 
-#[derive(Debug)]
-pub struct PixelRGB {
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
-}
+impl<P: Pixel> Image<P> {
+    // Raw interleaved little-endian samples, the channel count and sample type
+    // come from the pixel type
+    pub fn load_binary_image(path: &str, width: u32, height: u32) -> Result<Image<P>, ImageError> {
+        let contents = fs::read(path)?;
 
-impl PixelRGB {
-    pub fn build(data: Vec<u8>) -> PixelRGB {
-        PixelRGB {
-            r: data[0],
-            g: data[1],
-            b: data[2],
+        // Self-describing files carry their own geometry, so make sure it agrees,
+        // their channel layout is converted like any other decoded image
+        if codecs::is_imagely(&contents) {
+            let image = codecs::decode_imagely(&contents)?;
+            if (image.width(), image.height()) != (width, height) {
+                return Err(ImageError::Decoding(
+                    "imagely header does not match the requested geometry".to_string(),
+                ));
+            }
+            return Ok(image.into_image());
         }
-    }
-}
-
-#[derive(Debug)]
-pub enum Pixel {
-    RGBA(PixelRGBA),
-    RGB(PixelRGB),
-}
 
-#[derive(Debug)]
-pub struct Image {
-    pub channels: u8,
-    pub width: u32,
-    pub height: u32,
-    data: Vec<u8>,
-}
+        let sample_size = mem::size_of::<P::Subpixel>();
+        let expected = Image::<P>::data_len(width, height)? * sample_size;
+        if contents.len() != expected {
+            return Err(ImageError::DimensionMismatch { expected, actual: contents.len() });
+        }
 
-impl Image {
-    pub fn load_binary_image(
-        path: &str,
-        channels: u8,
-        width: u32,
-        height: u32,
-    ) -> Result<Image, std::io::Error> {
-        let contents = fs::read(path)?;
         Ok(Image {
-            channels,
             width,
             height,
-            data: contents,
+            data: contents.chunks_exact(sample_size).map(P::Subpixel::from_le_slice).collect(),
+            pixel: PhantomData,
         })
     }
 
-    pub fn write_binary_image(self, path: &str) -> Result<(), std::io::Error> {
-        fs::write(path, self.data)
+    pub fn write_binary_image(self, path: &str) -> Result<(), ImageError> {
+        let mut contents = Vec::with_capacity(self.data.len() * mem::size_of::<P::Subpixel>());
+        for sample in self.data {
+            sample.extend_le(&mut contents);
+        }
+        Ok(fs::write(path, contents)?)
+    }
+
+    fn data_len(width: u32, height: u32) -> Result<usize, ImageError> {
+        (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(P::CHANNEL_COUNT as usize))
+            .ok_or_else(|| ImageError::InvalidParameter(format!("a {width}x{height} image is too large")))
+    }
+
+    pub fn channels(&self) -> u8 {
+        P::CHANNEL_COUNT
     }
 
     pub fn pos_to_index(&self, x: usize, y: usize) -> usize {
-        (y * self.width as usize + x) * self.channels as usize
+        (y * self.width as usize + x) * P::CHANNEL_COUNT as usize
     }
 
     pub fn index_to_pos(&self, index: usize) -> (usize, usize) {
-        let pixel_index = index / self.channels as usize;
+        let pixel_index = index / P::CHANNEL_COUNT as usize;
         self.pixel_index_to_pos(pixel_index)
     }
 
     pub fn pixel_index_to_index(&self, pixel_index: usize) -> usize {
-        pixel_index * self.channels as usize
+        pixel_index * P::CHANNEL_COUNT as usize
     }
 
     pub fn pixel_index_to_pos(&self, pixel_index: usize) -> (usize, usize) {
@@ -115,58 +124,140 @@ impl Image {
         (pos1.0 as isize - pos2.0 as isize, pos1.1 as isize - pos2.1 as isize)
     }
 
-    pub fn new(width: u32, height: u32) -> Image {
-        let channels: u8 = 4;
-        let data_len = (width * height * channels as u32) as usize;
+    pub fn new(width: u32, height: u32) -> Image<P> {
+        Image::try_new(width, height).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn try_new(width: u32, height: u32) -> Result<Image<P>, ImageError> {
+        let data_len = Image::<P>::data_len(width, height)?;
 
-        let mut data = Vec::with_capacity(data_len);
-        data.resize(data_len, 0);
+        let data = vec![P::Subpixel::default(); data_len];
 
-        Image {
-            channels,
+        Ok(Image {
             width,
             height,
-            data
+            data,
+            pixel: PhantomData,
+        })
+    }
+
+    // Wraps interleaved samples, `data` must hold exactly width * height * channels of them
+    pub fn from_raw(width: u32, height: u32, data: Vec<P::Subpixel>) -> Result<Image<P>, ImageError> {
+        let data_len = Image::<P>::data_len(width, height)?;
+        if data.len() != data_len {
+            let sample_size = mem::size_of::<P::Subpixel>();
+            return Err(ImageError::DimensionMismatch { expected: data_len * sample_size, actual: data.len() * sample_size });
         }
 
+        Ok(Image {
+            width,
+            height,
+            data,
+            pixel: PhantomData,
+        })
     }
-}
-
-pub struct PixelIterator<'a> {
-    image: &'a Image,
-    cur_pixel_index: usize,
-}
 
-impl<'a> PixelIterator<'a> {
-    pub fn new(image: &'a Image) -> Self {
-        PixelIterator {
-            image,
-            cur_pixel_index: 0,
-        }
+    pub fn into_raw(self) -> Vec<P::Subpixel> {
+        self.data
     }
-}
 
-impl<'a> Iterator for PixelIterator<'a> {
-    type Item = Pixel;
+    // The interleaved samples row by row, which are bytes for 8-bit images
+    pub fn as_bytes(&self) -> &[P::Subpixel] {
+        &self.data
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let channels = self.image.channels;
+    pub fn as_bytes_mut(&mut self) -> &mut [P::Subpixel] {
+        &mut self.data
+    }
 
-        let greatest_pixel_data_index =
-            self.cur_pixel_index * channels as usize + channels as usize;
-        if greatest_pixel_data_index >= self.image.data.len() {
+    fn pixel_range(&self, x: u32, y: u32) -> Option<Range<usize>> {
+        if x >= self.width || y >= self.height {
             return None;
         }
+        let index = self.pos_to_index(x as usize, y as usize);
+        Some(index..index + P::CHANNEL_COUNT as usize)
+    }
+
+    fn out_of_bounds(&self, x: u32, y: u32) -> ! {
+        panic!("pixel ({x}, {y}) is outside the {}x{} image", self.width, self.height)
+    }
 
-        let pixel_data =
-            &self.image.data[(self.cur_pixel_index * channels as usize)..greatest_pixel_data_index];
-        self.cur_pixel_index += 1;
+    pub fn get_pixel(&self, x: u32, y: u32) -> &P {
+        self.get_pixel_checked(x, y).unwrap_or_else(|| self.out_of_bounds(x, y))
+    }
 
-        if channels == 4 {
-            return Some(Pixel::RGBA(PixelRGBA::build(pixel_data.to_vec())));
-        } else if channels == 3 {
-            return Some(Pixel::RGB(PixelRGB::build(pixel_data.to_vec())));
+    pub fn get_pixel_checked(&self, x: u32, y: u32) -> Option<&P> {
+        self.pixel_range(x, y).map(|range| P::from_slice_ref(&self.data[range]))
+    }
+
+    pub fn get_pixel_mut(&mut self, x: u32, y: u32) -> &mut P {
+        match self.pixel_range(x, y) {
+            Some(range) => P::from_slice_mut(&mut self.data[range]),
+            None => self.out_of_bounds(x, y),
         }
-        panic!("[FATAL] Invalid number of channels: {}", channels);
+    }
+
+    pub fn put_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        *self.get_pixel_mut(x, y) = pixel;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_buffers_must_match_the_geometry() {
+        assert!(matches!(
+            Image::<Rgb16>::from_raw(2, 2, vec![0; 11]),
+            Err(ImageError::DimensionMismatch { expected: 24, actual: 22 })
+        ));
+        assert!(matches!(Image::<Rgba8>::try_new(u32::MAX, u32::MAX), Err(ImageError::InvalidParameter(_))));
+
+        let path = std::env::temp_dir().join(format!("imagely-binary-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, [0u8; 7]).unwrap();
+        let short = Image::<Luma16>::load_binary_image(path, 2, 2);
+        fs::remove_file(path).unwrap();
+        assert!(matches!(short, Err(ImageError::DimensionMismatch { expected: 8, actual: 7 })));
+    }
+
+    #[test]
+    fn binary_images_are_little_endian() {
+        let path = std::env::temp_dir().join(format!("imagely-binary-le-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        Image::<Luma16>::from_raw(2, 1, vec![0x0102, 0xfffe]).unwrap().write_binary_image(path).unwrap();
+        let bytes = fs::read(path).unwrap();
+        let image = Image::<Luma16>::load_binary_image(path, 2, 1);
+        fs::remove_file(path).unwrap();
+        assert_eq!(bytes, [0x02, 0x01, 0xfe, 0xff]);
+        assert_eq!(image.unwrap().into_raw(), [0x0102, 0xfffe]);
+    }
+
+    #[test]
+    fn pixels_are_read_and_written_by_position() {
+        let mut image: Image<LumaA8> = Image::new(3, 2);
+        image.put_pixel(2, 1, LumaA([40, 200]));
+        *image.get_pixel_mut(0, 1) = LumaA([7, 8]);
+        assert_eq!(image.get_pixel(2, 1), &LumaA([40, 200]));
+        assert_eq!(image.get_pixel_checked(0, 1), Some(&LumaA([7, 8])));
+        assert_eq!(image.get_pixel_checked(3, 0), None);
+        assert_eq!(image.get_pixel_checked(0, 2), None);
+
+        // Rows are interleaved and stored top to bottom
+        assert_eq!(image.pos_to_index(2, 1), 10);
+        assert_eq!(image.index_to_pos(10), (2, 1));
+        assert_eq!(image.as_bytes()[6..], [7, 8, 0, 0, 40, 200]);
+
+        image.as_bytes_mut()[1] = 9;
+        assert_eq!(image.get_pixel(0, 0), &LumaA([0, 9]));
+        assert_eq!(image.into_raw(), [0, 9, 0, 0, 0, 0, 7, 8, 0, 0, 40, 200]);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (1, 3) is outside the 2x3 image")]
+    fn out_of_bounds_pixels_panic() {
+        let mut image: Image<Rgb8> = Image::new(2, 3);
+        image.put_pixel(1, 3, Rgb([0; 3]));
     }
 }
@@ -1,4 +1,4 @@
-use imagely::Image;
+use imagely::{Image, Rgba, Rgba8};
 use std::time::SystemTime;
 
 #[allow(dead_code)]
@@ -20,25 +20,25 @@ fn time_benchmark(benchmark: fn() -> (), iterations: usize, label: &str) -> u128
 #[allow(unused_mut)]
 fn main() {
     // Load binary image
-    let mut image = Image::load_binary_image("./src/imgs/image.bin", 4, 1080, 1920).unwrap();
+    let mut image = Image::<Rgba8>::load_binary_image("./src/imgs/image.bin", 1080, 1920).unwrap();
 
     // Or create new image
-    // let mut image = Image::new(1000, 1000);
+    // let mut image = Image::<Rgba8>::new(1000, 1000);
 
     // Load a secondary image
-    let mut image2 = Image::load_binary_image("./src/imgs/image2.bin", 4, 640, 468).unwrap();
+    let mut image2 = Image::<Rgba8>::load_binary_image("./src/imgs/image2.bin", 640, 468).unwrap();
     image2.rotate_180();
 
     // Paste image2 onto image at position (20, 50);
-    image.paste(image2, (20, 50));
+    image.paste(&image2, (20, 50));
     image.crop((0, 0, 1080, 1080));
 
     // Other operations
     image.to_grayscale();
-    image.gaussian_blur(3, 5.0);
+    image.gaussian_blur_with_size(3, 5.0);
 
     // Draw circle
-    let color = (255, 0, 0, 255);  // Red
+    let color = Rgba([255, 0, 0, 255]);  // Red
     image.draw_circle_outline((500, 500), 499, color);
 
     // Save result
@@ -1,221 +1,446 @@
-use core::panic;
+use std::marker::PhantomData;
+
+use super::{Image, ImageError, ImageViewMut, Luma, LumaA, Pixel, Primitive, Rgb8, Rgba, Rgba8};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrayscaleMethod {
+    // Unweighted (R + G + B) / 3, what `to_grayscale` uses
+    Average,
+    // Luma weights of SD video, applied to the gamma encoded values
+    Rec601,
+    // Luma weights of HD video and sRGB, applied to the gamma encoded values
+    Rec709,
+    // Rec.709 weights applied in linear light, then encoded as sRGB again
+    Luminance,
+    // CIE L*, perceptually uniform steps from black to white
+    Lightness,
+    // Midpoint of the brightest and darkest channel
+    Desaturation,
+    Red,
+    Green,
+    Blue,
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
 
-use super::Image;
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
 
+fn gray_value<T: Primitive>(method: GrayscaleMethod, rgb: &[T]) -> T {
+    let [r, g, b] = [rgb[0], rgb[1], rgb[2]].map(Primitive::to_normalized);
+    let gray = match method {
+        // Works on the raw values so it truncates like the integer version did
+        GrayscaleMethod::Average => return T::from_f64((rgb[0].to_f64() + rgb[1].to_f64() + rgb[2].to_f64()) / 3.0),
+        GrayscaleMethod::Rec601 => 0.299 * r + 0.587 * g + 0.114 * b,
+        GrayscaleMethod::Rec709 => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+        GrayscaleMethod::Luminance | GrayscaleMethod::Lightness => {
+            let [r, g, b] = [r, g, b].map(srgb_to_linear);
+            let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            if method == GrayscaleMethod::Luminance {
+                linear_to_srgb(y)
+            } else if y > 216.0 / 24389.0 {
+                (116.0 * y.cbrt() - 16.0) / 100.0
+            } else {
+                y * 24389.0 / 2700.0
+            }
+        }
+        GrayscaleMethod::Desaturation => (r.max(g).max(b) + r.min(g).min(b)) / 2.0,
+        GrayscaleMethod::Red => r,
+        GrayscaleMethod::Green => g,
+        GrayscaleMethod::Blue => b,
+    };
+    T::from_normalized(gray)
+}
 
-fn composite_rgba(top: Vec<u8>, bottom: Vec<u8>) -> Vec<u8> {
-    assert_eq!(top.len(), 4);
-    assert_eq!(bottom.len(), 4);
+fn composite<P: Pixel>(top: P, bottom: P) -> P {
+    let alpha = P::COLOR_CHANNELS;
+    let (top, bottom) = (top.channels(), bottom.channels());
 
     // Calculate new alpha
     // Reduce to range 0-1
-    let a_top = top[3] as f32 / 255.0;
-    let a_bottom = bottom[3] as f32 / 255.0;
+    let a_top = top[alpha].to_normalized();
+    let a_bottom = bottom[alpha].to_normalized();
 
     let a_out = a_top + a_bottom * (1.0 - a_top);
 
-    // Calculate new RGB values;
-    let mut result = Vec::with_capacity(4);
+    // Calculate new color values;
+    let mut result = Vec::with_capacity(alpha + 1);
 
-    for c in 0..3 {
-        let c_top = top[c] as f32;
-        let c_bottom = bottom[c] as f32;
+    for c in 0..alpha {
+        let c_top = top[c].to_f64();
+        let c_bottom = bottom[c].to_f64();
 
         let c_out = (c_top * a_top + c_bottom * a_bottom * (1.0 - a_top)) / a_out;
-        result.push(c_out as u8);
+        result.push(P::Subpixel::from_f64(c_out));
     }
-    result.push((a_out as u8 * 255).into());
+    result.push(P::Subpixel::from_normalized(a_out));
 
-    result
+    P::from_slice(&result)
 }
 
-impl Image {
-    pub fn to_rgb(&mut self) {
-        // Remove each alpha value
-        let mut output_data: Vec<u8> = vec![];
-        for (i, val) in self.data.iter().enumerate() {
-            if (i + 1) % 4 != 0 {
-                output_data.push(*val);
-            }
+impl<P: Pixel> Image<P> {
+    // Converts every pixel through RGBA, so gray to color and back is lossless,
+    // and rescales samples when the depth changes. Every pixel type converts to
+    // every other, so there is nothing for a `try_convert` to report.
+    pub fn convert<Q: Pixel>(&self) -> Image<Q> {
+        let mut output_data: Vec<Q::Subpixel> = Vec::with_capacity(self.width as usize * self.height as usize * Q::CHANNEL_COUNT as usize);
+        for pixel in self.pixels() {
+            let rgba = Rgba(pixel.to_rgba().0.map(Primitive::convert));
+            output_data.extend_from_slice(Q::from_rgba(rgba).channels());
         }
-        self.data = output_data;
-        self.channels = 3;
-    }
 
-    pub fn to_rgba(&mut self) {
-        // Add in alpha after every rgb value.
-        let mut output_data: Vec<u8> = vec![];
-        for (i, val) in self.data.iter().enumerate() {
-            output_data.push(*val);
-            if (i + 1) % 3 == 0 {
-                output_data.push(255);
-            }
+        Image {
+            width: self.width,
+            height: self.height,
+            data: output_data,
+            pixel: PhantomData,
         }
-        self.data = output_data;
-        self.channels = 4;
-
     }
 
-    pub fn make_compatible(&self, image2: &mut Image) {
-        if self.channels == 4 && image2.channels != 4 {
-            image2.to_rgba();
-        }
-        else if self.channels == 3 && image2.channels == 4 {
-            image2.to_rgb();
-        }
-        else if self.channels != 4 && self.channels != 4 {
-            panic!("[FATAL] Invalid number of channels: {}", image2.channels);
-        }
+    pub fn to_rgb(&self) -> Image<Rgb8> {
+        self.convert()
     }
-    
-    pub fn paste(&mut self, mut image2: Image, position: (u32, u32)) {
-        let starting_pos = position.1 * self.width + position.0;
-
-        self.make_compatible(&mut image2);
-
-        for i in 0..image2.height {
-            let row_pos = starting_pos + self.width * i as u32;
 
-            // Ensure position is within base image
-            if i + position.1 >= self.height {
-                break;
-            }
-
-
-            let splice_start = row_pos;
-            let mut splice_end = row_pos + image2.width;
+    pub fn to_rgba(&self) -> Image<Rgba8> {
+        self.convert()
+    }
 
-            // Prevent horizontal wraparound
-            let max_x_pos = (i + position.1 + 1) * self.width;
+    pub fn paste(&mut self, image2: &Image<P>, position: (u32, u32)) {
+        self.try_paste(image2, position).unwrap_or_else(|error| panic!("{error}"));
+    }
 
-            if splice_end > max_x_pos {
-                splice_end = max_x_pos;
+    // Draws `image2` with its top left corner at `position`, blending by its alpha.
+    // All of `image2` has to fit inside this image.
+    pub fn try_paste(&mut self, image2: &Image<P>, position: (u32, u32)) -> Result<(), ImageError> {
+        let channels = P::CHANNEL_COUNT as usize;
+
+        let right = position.0.checked_add(image2.width);
+        let bottom = position.1.checked_add(image2.height);
+        if right.is_none_or(|right| right > self.width) || bottom.is_none_or(|bottom| bottom > self.height) {
+            return Err(ImageError::OutOfBounds {
+                rect: (position.0, position.1, image2.width, image2.height),
+                width: self.width,
+                height: self.height,
+            });
+        }
+        let (paste_width, paste_height) = (image2.width as usize, image2.height as usize);
+
+        for y in 0..paste_height {
+            let row_start = self.pos_to_index(position.0 as usize, position.1 as usize + y);
+            let row = &mut self.data[row_start..row_start + paste_width * channels];
+
+            let image2_row_start = image2.pos_to_index(0, y);
+            let image2_row = &image2.data[image2_row_start..image2_row_start + paste_width * channels];
+
+            for (old_value, new_value) in row.chunks_exact_mut(channels).zip(image2_row.chunks_exact(channels)) {
+                if P::HAS_ALPHA && new_value[channels - 1] < P::Subpixel::MAX {
+                    // Calculate correct alpha composite.
+                    let composite = composite(P::from_slice(new_value), P::from_slice(old_value));
+                    old_value.copy_from_slice(composite.channels());
+                } else {
+                    old_value.copy_from_slice(new_value);
+                }
             }
+        }
+        Ok(())
+    }
 
-            // Prevent vertical overflow
-            let data_len = self.data.len();
-            if splice_end > data_len as u32 {
-                splice_end = data_len as u32;
-            }
+    // Grayscale as a single channel, keeping the sample type
+    pub fn to_luma(&self) -> Image<Luma<P::Subpixel>> {
+        self.to_luma_with_method(GrayscaleMethod::Average)
+    }
 
-            for p in 0..(splice_end - splice_start) {
-                let image1_pixel_pos = (row_pos + p) as usize * self.channels as usize;
-                let image2_pixel_pos = (i * image2.width + p) as usize * self.channels as usize;
+    pub fn to_luma_with_method(&self, method: GrayscaleMethod) -> Image<Luma<P::Subpixel>> {
+        let mut output_data = Vec::with_capacity(self.width as usize * self.height as usize);
+        for pixel in self.data.chunks_exact(P::CHANNEL_COUNT as usize) {
+            output_data.push(if P::COLOR_CHANNELS < 3 { pixel[0] } else { gray_value(method, pixel) });
+        }
 
-                let mut new_value = image2.data[image2_pixel_pos..(image2_pixel_pos + self.channels as usize)].to_vec();
+        Image {
+            width: self.width,
+            height: self.height,
+            data: output_data,
+            pixel: PhantomData,
+        }
+    }
 
-                if self.channels == 4 && image2.data[image2_pixel_pos + 3] < 255 {
-                    // Calculate correct RGBA composite.
-                    let old_value = self.data[image1_pixel_pos..(image1_pixel_pos + self.channels as usize)].to_vec();
+    // Grayscale plus alpha in two channels, keeping the sample type
+    pub fn to_luma_alpha(&self) -> Image<LumaA<P::Subpixel>> {
+        self.to_luma_alpha_with_method(GrayscaleMethod::Average)
+    }
 
-                    new_value = composite_rgba(new_value, old_value);
-                }
+    pub fn to_luma_alpha_with_method(&self, method: GrayscaleMethod) -> Image<LumaA<P::Subpixel>> {
+        let mut output_data = Vec::with_capacity(self.width as usize * self.height as usize * 2);
+        for pixel in self.data.chunks_exact(P::CHANNEL_COUNT as usize) {
+            output_data.push(if P::COLOR_CHANNELS < 3 { pixel[0] } else { gray_value(method, pixel) });
+            output_data.push(if P::HAS_ALPHA { pixel[P::COLOR_CHANNELS] } else { P::Subpixel::MAX });
+        }
 
-                self.data[image1_pixel_pos] = new_value[0];
-                self.data[image1_pixel_pos + 1] = new_value[1];
-                self.data[image1_pixel_pos + 2] = new_value[2];
-                if self.channels == 4 {
-                    self.data[image1_pixel_pos + 3] = new_value[3];
-                }
-            }
+        Image {
+            width: self.width,
+            height: self.height,
+            data: output_data,
+            pixel: PhantomData,
         }
     }
 
+    // Keeps the channel layout, use `to_luma` for a single-channel image
     pub fn to_grayscale(&mut self) {
-        for i in 0..self.data.len() / self.channels as usize {
-            let pos = i * self.channels as usize;
-            let avg_rgb = (self.data[pos] as u16 + self.data[pos + 1] as u16 + self.data[pos + 2] as u16) / 3;
-
-            self.data[pos] = avg_rgb as u8;
-            self.data[pos + 1] = avg_rgb as u8;
-            self.data[pos + 2] = avg_rgb as u8;
-        }
+        self.as_view_mut().to_grayscale();
     }
 
+    pub fn to_grayscale_with_method(&mut self, method: GrayscaleMethod) {
+        self.as_view_mut().to_grayscale_with_method(method);
+    }
 
- 
-    fn rotate_90_helper(&mut self, calc: fn(usize, &mut Image) -> usize) {
-        let mut output_data: Vec<u8> = Vec::with_capacity(self.data.len());
-        output_data.resize(self.data.len(), 0);
+    fn rotate_90_helper(&mut self, calc: fn(usize, &mut Image<P>) -> usize) {
+        let channels = P::CHANNEL_COUNT as usize;
+        let mut output_data: Vec<P::Subpixel> = vec![P::Subpixel::default(); self.data.len()];
 
-        for i in 0..self.data.len() / self.channels as usize {
-            let pos = i * self.channels as usize;
+        for i in 0..self.data.len() / channels {
+            let pos = i * channels;
 
             let new_pixel_index = calc(i, self);
 
-            output_data[new_pixel_index] = self.data[pos];
-            output_data[new_pixel_index + 1] = self.data[pos + 1];
-            output_data[new_pixel_index + 2] = self.data[pos + 2];
-
-            if self.channels == 4 {
-                output_data[new_pixel_index + 3] = self.data[pos + 3];
-            }
+            output_data[new_pixel_index..new_pixel_index + channels].copy_from_slice(&self.data[pos..pos + channels]);
         }
         self.data = output_data;
 
         // Swap width and height values
-        let height = self.width;
-        self.width = self.height;
-        self.height = height;
+        std::mem::swap(&mut self.width, &mut self.height);
     }
 
+    // Rotations work on any image, including empty ones, and can't fail
     pub fn rotate_90(&mut self) {
         self.rotate_90_helper(|i, img| {
             let row = (img.height - 1) - i as u32 / (img.width);
             let new_pixel_index = i as u32 % img.width * img.height + row;
-            new_pixel_index as usize * img.channels as usize
+            new_pixel_index as usize * P::CHANNEL_COUNT as usize
         });
-    }  
+    }
 
     pub fn rotate_270(&mut self) {
         self.rotate_90_helper(|i, img| {
             let row = img.height - i as u32 / img.width;
             let new_pixel_index = img.width * img.height - (i as u32 % img.width * img.height + row);
-            new_pixel_index as usize * img.channels as usize
+            new_pixel_index as usize * P::CHANNEL_COUNT as usize
         });
     }
 
     pub fn rotate_180(&mut self) {
-        let mut output_data: Vec<u8> = Vec::with_capacity(self.data.len());
-
-        for i in (0..self.data.len() / self.channels as usize).rev() {
-            let pos = i * self.channels as usize;
+        let mut output_data: Vec<P::Subpixel> = Vec::with_capacity(self.data.len());
 
-            output_data.push(self.data[pos]);
-            output_data.push(self.data[pos + 1]);
-            output_data.push(self.data[pos + 2]);
-
-            if self.channels == 4 {
-                // output_data[(new_pixel_index + 3) as usize] = self.data[pos + 3];
-            output_data.push(self.data[pos + 3]);
-            }
+        for pixel in self.pixels().rev() {
+            output_data.extend_from_slice(pixel.channels());
         }
         self.data = output_data;
     }
 
 
     pub fn crop(&mut self, rect: (u32, u32, u32, u32)) {
-        assert!(rect.2 >= rect.0);
-        assert!(rect.3 >= rect.1);
-        assert!(rect.0 <= self.width && rect.2 <= self.width);
-        assert!(rect.1 <= self.height && rect.3 <= self.height);
+        self.try_crop(rect).unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    // `rect` holds the left, top, right and bottom edges, right and bottom exclusive
+    pub fn try_crop(&mut self, rect: (u32, u32, u32, u32)) -> Result<(), ImageError> {
+        self.check_rect(rect)?;
 
         let crop_width = rect.2 - rect.0;
         let crop_height = rect.3 - rect.1;
+        let channels = P::CHANNEL_COUNT as u32;
 
-        let mut output_data: Vec<u8> = vec![];
+        let mut output_data: Vec<P::Subpixel> = vec![];
         let start_pos = rect.1 * self.width + rect.0; // Translate (x, y) into 1d
 
         for i in 0..crop_height {
             let slice = &self.data[
-                ((start_pos + (self.width * i)) * self.channels as u32) as usize..
-                ((start_pos + (self.width * i) + crop_width) * self.channels as u32) as usize
+                ((start_pos + (self.width * i)) * channels) as usize..
+                ((start_pos + (self.width * i) + crop_width) * channels) as usize
             ];
             output_data.extend_from_slice(slice);
         };
 
-        self.width = crop_width as u32;
-        self.height = crop_height as u32;
+        self.width = crop_width;
+        self.height = crop_height;
         self.data = output_data;
+        Ok(())
+    }
+}
+
+impl<P: Pixel> ImageViewMut<'_, P> {
+    pub fn to_grayscale(&mut self) {
+        self.to_grayscale_with_method(GrayscaleMethod::Average);
+    }
+
+    pub fn to_grayscale_with_method(&mut self, method: GrayscaleMethod) {
+        // Gray pixel types are already grayscale
+        if P::COLOR_CHANNELS < 3 {
+            return;
+        }
+
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let mut pixel = self.get_pixel(x, y);
+                let channels = pixel.channels_mut();
+                let gray = gray_value(method, channels);
+
+                channels[0] = gray;
+                channels[1] = gray;
+                channels[2] = gray;
+                self.put_pixel(x, y, pixel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{GrayscaleMethod, Image, ImageError, Luma8, LumaA8, Rgb8, Rgba16, Rgba8};
+
+    fn numbered(width: u32, height: u32) -> Image<Luma8> {
+        Image::from_raw(width, height, (1..=(width * height) as u8).collect()).unwrap()
+    }
+
+    #[test]
+    fn paste_has_to_fit_entirely() {
+        let mut base: Image<Luma8> = Image::new(3, 3);
+        base.try_paste(&numbered(2, 2), (1, 1)).unwrap();
+        assert_eq!(base.as_bytes(), [0, 0, 0, 0, 1, 2, 0, 3, 4]);
+
+        for position in [(2, 0), (0, 2), (3, 3), (u32::MAX, 0)] {
+            let error = base.try_paste(&numbered(2, 2), position).unwrap_err();
+            assert!(matches!(error, ImageError::OutOfBounds { rect, width: 3, height: 3 } if rect == (position.0, position.1, 2, 2)));
+        }
+        // A failed paste leaves the image alone, an empty one fits anywhere on it
+        assert_eq!(base.as_bytes(), [0, 0, 0, 0, 1, 2, 0, 3, 4]);
+        base.try_paste(&Image::new(0, 0), (3, 3)).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn paste_panics_where_try_paste_fails() {
+        numbered(2, 2).paste(&numbered(3, 1), (0, 0));
+    }
+
+    #[test]
+    fn paste_blends_by_the_pasted_alpha() {
+        let mut base: Image<Rgba8> = Image::from_raw(2, 1, vec![10, 20, 30, 255, 10, 20, 30, 255]).unwrap();
+        let top: Image<Rgba8> = Image::from_raw(2, 1, vec![200, 0, 0, 0, 1, 2, 3, 255]).unwrap();
+        base.paste(&top, (0, 0));
+        assert_eq!(base.as_bytes(), [10, 20, 30, 255, 1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn crop_reports_bad_rectangles() {
+        let mut image = numbered(3, 2);
+        assert!(matches!(image.try_crop((2, 0, 1, 2)), Err(ImageError::InvalidParameter(_))));
+        assert!(matches!(
+            image.try_crop((1, 1, 4, 2)),
+            Err(ImageError::OutOfBounds { rect: (1, 1, 3, 1), width: 3, height: 2 })
+        ));
+        image.try_crop((1, 0, 3, 2)).unwrap();
+        assert_eq!((image.width, image.as_bytes()), (2, &[2, 3, 5, 6][..]));
+    }
+
+    #[test]
+    fn infallible_operations_accept_empty_images() {
+        let mut image: Image<Luma8> = Image::new(0, 3);
+        image.rotate_90();
+        assert_eq!((image.width, image.height), (3, 0));
+        image.rotate_180();
+        image.rotate_270();
+        image.box_blur(u32::MAX);
+        image.median_filter(u32::MAX);
+        assert_eq!(image.convert::<Rgba8>().as_bytes().len(), 0);
+    }
+
+    #[test]
+    fn luma_images_have_one_channel_and_luma_alpha_two() {
+        let image: Image<Rgba8> = Image::from_raw(2, 1, vec![30, 60, 91, 255, 255, 0, 0, 40]).unwrap();
+        let luma = image.to_luma();
+        assert_eq!((luma.channels(), luma.as_bytes()), (1, &[60, 85][..]));
+        let luma_alpha = image.to_luma_alpha();
+        assert_eq!((luma_alpha.channels(), luma_alpha.as_bytes()), (2, &[60, 255, 85, 40][..]));
+        // Without alpha the second channel is opaque
+        assert_eq!(image.convert::<Rgb8>().to_luma_alpha().as_bytes(), [60, 255, 85, 255]);
+
+        // In place, the layout stays and the gray lands in every color channel
+        let mut in_place = image.clone();
+        in_place.to_grayscale();
+        assert_eq!(in_place.as_bytes(), [60, 60, 60, 255, 85, 85, 85, 40]);
+    }
+
+    #[test]
+    fn luma_images_rotate_crop_and_paste() {
+        let mut image = numbered(3, 2);
+        image.rotate_90();
+        assert_eq!((image.width, image.height, image.as_bytes()), (2, 3, &[4, 1, 5, 2, 6, 3][..]));
+        image.rotate_180();
+        assert_eq!(image.as_bytes(), [3, 6, 2, 5, 1, 4]);
+        image.rotate_270();
+        assert_eq!((image.width, image.as_bytes()), (3, &[6, 5, 4, 3, 2, 1][..]));
+
+        image.crop((1, 0, 3, 2));
+        let mut base: Image<Luma8> = Image::new(3, 3);
+        base.paste(&image, (1, 1));
+        assert_eq!(base.as_bytes(), [0, 0, 0, 0, 5, 4, 0, 2, 1]);
+    }
+
+    #[test]
+    fn luma_alpha_blurs_by_coverage() {
+        // The transparent pixel's gray doesn't leak into its opaque neighbour
+        let mut image: Image<LumaA8> = Image::from_raw(2, 1, vec![200, 255, 0, 0]).unwrap();
+        image.box_blur(1);
+        assert_eq!(image.as_bytes(), [200, 128, 200, 128]);
+
+        let mut flat: Image<Luma8> = Image::from_raw(3, 3, vec![77; 9]).unwrap();
+        flat.gaussian_blur(1.0);
+        assert_eq!(flat.as_bytes(), [77; 9]);
+    }
+
+    #[test]
+    fn grayscale_methods_weigh_the_channels() {
+        use GrayscaleMethod::*;
+
+        let image: Image<Rgb8> = Image::from_raw(1, 1, vec![200, 100, 50]).unwrap();
+        let gray = |method| image.to_luma_with_method(method).as_bytes()[0];
+        // Average truncates 116.67 like the original integer code
+        assert_eq!(gray(Average), 116);
+        assert_eq!(gray(Rec601), 124);
+        assert_eq!(gray(Rec709), 118);
+        // Through linear light, then back to sRGB or to L*
+        assert_eq!(gray(Luminance), 128);
+        assert_eq!(gray(Lightness), 137);
+        assert_eq!(gray(Desaturation), 125);
+        assert_eq!([gray(Red), gray(Green), gray(Blue)], [200, 100, 50]);
+    }
+
+    #[test]
+    fn grayscale_methods_agree_on_gray_pixels() {
+        use GrayscaleMethod::*;
+
+        let image: Image<Rgb8> = Image::from_raw(2, 1, vec![10, 10, 10, 180, 180, 180]).unwrap();
+        for method in [Average, Rec601, Rec709, Luminance, Desaturation, Red, Green, Blue] {
+            assert_eq!(image.to_luma_with_method(method).as_bytes(), [10, 180], "{method:?}");
+        }
+        // Lightness is perceptual, dark grays use the linear segment of L*
+        assert_eq!(image.to_luma_with_method(Lightness).as_bytes()[0], 7);
+    }
+
+    #[test]
+    fn grayscale_methods_keep_depth_and_alpha() {
+        let image = Image::<Rgba16>::from_raw(1, 1, vec![0xffff, 0, 0, 0x1234]).unwrap();
+        assert_eq!(image.to_luma_alpha_with_method(GrayscaleMethod::Rec709).as_bytes(), [13933, 0x1234]);
+
+        let mut view_target = image.clone();
+        view_target.view_mut((0, 0, 1, 1)).to_grayscale_with_method(GrayscaleMethod::Red);
+        assert_eq!(view_target.as_bytes(), [0xffff, 0xffff, 0xffff, 0x1234]);
+
+        // Gray images have nothing to weigh
+        let luma: Image<Luma8> = numbered(2, 1);
+        assert_eq!(luma.to_luma_with_method(GrayscaleMethod::Blue).as_bytes(), [1, 2]);
     }
 }
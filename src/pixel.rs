@@ -0,0 +1,269 @@
+use std::fmt::Debug;
+
+// A sample type: 8 or 16-bit integers spanning their whole range, or floats
+// where 0.0 to 1.0 is the displayable range
+pub trait Primitive: Copy + Debug + Default + PartialEq + PartialOrd + Send + Sync + 'static {
+    // Full intensity, used for opaque alpha
+    const MAX: Self;
+
+    fn to_f64(self) -> f64;
+
+    // Truncates like an `as` cast, integers saturate at their range
+    fn from_f64(value: f64) -> Self;
+
+    fn to_normalized(self) -> f64 {
+        self.to_f64() / Self::MAX.to_f64()
+    }
+
+    // Rounds to the nearest value, for converting between depths
+    fn from_normalized(value: f64) -> Self;
+
+    fn convert<T: Primitive>(self) -> T {
+        T::from_normalized(self.to_normalized())
+    }
+
+    // Little-endian encoding used by the raw binary format
+    fn from_le_slice(bytes: &[u8]) -> Self;
+
+    fn extend_le(self, out: &mut Vec<u8>);
+}
+
+impl Primitive for u8 {
+    const MAX: Self = u8::MAX;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as u8
+    }
+
+    fn from_normalized(value: f64) -> Self {
+        (value * 255.0).round() as u8
+    }
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        u8::from_le_bytes([bytes[0]])
+    }
+
+    fn extend_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Primitive for u16 {
+    const MAX: Self = u16::MAX;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as u16
+    }
+
+    fn from_normalized(value: f64) -> Self {
+        (value * 65535.0).round() as u16
+    }
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+
+    fn extend_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+// Floats aren't clamped, so values outside 0.0 to 1.0 survive processing
+impl Primitive for f32 {
+    const MAX: Self = 1.0;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn from_normalized(value: f64) -> Self {
+        value as f32
+    }
+
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn extend_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+// A pixel type stored as `CHANNEL_COUNT` interleaved samples, alpha last
+pub trait Pixel: Copy + Debug + PartialEq + Send + Sync + 'static {
+    type Subpixel: Primitive;
+
+    const CHANNEL_COUNT: u8;
+    const HAS_ALPHA: bool;
+    // Channels holding color, which is every channel but alpha
+    const COLOR_CHANNELS: usize = Self::CHANNEL_COUNT as usize - Self::HAS_ALPHA as usize;
+
+    fn channels(&self) -> &[Self::Subpixel];
+
+    fn channels_mut(&mut self) -> &mut [Self::Subpixel];
+
+    // Reads a pixel from the first `CHANNEL_COUNT` samples of `slice`
+    fn from_slice(slice: &[Self::Subpixel]) -> Self;
+
+    // Views the first `CHANNEL_COUNT` samples of `slice` as a pixel, without copying
+    fn from_slice_ref(slice: &[Self::Subpixel]) -> &Self;
+
+    fn from_slice_mut(slice: &mut [Self::Subpixel]) -> &mut Self;
+
+    fn to_rgba(&self) -> Rgba<Self::Subpixel>;
+
+    fn from_rgba(rgba: Rgba<Self::Subpixel>) -> Self;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Luma<T>(pub [T; 1]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct LumaA<T>(pub [T; 2]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Rgb<T>(pub [T; 3]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Rgba<T>(pub [T; 4]);
+
+pub type Luma8 = Luma<u8>;
+pub type LumaA8 = LumaA<u8>;
+pub type Rgb8 = Rgb<u8>;
+pub type Rgba8 = Rgba<u8>;
+
+pub type Luma16 = Luma<u16>;
+pub type LumaA16 = LumaA<u16>;
+pub type Rgb16 = Rgb<u16>;
+pub type Rgba16 = Rgba<u16>;
+
+pub type Luma32F = Luma<f32>;
+pub type LumaA32F = LumaA<f32>;
+pub type Rgb32F = Rgb<f32>;
+pub type Rgba32F = Rgba<f32>;
+
+// Unweighted average of the color channels, the same as `to_grayscale`
+fn luma<T: Primitive>(r: T, g: T, b: T) -> T {
+    T::from_f64((r.to_f64() + g.to_f64() + b.to_f64()) / 3.0)
+}
+
+macro_rules! impl_channels {
+    ($count:literal) => {
+        fn channels(&self) -> &[T] {
+            &self.0
+        }
+
+        fn channels_mut(&mut self) -> &mut [T] {
+            &mut self.0
+        }
+
+        fn from_slice(slice: &[T]) -> Self {
+            let mut channels = [T::default(); $count];
+            channels.copy_from_slice(&slice[..$count]);
+            Self(channels)
+        }
+
+        fn from_slice_ref(slice: &[T]) -> &Self {
+            let channels: &[T; $count] = slice[..$count].try_into().unwrap();
+            // SAFETY: the pixel types are `repr(transparent)` over their channel array
+            unsafe { &*(channels as *const [T; $count] as *const Self) }
+        }
+
+        fn from_slice_mut(slice: &mut [T]) -> &mut Self {
+            let channels: &mut [T; $count] = (&mut slice[..$count]).try_into().unwrap();
+            // SAFETY: as above
+            unsafe { &mut *(channels as *mut [T; $count] as *mut Self) }
+        }
+    };
+}
+
+impl<T: Primitive> Pixel for Luma<T> {
+    type Subpixel = T;
+
+    const CHANNEL_COUNT: u8 = 1;
+    const HAS_ALPHA: bool = false;
+
+    impl_channels!(1);
+
+    fn to_rgba(&self) -> Rgba<T> {
+        let [l] = self.0;
+        Rgba([l, l, l, T::MAX])
+    }
+
+    fn from_rgba(rgba: Rgba<T>) -> Self {
+        let [r, g, b, _] = rgba.0;
+        Luma([luma(r, g, b)])
+    }
+}
+
+impl<T: Primitive> Pixel for LumaA<T> {
+    type Subpixel = T;
+
+    const CHANNEL_COUNT: u8 = 2;
+    const HAS_ALPHA: bool = true;
+
+    impl_channels!(2);
+
+    fn to_rgba(&self) -> Rgba<T> {
+        let [l, a] = self.0;
+        Rgba([l, l, l, a])
+    }
+
+    fn from_rgba(rgba: Rgba<T>) -> Self {
+        let [r, g, b, a] = rgba.0;
+        LumaA([luma(r, g, b), a])
+    }
+}
+
+impl<T: Primitive> Pixel for Rgb<T> {
+    type Subpixel = T;
+
+    const CHANNEL_COUNT: u8 = 3;
+    const HAS_ALPHA: bool = false;
+
+    impl_channels!(3);
+
+    fn to_rgba(&self) -> Rgba<T> {
+        let [r, g, b] = self.0;
+        Rgba([r, g, b, T::MAX])
+    }
+
+    // Alpha is dropped, not composited onto a background
+    fn from_rgba(rgba: Rgba<T>) -> Self {
+        let [r, g, b, _] = rgba.0;
+        Rgb([r, g, b])
+    }
+}
+
+impl<T: Primitive> Pixel for Rgba<T> {
+    type Subpixel = T;
+
+    const CHANNEL_COUNT: u8 = 4;
+    const HAS_ALPHA: bool = true;
+
+    impl_channels!(4);
+
+    fn to_rgba(&self) -> Rgba<T> {
+        *self
+    }
+
+    fn from_rgba(rgba: Rgba<T>) -> Self {
+        rgba
+    }
+}
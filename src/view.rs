@@ -0,0 +1,372 @@
+use std::marker::PhantomData;
+
+use super::{Image, ImageError, Pixel};
+
+// Where each sample lives, sample `c` of pixel (x, y) is at
+// y * row_stride + x * pixel_stride + c * channel_stride
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleLayout {
+    pub channel_stride: usize,
+    pub pixel_stride: usize,
+    pub row_stride: usize,
+}
+
+impl SampleLayout {
+    // Tightly packed pixels, as stored by `Image`
+    pub fn interleaved(width: u32, channels: u8) -> SampleLayout {
+        SampleLayout::strided(channels, width as usize * channels as usize)
+    }
+
+    // Interleaved pixels whose rows are padded to `row_stride` samples
+    pub fn strided(channels: u8, row_stride: usize) -> SampleLayout {
+        SampleLayout { channel_stride: 1, pixel_stride: channels as usize, row_stride }
+    }
+
+    // One plane per channel, each `plane_stride` samples after the previous one.
+    // Rows within a plane are `row_stride` samples apart.
+    pub fn planar(row_stride: usize, plane_stride: usize) -> SampleLayout {
+        SampleLayout { channel_stride: plane_stride, pixel_stride: 1, row_stride }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        y * self.row_stride + x * self.pixel_stride
+    }
+
+    // Samples needed to hold every pixel of a width x height image
+    fn min_len(&self, width: u32, height: u32, channels: u8) -> Option<usize> {
+        if width == 0 || height == 0 {
+            return Some(0);
+        }
+        let last_row = (height as usize - 1).checked_mul(self.row_stride)?;
+        let last_pixel = (width as usize - 1).checked_mul(self.pixel_stride)?;
+        let last_channel = (channels as usize - 1).checked_mul(self.channel_stride)?;
+        last_row.checked_add(last_pixel)?.checked_add(last_channel)?.checked_add(1)
+    }
+}
+
+// A borrowed image, either a rectangle of an `Image` or a buffer owned by
+// someone else, with any `SampleLayout`. Samples that no pixel maps to, such
+// as row padding or pixels outside a rectangle, are never touched.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a, P: Pixel> {
+    samples: &'a [P::Subpixel],
+    width: u32,
+    height: u32,
+    layout: SampleLayout,
+}
+
+#[derive(Debug)]
+pub struct ImageViewMut<'a, P: Pixel> {
+    samples: &'a mut [P::Subpixel],
+    width: u32,
+    height: u32,
+    layout: SampleLayout,
+}
+
+fn check_layout<P: Pixel>(len: usize, width: u32, height: u32, layout: SampleLayout) -> Result<(), ImageError> {
+    let expected = layout
+        .min_len(width, height, P::CHANNEL_COUNT)
+        .ok_or_else(|| ImageError::InvalidParameter(format!("a {width}x{height} image is too large")))?;
+    if len < expected {
+        let sample_size = size_of::<P::Subpixel>();
+        return Err(ImageError::DimensionMismatch { expected: expected * sample_size, actual: len * sample_size });
+    }
+    if expected == 0 {
+        return Ok(());
+    }
+
+    // Going from the smallest stride up, each step has to jump past every
+    // sample reached by the smaller ones, otherwise two samples would overlap
+    let mut steps = [
+        (layout.channel_stride, P::CHANNEL_COUNT as usize),
+        (layout.pixel_stride, width as usize),
+        (layout.row_stride, height as usize),
+    ];
+    steps.sort_unstable();
+    let mut reach = 0;
+    for (stride, count) in steps.into_iter().filter(|&(_, count)| count > 1) {
+        if stride <= reach {
+            return Err(ImageError::InvalidParameter(format!("{layout:?} makes pixels of a {width}x{height} image overlap")));
+        }
+        reach += (count - 1) * stride;
+    }
+    Ok(())
+}
+
+impl<P: Pixel> Image<P> {
+    // `rect` holds the left, top, right and bottom edges, right and bottom exclusive
+    pub(crate) fn check_rect(&self, rect: (u32, u32, u32, u32)) -> Result<(), ImageError> {
+        if rect.2 < rect.0 || rect.3 < rect.1 {
+            return Err(ImageError::InvalidParameter(format!("rectangle {rect:?} is inverted")));
+        }
+        if rect.2 > self.width || rect.3 > self.height {
+            return Err(ImageError::OutOfBounds {
+                rect: (rect.0, rect.1, rect.2 - rect.0, rect.3 - rect.1),
+                width: self.width,
+                height: self.height,
+            });
+        }
+        Ok(())
+    }
+
+    // The samples from the first to the last pixel of `rect`
+    fn rect_span(&self, rect: (u32, u32, u32, u32)) -> (usize, usize) {
+        // An empty rect may sit on the right or bottom edge, past the last sample
+        if rect.2 == rect.0 || rect.3 == rect.1 {
+            return (0, 0);
+        }
+        let layout = SampleLayout::interleaved(self.width, P::CHANNEL_COUNT);
+        let start = layout.index(rect.0 as usize, rect.1 as usize);
+        let len = layout.min_len(rect.2 - rect.0, rect.3 - rect.1, P::CHANNEL_COUNT).unwrap();
+        (start, start + len)
+    }
+
+    pub fn view(&self, rect: (u32, u32, u32, u32)) -> ImageView<'_, P> {
+        self.try_view(rect).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn try_view(&self, rect: (u32, u32, u32, u32)) -> Result<ImageView<'_, P>, ImageError> {
+        self.check_rect(rect)?;
+        let (start, end) = self.rect_span(rect);
+
+        Ok(ImageView {
+            samples: &self.data[start..end],
+            width: rect.2 - rect.0,
+            height: rect.3 - rect.1,
+            layout: SampleLayout::interleaved(self.width, P::CHANNEL_COUNT),
+        })
+    }
+
+    pub fn view_mut(&mut self, rect: (u32, u32, u32, u32)) -> ImageViewMut<'_, P> {
+        self.try_view_mut(rect).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn try_view_mut(&mut self, rect: (u32, u32, u32, u32)) -> Result<ImageViewMut<'_, P>, ImageError> {
+        self.check_rect(rect)?;
+        let (start, end) = self.rect_span(rect);
+
+        Ok(ImageViewMut {
+            samples: &mut self.data[start..end],
+            width: rect.2 - rect.0,
+            height: rect.3 - rect.1,
+            layout: SampleLayout::interleaved(self.width, P::CHANNEL_COUNT),
+        })
+    }
+
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        self.view((0, 0, self.width, self.height))
+    }
+
+    pub fn as_view_mut(&mut self) -> ImageViewMut<'_, P> {
+        self.view_mut((0, 0, self.width, self.height))
+    }
+}
+
+impl<'a, P: Pixel> ImageView<'a, P> {
+    // Wraps a buffer without copying it, such as a camera or video frame
+    pub fn from_raw(samples: &'a [P::Subpixel], width: u32, height: u32, layout: SampleLayout) -> Result<Self, ImageError> {
+        check_layout::<P>(samples.len(), width, height, layout)?;
+        Ok(ImageView { samples, width, height, layout })
+    }
+}
+
+impl<'a, P: Pixel> ImageViewMut<'a, P> {
+    // Wraps a buffer without copying it
+    pub fn from_raw(samples: &'a mut [P::Subpixel], width: u32, height: u32, layout: SampleLayout) -> Result<Self, ImageError> {
+        check_layout::<P>(samples.len(), width, height, layout)?;
+        Ok(ImageViewMut { samples, width, height, layout })
+    }
+}
+
+// Accessors shared by both view types
+macro_rules! impl_view_access {
+    ($view:ident) => {
+        impl<P: Pixel> $view<'_, P> {
+            pub fn width(&self) -> u32 {
+                self.width
+            }
+
+            pub fn height(&self) -> u32 {
+                self.height
+            }
+
+            pub fn layout(&self) -> SampleLayout {
+                self.layout
+            }
+
+            pub fn is_pos_in_image(&self, pos: (isize, isize)) -> bool {
+                0 <= pos.0 && pos.0 < self.width as isize && 0 <= pos.1 && pos.1 < self.height as isize
+            }
+
+            // Pixels are gathered from their samples, so they're returned by value
+            pub fn get_pixel(&self, x: u32, y: u32) -> P {
+                self.get_pixel_checked(x, y).unwrap_or_else(|| {
+                    panic!("pixel ({x}, {y}) is outside the {}x{} view", self.width, self.height)
+                })
+            }
+
+            pub fn get_pixel_checked(&self, x: u32, y: u32) -> Option<P> {
+                if x >= self.width || y >= self.height {
+                    return None;
+                }
+                let index = self.layout.index(x as usize, y as usize);
+                if self.layout.channel_stride == 1 {
+                    return Some(P::from_slice(&self.samples[index..]));
+                }
+
+                let mut channels = [P::Subpixel::default(); 4];
+                for (c, channel) in channels.iter_mut().enumerate().take(P::CHANNEL_COUNT as usize) {
+                    *channel = self.samples[index + c * self.layout.channel_stride];
+                }
+                Some(P::from_slice(&channels))
+            }
+
+            // Copies the viewed pixels into a packed image of their own
+            pub fn to_image(&self) -> Image<P> {
+                let mut data = Vec::with_capacity(self.width as usize * self.height as usize * P::CHANNEL_COUNT as usize);
+                for y in 0..self.height {
+                    for x in 0..self.width {
+                        data.extend_from_slice(self.get_pixel(x, y).channels());
+                    }
+                }
+
+                Image {
+                    width: self.width,
+                    height: self.height,
+                    data,
+                    pixel: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+impl_view_access!(ImageView);
+impl_view_access!(ImageViewMut);
+
+impl<P: Pixel> ImageViewMut<'_, P> {
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            samples: self.samples,
+            width: self.width,
+            height: self.height,
+            layout: self.layout,
+        }
+    }
+
+    pub fn put_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        if x >= self.width || y >= self.height {
+            panic!("pixel ({x}, {y}) is outside the {}x{} view", self.width, self.height);
+        }
+        let index = self.layout.index(x as usize, y as usize);
+        for (c, &sample) in pixel.channels().iter().enumerate() {
+            self.samples[index + c * self.layout.channel_stride] = sample;
+        }
+    }
+
+    // Writes densely packed pixels, as produced by the filters, back into the view
+    pub(crate) fn write_pixels(&mut self, data: &[P::Subpixel]) {
+        let mut pixels = data.chunks_exact(P::CHANNEL_COUNT as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.put_pixel(x, y, P::from_slice(pixels.next().unwrap()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Rgb, Rgb8};
+
+    // A 3x2 RGB image with every sample distinct
+    fn interleaved() -> Image<Rgb8> {
+        Image::from_raw(3, 2, (1..=18).collect()).unwrap()
+    }
+
+    // The same pixels as separate R, G and B planes, rows padded to 4 samples
+    // and planes to 10, with 255 in every unused sample
+    fn planar_samples() -> Vec<u8> {
+        let image = interleaved();
+        let mut samples = vec![255; 30];
+        for (x, y, pixel) in image.enumerate_pixels() {
+            for c in 0..3 {
+                samples[c * 10 + y as usize * 4 + x as usize] = pixel.0[c];
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn empty_rect_on_the_edge() {
+        let mut image: Image<Rgb<u8>> = Image::new(4, 4);
+        let view = image.try_view((4, 4, 4, 4)).unwrap();
+        assert_eq!((view.width(), view.height()), (0, 0));
+        assert_eq!(image.try_view((4, 0, 4, 4)).unwrap().to_image().width, 0);
+        assert_eq!(image.try_view_mut((0, 4, 4, 4)).unwrap().height(), 0);
+    }
+
+    #[test]
+    fn planar_and_strided_buffers_read_the_same_pixels() {
+        let image = interleaved();
+        let samples = planar_samples();
+        let planar = ImageView::<Rgb8>::from_raw(&samples, 3, 2, SampleLayout::planar(4, 10)).unwrap();
+        assert_eq!(planar.get_pixel(2, 1), *image.get_pixel(2, 1));
+        assert_eq!(planar.to_image().as_bytes(), image.as_bytes());
+
+        // Interleaved rows padded with two unused samples each
+        let padded: Vec<u8> = image.as_bytes().chunks(9).flat_map(|row| [row, &[255, 255]].concat()).collect();
+        let strided = ImageView::<Rgb8>::from_raw(&padded, 3, 2, SampleLayout::strided(3, 11)).unwrap();
+        assert_eq!(strided.to_image().as_bytes(), image.as_bytes());
+        assert_eq!(SampleLayout::interleaved(3, 3), SampleLayout::strided(3, 9));
+    }
+
+    #[test]
+    fn filters_on_planar_views_match_interleaved_images() {
+        let mut image = interleaved();
+        image.box_blur(1);
+        image.to_grayscale();
+
+        let mut samples = planar_samples();
+        let mut planar = ImageViewMut::<Rgb8>::from_raw(&mut samples, 3, 2, SampleLayout::planar(4, 10)).unwrap();
+        planar.box_blur(1);
+        planar.to_grayscale();
+        assert_eq!(planar.to_image().as_bytes(), image.as_bytes());
+
+        // Padding between rows and after each plane is left alone
+        for unused in [3, 7, 8, 9, 13, 17, 18, 19, 23, 27, 28, 29] {
+            assert_eq!(samples[unused], 255);
+        }
+    }
+
+    #[test]
+    fn layouts_have_to_fit_and_not_overlap() {
+        let samples = planar_samples();
+        let exact = ImageView::<Rgb8>::from_raw(&samples[..27], 3, 2, SampleLayout::planar(4, 10));
+        assert!(exact.is_ok());
+        assert!(matches!(
+            ImageView::<Rgb8>::from_raw(&samples[..26], 3, 2, SampleLayout::planar(4, 10)),
+            Err(ImageError::DimensionMismatch { expected: 27, actual: 26 })
+        ));
+        // Rows shorter than a row of pixels, planes inside a row
+        for layout in [SampleLayout::strided(3, 8), SampleLayout::planar(4, 2)] {
+            let overlapping = ImageView::<Rgb8>::from_raw(&samples, 3, 2, layout);
+            assert!(matches!(overlapping, Err(ImageError::InvalidParameter(_))), "{layout:?}");
+        }
+        // A single row never steps by its row stride
+        assert!(ImageView::<Rgb8>::from_raw(&samples[..9], 3, 1, SampleLayout::strided(3, 0)).is_ok());
+    }
+
+    #[test]
+    fn rect_views_reach_only_their_pixels() {
+        let mut image = interleaved();
+        let mut view = image.view_mut((1, 0, 3, 2));
+        assert_eq!(view.layout(), SampleLayout::interleaved(3, 3));
+        assert_eq!(view.get_pixel(0, 1), Rgb([13, 14, 15]));
+        view.put_pixel(1, 1, Rgb([0, 0, 0]));
+        assert_eq!(view.get_pixel_checked(2, 0), None);
+        assert_eq!(image.as_bytes()[9..], [10, 11, 12, 13, 14, 15, 0, 0, 0]);
+        assert!(matches!(image.try_view((2, 0, 4, 1)), Err(ImageError::OutOfBounds { .. })));
+    }
+}